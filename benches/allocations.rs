@@ -0,0 +1,92 @@
+//! Counts heap allocations per request over one keep-alive connection.
+//!
+//! Run with `cargo bench --bench allocations`. The count covers every
+//! thread in the process, client included, so compare it between builds
+//! rather than reading it as the server's cost alone.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+use hello::{Logger, Record, Response, Router, Server, ServerConfig};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const REQUESTS: u64 = 10_000;
+
+/// Drops access-log lines so they are not counted.
+struct Quiet;
+
+impl Logger for Quiet {
+    fn log(&self, _record: &Record) {}
+}
+
+fn main() {
+    let _ = hello::set_logger(Box::new(Quiet));
+    let mut router = Router::new();
+    router.get("/", |_| Response::text(200, "hello, world"));
+    let config = ServerConfig {
+        pool_size: 1,
+        max_connections: Some(1),
+        ..ServerConfig::default()
+    };
+    let server = Server::bind("127.0.0.1:0", config, router).expect("failed to start server");
+    let addr = server.local_addr();
+    let handle = thread::spawn(move || server.run());
+
+    let mut stream = TcpStream::connect(addr).expect("failed to connect");
+    let mut reader = BufReader::new(stream.try_clone().expect("failed to clone stream"));
+    let mut line = String::new();
+    let mut body = [0; 12];
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..REQUESTS {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: bench\r\n\r\n")
+            .expect("failed to send request");
+        loop {
+            line.clear();
+            reader
+                .read_line(&mut line)
+                .expect("failed to read response");
+            if line == "\r\n" {
+                break;
+            }
+        }
+        reader.read_exact(&mut body).expect("failed to read body");
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    drop(reader);
+    drop(stream);
+    handle.join().expect("server panicked");
+    println!(
+        "{:.1} allocations per request ({allocations} over {REQUESTS} requests)",
+        allocations as f64 / REQUESTS as f64
+    );
+}
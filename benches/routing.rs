@@ -0,0 +1,50 @@
+//! Times route matching over 1000 routes with each `MatchStrategy`.
+//!
+//! Run with `cargo bench --bench routing`. Paths are looked up in a fixed
+//! order, so the numbers compare strategies rather than model real traffic.
+
+use std::time::Instant;
+
+use hello::{MatchStrategy, Method, Request, Response, Router};
+
+const ROUTES: usize = 1000;
+const LOOKUPS: usize = 100_000;
+
+fn build(strategy: MatchStrategy) -> Router {
+    let mut router = Router::new();
+    router.match_strategy(strategy);
+    for i in 0..ROUTES {
+        let pattern = match i % 3 {
+            0 => format!("/static/{i}"),
+            1 => format!("/items/{i}/:id"),
+            _ => format!("/files/{i}/*path"),
+        };
+        router.get(&pattern, |_| Response::new(204));
+    }
+    router
+}
+
+fn main() {
+    let paths: Vec<String> = (0..ROUTES)
+        .map(|i| match i % 3 {
+            0 => format!("/static/{i}"),
+            1 => format!("/items/{i}/42"),
+            _ => format!("/files/{i}/a/b"),
+        })
+        .chain(["/missing".into()])
+        .collect();
+
+    for strategy in [MatchStrategy::Linear, MatchStrategy::Trie] {
+        let router = build(strategy);
+        let started = Instant::now();
+        for i in 0..LOOKUPS {
+            let mut req = Request::new(Method::Get, &paths[i % paths.len()]);
+            router.handle(&mut req);
+        }
+        let elapsed = started.elapsed();
+        println!(
+            "{strategy:?}: {LOOKUPS} lookups over {ROUTES} routes in {elapsed:?} ({:?} each)",
+            elapsed / LOOKUPS as u32
+        );
+    }
+}
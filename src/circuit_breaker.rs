@@ -0,0 +1,279 @@
+use std::{
+    fmt, mem,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    response::Response,
+};
+
+/// Where a `CircuitBreaker` is in its cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through; failures are counted.
+    Closed,
+    /// Calls are refused until the cooldown has passed.
+    Open,
+    /// One trial call is let through to probe the dependency.
+    HalfOpen,
+}
+
+/// Why `CircuitBreaker::call` did not return a value.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CircuitError<E> {
+    /// The circuit is open, so the call was not attempted.
+    Open,
+    /// The call ran and failed.
+    Failed(E),
+}
+
+impl<E: fmt::Display> fmt::Display for CircuitError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CircuitError::Open => f.write_str("Circuit is open"),
+            CircuitError::Failed(e) => write!(f, "Call failed: {e}"),
+        }
+    }
+}
+
+struct BreakerState {
+    state: CircuitState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    trial_in_flight: bool,
+}
+
+/// Stops calling a failing dependency for a while after repeated failures.
+///
+/// After `failure_threshold` consecutive failures the circuit opens and
+/// calls fail fast. Once `cooldown` has passed it goes half-open and lets a
+/// single trial call through: success closes the circuit, failure opens it
+/// again for another cooldown.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<BreakerState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitBreaker {
+    /// Create a closed breaker.
+    ///
+    /// A `failure_threshold` of 0 is treated as 1.
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            inner: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                failures: 0,
+                opened_at: None,
+                trial_in_flight: false,
+            }),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Measures the cooldown against `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> CircuitBreaker {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the current state, moving from open to half-open if the
+    /// cooldown has passed.
+    pub fn state(&self) -> CircuitState {
+        let mut inner = self.inner.lock().expect("Mutex poisoned");
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    /// Runs `f` unless the circuit is open, recording its outcome.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CircuitError::Open` without calling `f` while the circuit is
+    /// open (or a half-open trial is already running), and
+    /// `CircuitError::Failed` with `f`'s error otherwise.
+    pub fn call<T, E, F>(&self, f: F) -> Result<T, CircuitError<E>>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        {
+            let mut inner = self.inner.lock().expect("Mutex poisoned");
+            self.refresh(&mut inner);
+            match inner.state {
+                CircuitState::Closed => {}
+                CircuitState::Open => return Err(CircuitError::Open),
+                CircuitState::HalfOpen if inner.trial_in_flight => {
+                    return Err(CircuitError::Open);
+                }
+                CircuitState::HalfOpen => inner.trial_in_flight = true,
+            }
+        }
+
+        // The lock is not held while the dependency is called.
+        let guard = PanicGuard(self);
+        let result = f();
+        mem::forget(guard);
+        match result {
+            Ok(value) => {
+                self.record_success();
+                Ok(value)
+            }
+            Err(e) => {
+                self.record_failure();
+                Err(CircuitError::Failed(e))
+            }
+        }
+    }
+
+    /// Wraps a handler's downstream call.
+    ///
+    /// An open circuit yields `503 Service Unavailable` with a `Retry-After`
+    /// header, and a failed call yields `502 Bad Gateway`.
+    pub fn respond<E, F>(&self, f: F) -> Response
+    where
+        F: FnOnce() -> Result<Response, E>,
+    {
+        match self.call(f) {
+            Ok(response) => response,
+            Err(CircuitError::Open) => {
+                let retry_after = self.cooldown.as_secs_f64().ceil().max(1.0) as u64;
+                Response::error(503, "A dependency is unavailable; try again later.")
+                    .with_header("Retry-After", retry_after.to_string())
+            }
+            Err(CircuitError::Failed(_)) => Response::error(502, "A dependency failed to respond."),
+        }
+    }
+
+    /// Records a successful call, closing the circuit.
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().expect("Mutex poisoned");
+        inner.state = CircuitState::Closed;
+        inner.failures = 0;
+        inner.opened_at = None;
+        inner.trial_in_flight = false;
+    }
+
+    /// Records a failed call, opening the circuit at the threshold or after
+    /// a failed half-open trial.
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().expect("Mutex poisoned");
+        inner.failures = inner.failures.saturating_add(1);
+        inner.trial_in_flight = false;
+        if inner.state == CircuitState::HalfOpen || inner.failures >= self.failure_threshold {
+            inner.state = CircuitState::Open;
+            inner.opened_at = Some(self.clock.now());
+        }
+    }
+
+    fn refresh(&self, inner: &mut BreakerState) {
+        if inner.state == CircuitState::Open
+            && inner.opened_at.is_some_and(|opened| {
+                self.clock.now().saturating_duration_since(opened) >= self.cooldown
+            })
+        {
+            inner.state = CircuitState::HalfOpen;
+        }
+    }
+}
+
+/// Records a failure if the call it guards panics, so a panicking trial
+/// cannot leave the breaker half-open with its trial forever in flight.
+struct PanicGuard<'a>(&'a CircuitBreaker);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        self.0.record_failure();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::thread;
+
+    fn fail(breaker: &CircuitBreaker) -> Result<(), CircuitError<&'static str>> {
+        breaker.call(|| Err("down"))
+    }
+
+    #[test]
+    fn test_consecutive_failures_open_the_circuit() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        assert_eq!(fail(&breaker), Err(CircuitError::Failed("down")));
+        assert_eq!(fail(&breaker), Err(CircuitError::Failed("down")));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(fail(&breaker), Err(CircuitError::Failed("down")));
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let mut called = false;
+        let result: Result<(), CircuitError<()>> = breaker.call(|| {
+            called = true;
+            Ok(())
+        });
+        assert_eq!(result, Err(CircuitError::Open));
+        assert!(!called);
+
+        let response = breaker.respond(|| Ok::<_, ()>(Response::ok()));
+        assert_eq!(response.status, 503);
+        assert_eq!(response.headers.get("Retry-After"), Some("60"));
+    }
+
+    #[test]
+    fn test_cooldown_half_opens_and_success_closes() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        fail(&breaker).unwrap_err();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // A failed trial reopens the circuit for another cooldown.
+        fail(&breaker).unwrap_err();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        thread::sleep(Duration::from_millis(30));
+        let response = breaker.respond(|| Ok::<_, ()>(Response::text(200, "up")));
+        assert_eq!(response.status, 200);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_cooldown_follows_clock() {
+        let clock = Arc::new(MockClock::new());
+        let breaker =
+            CircuitBreaker::new(1, Duration::from_secs(30)).with_clock(Arc::clone(&clock) as _);
+        fail(&breaker).unwrap_err();
+
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(breaker.state(), CircuitState::Open);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+
+    #[test]
+    fn test_panicking_trial_counts_as_failure() {
+        let clock = Arc::new(MockClock::new());
+        let breaker =
+            CircuitBreaker::new(1, Duration::from_secs(30)).with_clock(Arc::clone(&clock) as _);
+        fail(&breaker).unwrap_err();
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        let trial = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _: Result<(), CircuitError<()>> = breaker.call(|| panic!("trial blew up"));
+        }));
+        assert!(trial.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // The next cooldown lets a fresh trial through.
+        clock.advance(Duration::from_secs(30));
+        let result: Result<u8, CircuitError<()>> = breaker.call(|| Ok(1));
+        assert_eq!(result, Ok(1));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
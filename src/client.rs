@@ -0,0 +1,406 @@
+use std::{
+    fmt,
+    io::{self, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{headers::Headers, request::Method, response::Response};
+
+/// Errors from sending a request with `HttpClient`.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The URL is malformed or not `http://`.
+    InvalidUrl(String),
+    /// Connecting, sending, or reading the response failed.
+    Io(io::Error),
+    /// The server took longer than the client's timeout.
+    Timeout,
+    /// More redirects than the client follows.
+    TooManyRedirects(usize),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(url) => write!(f, "Invalid URL: {url}"),
+            ClientError::Io(e) => write!(f, "Client I/O Error: {e}"),
+            ClientError::Timeout => write!(f, "Client Timeout"),
+            ClientError::TooManyRedirects(max) => {
+                write!(f, "Too Many Redirects: gave up after {max}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+            _ => ClientError::Io(e),
+        }
+    }
+}
+
+/// A small blocking HTTP/1.1 client for calling upstream services from a
+/// handler.
+///
+/// Each request opens a new connection with `Connection: close`. Only
+/// plain `http://` URLs are supported.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    timeout: Option<Duration>,
+    max_redirects: usize,
+}
+
+impl Default for HttpClient {
+    fn default() -> HttpClient {
+        HttpClient {
+            timeout: Some(Duration::from_secs(30)),
+            max_redirects: 5,
+        }
+    }
+}
+
+impl HttpClient {
+    /// Creates a client with a 30 second timeout that follows up to 5
+    /// redirects.
+    pub fn new() -> HttpClient {
+        HttpClient::default()
+    }
+
+    /// Sets how long connecting, and each read or write, may take; `None`
+    /// waits forever.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> HttpClient {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many redirects to follow; 0 returns them to the caller.
+    pub fn with_max_redirects(mut self, max: usize) -> HttpClient {
+        self.max_redirects = max;
+        self
+    }
+
+    /// Sends a `GET` request for `url`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ClientError` under the same conditions as `request`.
+    pub fn get(&self, url: &str) -> Result<Response, ClientError> {
+        self.request(Method::Get, url, &Headers::new(), b"")
+    }
+
+    /// Sends a request and returns the response, following redirects.
+    ///
+    /// `Host`, `Connection`, and (for non-empty bodies) `Content-Length`
+    /// are added unless `headers` sets them. A `303`, or a `301` or `302`
+    /// answering a `POST`, is followed with a bodiless `GET`; `307` and
+    /// `308` repeat the original method and body.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidUrl` for a malformed or non-`http` URL
+    /// (including a redirect's `Location`), `ClientError::Timeout` if the
+    /// server stops responding, `ClientError::TooManyRedirects` past the
+    /// limit, and `ClientError::Io` for other connection failures.
+    pub fn request(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &Headers,
+        body: &[u8],
+    ) -> Result<Response, ClientError> {
+        let mut url = Url::parse(url)?;
+        let mut method = method;
+        let mut body = body;
+        let mut redirects = 0;
+        loop {
+            let response = self.send(&method, &url, headers, body)?;
+            let Some(location) = response
+                .headers
+                .get("Location")
+                .filter(|_| matches!(response.status, 301 | 302 | 303 | 307 | 308))
+            else {
+                return Ok(response);
+            };
+            if redirects == self.max_redirects {
+                if self.max_redirects == 0 {
+                    return Ok(response);
+                }
+                return Err(ClientError::TooManyRedirects(self.max_redirects));
+            }
+            redirects += 1;
+
+            url = url.join(location)?;
+            if response.status == 303 || (response.status <= 302 && method == Method::Post) {
+                method = Method::Get;
+                body = b"";
+            }
+        }
+    }
+
+    fn send(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &Headers,
+        body: &[u8],
+    ) -> Result<Response, ClientError> {
+        let (mut response, mut reader) = self.open(method, url, headers, body)?;
+        response.read_body_from(&mut reader, *method != Method::Head)?;
+        Ok(response)
+    }
+
+    /// Sends one request and reads the response head, returning it with
+    /// the connection positioned at the body.
+    pub(crate) fn open(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &Headers,
+        body: &[u8],
+    ) -> Result<(Response, BufReader<TcpStream>), ClientError> {
+        let mut head = format!("{method} {} HTTP/1.1\r\n", url.target);
+        if !headers.contains("Host") {
+            head.push_str(&format!("Host: {}\r\n", url.authority()));
+        }
+        if !headers.contains("Connection") {
+            head.push_str("Connection: close\r\n");
+        }
+        for (name, value) in headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() && !headers.contains("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut stream = self.connect(url)?;
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        let mut reader = BufReader::new(stream);
+        let response = Response::read_head(&mut reader)?;
+        Ok((response, reader))
+    }
+
+    fn connect(&self, url: &Url) -> Result<TcpStream, ClientError> {
+        let host = url.host.trim_start_matches('[').trim_end_matches(']');
+        let mut last_error = None;
+        for addr in (host, url.port).to_socket_addrs()? {
+            let connected = match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match connected {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.timeout)?;
+                    stream.set_write_timeout(self.timeout)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error
+            .map(ClientError::from)
+            .unwrap_or_else(|| ClientError::InvalidUrl(format!("{host} has no address"))))
+    }
+}
+
+/// The parts of an `http://` URL needed to send a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    host: String,
+    port: u16,
+    /// Path and query, starting with `/`.
+    target: String,
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> Result<Url, ClientError> {
+        let invalid = || ClientError::InvalidUrl(url.to_string());
+        let rest = url
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .map(|_| &url[7..])
+            .ok_or_else(invalid)?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, String::from("/")),
+        };
+        // Drop any fragment; it is never sent.
+        let target = match target.split_once('#') {
+            Some((target, _)) => target.to_string(),
+            None => target,
+        };
+
+        let port_at = match authority.rfind(']') {
+            Some(bracket) => authority[bracket..].find(':').map(|i| bracket + i),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_at {
+            Some(i) => (
+                &authority[..i],
+                authority[i + 1..].parse().map_err(|_| invalid())?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err(invalid());
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    /// The `Host` header value.
+    fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Resolves a `Location` header against this URL.
+    fn join(&self, location: &str) -> Result<Url, ClientError> {
+        if location
+            .get(..7)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        {
+            return Url::parse(location);
+        }
+        if location.contains("://") || location.starts_with("//") {
+            return Err(ClientError::InvalidUrl(location.to_string()));
+        }
+        let target = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let path = self.target.split('?').next().unwrap_or("/");
+            let dir = &path[..=path.rfind('/').unwrap_or(0)];
+            format!("{dir}{location}")
+        };
+        Ok(Url {
+            target,
+            ..self.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{router::Router, server::ServerConfig, testing::TestClient};
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn test_url_parsing() {
+        let url = Url::parse("http://example.com:8080/a/b?x=1#top").unwrap();
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.target, "/a/b?x=1");
+        assert_eq!(url.authority(), "example.com:8080");
+
+        let url = Url::parse("HTTP://[::1]?q").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 80));
+        assert_eq!(url.target, "/?q");
+
+        for bad in [
+            "https://example.com/",
+            "example.com",
+            "http://",
+            "http://h:port/",
+        ] {
+            assert!(
+                matches!(Url::parse(bad), Err(ClientError::InvalidUrl(_))),
+                "{bad}"
+            );
+        }
+
+        let base = Url::parse("http://h/docs/page?x").unwrap();
+        assert_eq!(base.join("other").unwrap().target, "/docs/other");
+        assert_eq!(base.join("/root").unwrap().target, "/root");
+        assert_eq!(base.join("http://g:81/").unwrap().authority(), "g:81");
+        assert!(base.join("https://h/").is_err());
+    }
+
+    #[test]
+    fn test_fetches_from_own_server() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "home"));
+        router.get("/old", |_| Response::new(301).with_header("Location", "/"));
+        router.get("/loop", |_| {
+            Response::new(302).with_header("Location", "/loop")
+        });
+        router.post("/form", |_| {
+            Response::new(303).with_header("Location", "/echo")
+        });
+        router.get("/echo", |req| Response::text(200, req.method.to_string()));
+        router.add(Method::Put, "/echo", |req| {
+            Response::new(200).with_body(req.body.clone())
+        });
+        router.add(Method::Put, "/moved", |_| {
+            Response::new(307).with_header("Location", "echo")
+        });
+        let server = TestClient::new(ServerConfig::default(), router);
+        let base = format!("http://{}", server.server().local_addr());
+        let client = HttpClient::new();
+
+        let response = client.get(&format!("{base}/")).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"home");
+
+        let response = client.get(&format!("{base}/old")).unwrap();
+        assert_eq!(response.body, b"home");
+        let response = client
+            .clone()
+            .with_max_redirects(0)
+            .get(&format!("{base}/old"))
+            .unwrap();
+        assert_eq!(response.status, 301);
+        assert!(matches!(
+            client.get(&format!("{base}/loop")),
+            Err(ClientError::TooManyRedirects(5))
+        ));
+
+        let form = client
+            .request(
+                Method::Post,
+                &format!("{base}/form"),
+                &Headers::new(),
+                b"a=1",
+            )
+            .unwrap();
+        assert_eq!(form.body, b"GET");
+        let moved = client
+            .request(
+                Method::Put,
+                &format!("{base}/moved"),
+                &Headers::new(),
+                b"data",
+            )
+            .unwrap();
+        assert_eq!(moved.body, b"data");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let silent = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(300));
+            drop(stream);
+        });
+
+        let client = HttpClient::new().with_timeout(Some(Duration::from_millis(50)));
+        assert!(matches!(
+            client.get(&format!("http://{addr}/")),
+            Err(ClientError::Timeout)
+        ));
+        silent.join().unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A source of the current time.
+///
+/// The pool's idle timeouts, job deadlines and schedules, and the server's
+/// keep-alive, connection-duration and body-rate limits, read the time
+/// through a `Clock` rather than `Instant::now`, so tests can swap in a
+/// `MockClock` and move time forward without sleeping.
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> Instant;
+}
+
+/// The real clock, backed by `Instant::now`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// It starts at the moment it is created and stays there until `advance`
+/// is called.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+impl MockClock {
+    /// Create a clock stopped at the current instant.
+    pub fn new() -> MockClock {
+        MockClock {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().expect("Mutex poisoned") += by;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().expect("Mutex poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_advanced() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        assert!(SystemClock.now() <= SystemClock.now());
+    }
+}
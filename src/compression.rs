@@ -0,0 +1,800 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::{middleware::Middleware, request::Request, response::Response};
+
+/// Turns a response body into an encoded one.
+pub type Encoder = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// Parses an `Accept-Encoding` header into `(coding, quality)` pairs.
+///
+/// Codings are lowercased, a missing `q` means 1.0, and entries with an
+/// unparseable quality are skipped.
+pub fn parse_accept_encoding(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+
+            let mut quality = 1.0;
+            for param in params {
+                if let Some((name, value)) = param.split_once('=')
+                    && name.trim().eq_ignore_ascii_case("q")
+                {
+                    quality = value.trim().parse::<f32>().ok()?.clamp(0.0, 1.0);
+                }
+            }
+            Some((coding, quality))
+        })
+        .collect()
+}
+
+/// Picks the coding from `available` that the client prefers.
+///
+/// `available` is in server preference order, which breaks ties between
+/// equal qualities. Codings the client does not list fall back to its `*`
+/// entry, if any; a quality of 0 refuses a coding. Returns `None` when the
+/// identity encoding should be used.
+pub fn negotiate<'a>(accept_encoding: &str, available: &[&'a str]) -> Option<&'a str> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    let quality = |coding: &str| {
+        let lookup = |name: &str| accepted.iter().find(|(c, _)| c == name).map(|&(_, q)| q);
+        lookup(coding).or_else(|| lookup("*")).unwrap_or(0.0)
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for &coding in available {
+        let q = quality(coding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Middleware that compresses response bodies the client can decode.
+///
+/// `gzip` is built in. Other codings, such as `br` backed by an external
+/// brotli encoder, can be registered with `encoder`; codings registered
+/// earlier win ties in client preference, and registered codings are tried
+/// before `gzip`.
+pub struct Compression {
+    encoders: Vec<(String, Encoder)>,
+    min_size: usize,
+}
+
+impl Compression {
+    /// Compresses bodies of at least 256 bytes with gzip.
+    pub fn new() -> Compression {
+        Compression {
+            encoders: vec![(String::from("gzip"), Arc::new(gzip))],
+            min_size: 256,
+        }
+    }
+
+    /// Registers an encoder for `coding`, preferred over those already set.
+    pub fn encoder<F>(mut self, coding: impl Into<String>, encode: F) -> Compression
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let coding = coding.into().to_ascii_lowercase();
+        self.encoders.retain(|(c, _)| *c != coding);
+        self.encoders.insert(0, (coding, Arc::new(encode)));
+        self
+    }
+
+    /// Leaves bodies shorter than `min_size` bytes uncompressed.
+    pub fn min_size(mut self, min_size: usize) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, req: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response {
+        let mut response = next(req);
+        let is_candidate = (200..300).contains(&response.status)
+            && !matches!(response.status, 204 | 206)
+            && !response.is_chunked()
+            && !response.headers.contains("Content-Encoding");
+        if !is_candidate {
+            return response;
+        }
+
+        // The body depends on Accept-Encoding whether or not it is encoded.
+        add_vary(&mut response);
+        if response.body.len() < self.min_size {
+            return response;
+        }
+
+        let available: Vec<&str> = self.encoders.iter().map(|(c, _)| c.as_str()).collect();
+        let accept_encoding = req.headers.get("Accept-Encoding").unwrap_or_default();
+        let Some(coding) = negotiate(accept_encoding, &available) else {
+            return response;
+        };
+        let encoders: HashMap<&str, &Encoder> =
+            self.encoders.iter().map(|(c, e)| (c.as_str(), e)).collect();
+
+        let encoded = encoders[coding](&response.body);
+        // Incompressible data can come out larger; send it as is.
+        if encoded.len() >= response.body.len() {
+            return response;
+        }
+        response.body = encoded;
+        response.headers.remove("Content-Length");
+        response.headers.insert("Content-Encoding", coding);
+        response
+    }
+}
+
+fn add_vary(response: &mut Response) {
+    let vary = match response.headers.get("Vary") {
+        Some(existing)
+            if existing
+                .split(',')
+                .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case("Accept-Encoding")) =>
+        {
+            return;
+        }
+        Some(existing) => format!("{existing}, Accept-Encoding"),
+        None => String::from("Accept-Encoding"),
+    };
+    response.headers.insert("Vary", vary);
+}
+
+/// Compresses `data` into a gzip (RFC 1952) member.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, CM = deflate, no flags, no mtime, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Computes the CRC-32 (IEEE) checksum used by gzip.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW: usize = 32 * 1024;
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Writes bits least-significant first, as deflate requires.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which deflate stores most-significant first.
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Writes a literal/length symbol with the fixed Huffman code.
+fn write_symbol(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_symbol(writer, 257 + code as u32);
+    writer.write(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write(
+        (distance - DIST_BASE[code] as usize) as u32,
+        DIST_EXTRA[code] as u32,
+    );
+}
+
+/// Compresses `data` into a single fixed-Huffman deflate (RFC 1951) block,
+/// finding repeats with a hash-chained LZ77 search.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        bits: 0,
+        count: 0,
+    };
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes).
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let hash = |i: usize| {
+        let key = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (key.wrapping_mul(0x9e37_79b1) >> 17) as usize
+    };
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            let max = MAX_MATCH.min(data.len() - i);
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let length = (0..max)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == max {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            write_match(&mut writer, best.0, best.1);
+            for k in i..i + best.0 {
+                insert(k, &mut head, &mut prev);
+            }
+            i += best.0;
+        } else {
+            write_symbol(&mut writer, data[i] as u32);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    write_symbol(&mut writer, 256);
+    writer.finish()
+}
+
+/// Why `gunzip` failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GunzipError {
+    /// The input is not a valid gzip stream.
+    Malformed(String),
+    /// The decompressed output would exceed the limit.
+    TooLarge,
+}
+
+impl fmt::Display for GunzipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GunzipError::Malformed(msg) => write!(f, "Malformed gzip data: {msg}"),
+            GunzipError::TooLarge => f.write_str("Decompressed data exceeds the limit"),
+        }
+    }
+}
+
+fn malformed(msg: &str) -> GunzipError {
+    GunzipError::Malformed(msg.to_string())
+}
+
+/// Decompresses gzip (RFC 1952) data, which may hold several members.
+///
+/// Decoding stops with `GunzipError::TooLarge` as soon as the output
+/// passes `limit` bytes, so a small input that expands enormously costs at
+/// most `limit` bytes of memory.
+pub fn gunzip(data: &[u8], limit: Option<u64>) -> Result<Vec<u8>, GunzipError> {
+    let limit = limit.map_or(usize::MAX, |limit| {
+        usize::try_from(limit).unwrap_or(usize::MAX)
+    });
+    let mut out = Vec::new();
+    let mut rest = data;
+    loop {
+        let start = out.len();
+        let body = skip_gzip_header(rest)?;
+        let used = inflate(body, &mut out, limit)?;
+        let trailer = body
+            .get(used..used + 8)
+            .ok_or_else(|| malformed("truncated trailer"))?;
+        let (crc, size) = trailer.split_at(4);
+        let member = &out[start..];
+        if crc32(member).to_le_bytes() != crc {
+            return Err(malformed("CRC mismatch"));
+        }
+        if (member.len() as u32).to_le_bytes() != size {
+            return Err(malformed("length mismatch"));
+        }
+        rest = &body[used + 8..];
+        if rest.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+/// Returns the deflate stream following a gzip member's header.
+fn skip_gzip_header(data: &[u8]) -> Result<&[u8], GunzipError> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let [0x1f, 0x8b, 8, flags, _, _, _, _, _, _, rest @ ..] = data else {
+        return Err(malformed("not a gzip member"));
+    };
+    if flags & 0xe0 != 0 {
+        return Err(malformed("reserved flags set"));
+    }
+    let mut rest = rest;
+    if flags & FEXTRA != 0 {
+        let [lo, hi, tail @ ..] = rest else {
+            return Err(malformed("truncated header"));
+        };
+        let len = u16::from_le_bytes([*lo, *hi]) as usize;
+        rest = tail
+            .get(len..)
+            .ok_or_else(|| malformed("truncated header"))?;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| malformed("truncated header"))?;
+            rest = &rest[end + 1..];
+        }
+    }
+    if flags & FHCRC != 0 {
+        rest = rest.get(2..).ok_or_else(|| malformed("truncated header"))?;
+    }
+    Ok(rest)
+}
+
+/// Reads bits least-significant first, as deflate stores them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, GunzipError> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| malformed("truncated deflate stream"))?;
+            self.bits |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << count) - 1);
+        self.bits >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drops the rest of the current byte. Whole bytes are only loaded as
+    /// needed, so no more than that is buffered.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+    /// How many codes have each length.
+    counts: [u16; 16],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code for `lengths`, indexed by symbol; 0 means unused.
+    /// An incomplete code is allowed, as deflate permits one for a single
+    /// distance code.
+    fn new(lengths: &[u8]) -> Result<Huffman, GunzipError> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(malformed("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader<'_>) -> Result<u16, GunzipError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(malformed("invalid Huffman code"))
+    }
+}
+
+/// The order code length code lengths are sent in (RFC 1951, 3.2.7).
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses one deflate (RFC 1951) stream from `data` onto `out`,
+/// returning how many bytes of `data` it used.
+fn inflate(data: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<usize, GunzipError> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bits: 0,
+        count: 0,
+    };
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or_else(|| malformed("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if !len != u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(malformed("stored block length mismatch"));
+                }
+                let start = reader.pos + 4;
+                let block = data
+                    .get(start..start + len as usize)
+                    .ok_or_else(|| malformed("truncated stored block"))?;
+                if out.len() + block.len() > limit {
+                    return Err(GunzipError::TooLarge);
+                }
+                out.extend_from_slice(block);
+                reader.pos = start + block.len();
+            }
+            1 => {
+                let mut lengths = [0u8; 288 + 30];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                lengths[288..].fill(5);
+                let literals = Huffman::new(&lengths[..288])?;
+                let distances = Huffman::new(&lengths[288..])?;
+                inflate_block(&mut reader, out, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, out, limit, &literals, &distances)?;
+            }
+            _ => return Err(malformed("invalid block type")),
+        }
+        if last {
+            return Ok(reader.pos);
+        }
+    }
+}
+
+/// Reads the code definitions at the start of a dynamic Huffman block.
+fn read_dynamic_codes(reader: &mut BitReader<'_>) -> Result<(Huffman, Huffman), GunzipError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(malformed("too many codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *i
+                    .checked_sub(1)
+                    .and_then(|j| lengths.get(j))
+                    .ok_or_else(|| malformed("repeat with no previous length"))?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        let run = lengths
+            .get_mut(i..i + repeat)
+            .ok_or_else(|| malformed("code lengths overflow"))?;
+        run.fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(malformed("no end-of-block code"));
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals)?, Huffman::new(distances)?))
+}
+
+/// Decodes one Huffman-coded block's symbols onto `out`.
+fn inflate_block(
+    reader: &mut BitReader<'_>,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), GunzipError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => {
+                if out.len() >= limit {
+                    return Err(GunzipError::TooLarge);
+                }
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() {
+                    return Err(malformed("invalid length code"));
+                }
+                let length =
+                    LENGTH_BASE[code] as usize + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                let code = distances.decode(reader)? as usize;
+                if code >= DIST_BASE.len() {
+                    return Err(malformed("invalid distance code"));
+                }
+                let distance =
+                    DIST_BASE[code] as usize + reader.bits(DIST_EXTRA[code] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(malformed("distance before start of output"));
+                }
+                if out.len() + length > limit {
+                    return Err(GunzipError::TooLarge);
+                }
+                // The source may overlap the bytes being written, so copy
+                // one at a time.
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::Method, router::Router};
+
+    fn compressed_router() -> Router {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "abc".repeat(200)));
+        router.wrap(Compression::new().encoder("br", |body| {
+            // Stand-in for a real brotli encoder.
+            format!("br:{}", body.len()).into_bytes()
+        }));
+        router
+    }
+
+    fn get(accept_encoding: &str) -> Response {
+        let mut req = Request::new(Method::Get, "/");
+        req.headers.insert("Accept-Encoding", accept_encoding);
+        compressed_router().handle(&mut req)
+    }
+
+    #[test]
+    fn test_quality_values() {
+        assert_eq!(
+            parse_accept_encoding("gzip;q=0.5, BR ; q=1.0, deflate"),
+            [
+                (String::from("gzip"), 0.5),
+                (String::from("br"), 1.0),
+                (String::from("deflate"), 1.0)
+            ]
+        );
+        assert_eq!(
+            negotiate("gzip;q=0.5, br;q=1.0", &["br", "gzip"]),
+            Some("br")
+        );
+        assert_eq!(negotiate("gzip, br", &["br", "gzip"]), Some("br"));
+        assert_eq!(negotiate("gzip, br;q=0.9", &["br", "gzip"]), Some("gzip"));
+        assert_eq!(negotiate("br;q=0, *", &["br", "gzip"]), Some("gzip"));
+        assert_eq!(negotiate("identity", &["br", "gzip"]), None);
+    }
+
+    #[test]
+    fn test_br_preferred() {
+        let response = get("gzip;q=0.5, br;q=1.0");
+        assert_eq!(response.headers.get("Content-Encoding"), Some("br"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body, b"br:600");
+    }
+
+    #[test]
+    fn test_gzip_only() {
+        let response = get("gzip");
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(&response.body[..3], [0x1f, 0x8b, 8]);
+        assert!(response.body.len() < 100);
+
+        let len = response.body.len();
+        assert_eq!(response.body[len - 4..], 600u32.to_le_bytes());
+        assert_eq!(
+            response.body[len - 8..len - 4],
+            crc32("abc".repeat(200).as_bytes()).to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_refused_encoding_sends_identity() {
+        let response = get("gzip;q=0, br;q=0");
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body, "abc".repeat(200).as_bytes());
+    }
+
+    #[test]
+    fn test_gzip_known_output() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        // Checked against zlib's decoder.
+        assert_eq!(
+            gzip(b"hello hello hello"),
+            [
+                0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0x40,
+                0x22, 0x01, 0x80, 0x88, 0xf9, 0xe5, 0x11, 0, 0, 0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gunzip() {
+        let text = b"Content negotiation, compression, and more compression.".repeat(20);
+        assert_eq!(gunzip(&gzip(&text), None).unwrap(), text);
+
+        // From Python's gzip module: a stored block, and a dynamic Huffman
+        // block, neither of which `gzip` writes.
+        let stored = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x06, 0x00, 0xf9,
+            0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x0b, 0xf9, 0x43, 0x56, 0x06, 0x00, 0x00,
+            0x00,
+        ];
+        assert_eq!(gunzip(&stored, None).unwrap(), b"stored");
+        let dynamic = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x5d, 0xc1, 0xd9, 0x11,
+            0xc0, 0x10, 0x14, 0x00, 0xc0, 0x56, 0xf4, 0xa1, 0x1a, 0xf7, 0x2d, 0x84, 0xb8, 0xaa,
+            0xcf, 0x1b, 0xe3, 0xcb, 0x2e, 0x42, 0x37, 0x8c, 0x09, 0xa1, 0x94, 0x31, 0xce, 0xc5,
+            0x26, 0xa5, 0x52, 0x1a, 0x18, 0x63, 0xad, 0x73, 0xde, 0x87, 0x10, 0xe3, 0x73, 0xa4,
+            0x94, 0xf3, 0x0b, 0x4a, 0xa9, 0xe0, 0x03, 0xad, 0xf5, 0x3e, 0xc6, 0x9c, 0x6b, 0xfd,
+            0x69, 0x1d, 0xab, 0xec, 0x5a, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            gunzip(&dynamic, None).unwrap(),
+            b"                  ;;aabbccddeeeeeeffgghhhhiijjkkllmmnnooooooooppqqrrrrssttttuuuuvvwwxxyyzz"
+        );
+
+        // Concatenated members decode as one stream.
+        let mut both = gzip(b"one, ");
+        both.extend_from_slice(&gzip(b"two"));
+        assert_eq!(gunzip(&both, None).unwrap(), b"one, two");
+    }
+
+    #[test]
+    fn test_gunzip_limits_and_errors() {
+        let bomb = gzip(&vec![0; 1 << 20]);
+        assert!(bomb.len() < 8 * 1024);
+        assert_eq!(gunzip(&bomb, Some(64 * 1024)), Err(GunzipError::TooLarge));
+        assert_eq!(gunzip(&bomb, Some(1 << 20)).unwrap().len(), 1 << 20);
+
+        let mut corrupt = gzip(b"payload");
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 1;
+        assert!(matches!(
+            gunzip(&corrupt, None),
+            Err(GunzipError::Malformed(_))
+        ));
+
+        let truncated = gzip(b"payload payload payload");
+        assert!(matches!(
+            gunzip(&truncated[..truncated.len() - 12], None),
+            Err(GunzipError::Malformed(_))
+        ));
+        assert!(matches!(
+            gunzip(b"plain text", None),
+            Err(GunzipError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use crate::{
+    logging::warning,
+    response::{Response, reason_phrase},
+};
+
+/// The page used when no custom page is registered for a status.
+const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"utf-8\">
+    <title>{status} {reason}</title>
+  </head>
+  <body>
+    <h1>{status} {reason}</h1>
+    <p>{message}</p>
+  </body>
+</html>
+";
+
+/// Where the body of a custom error page comes from.
+#[derive(Debug, Clone)]
+pub enum ErrorPage {
+    /// A file read from disk each time the page is served.
+    File(PathBuf),
+    /// An inline HTML template. `{status}`, `{reason}`, and `{message}` are
+    /// replaced when the page is rendered.
+    Template(String),
+}
+
+/// Custom bodies for the error responses the server generates itself.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    pages: HashMap<u16, ErrorPage>,
+}
+
+impl ErrorPages {
+    /// Create an empty set; every status uses the built-in page.
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    /// Serves the file at `path` for `status` errors.
+    pub fn file(&mut self, status: u16, path: impl Into<PathBuf>) -> &mut ErrorPages {
+        self.pages.insert(status, ErrorPage::File(path.into()));
+        self
+    }
+
+    /// Renders `template` for `status` errors.
+    pub fn template(&mut self, status: u16, template: impl Into<String>) -> &mut ErrorPages {
+        self.pages
+            .insert(status, ErrorPage::Template(template.into()));
+        self
+    }
+
+    /// Builds the HTML error response for `status`.
+    ///
+    /// Falls back to the built-in page if none is registered or the
+    /// registered file cannot be read.
+    pub fn render(&self, status: u16, message: &str) -> Response {
+        let body = match self.pages.get(&status) {
+            Some(ErrorPage::File(path)) => match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    warning!("Failed to read error page {}: {}", path.display(), e);
+                    fill(DEFAULT_TEMPLATE, status, message)
+                }
+            },
+            Some(ErrorPage::Template(template)) => fill(template, status, message),
+            None => fill(DEFAULT_TEMPLATE, status, message),
+        };
+
+        Response::html(status, body)
+    }
+}
+
+/// Renders the built-in error page.
+pub(crate) fn default_page(status: u16, message: &str) -> String {
+    fill(DEFAULT_TEMPLATE, status, message)
+}
+
+/// Builds an RFC 7807 `application/problem+json` response for `status`.
+///
+/// The `type` is `about:blank`, so `title` is the status's reason phrase
+/// and `detail` explains this occurrence.
+pub fn problem_json(status: u16, detail: &str) -> Response {
+    let body = format!(
+        "{{\"type\":\"about:blank\",\"title\":\"{}\",\"status\":{},\"detail\":\"{}\"}}",
+        escape_json(reason_phrase(status)),
+        status,
+        escape_json(detail),
+    );
+    Response::new(status)
+        .with_header("Content-Type", "application/problem+json")
+        .with_body(body)
+}
+
+fn fill(template: &str, status: u16, message: &str) -> String {
+    template
+        .replace("{status}", &status.to_string())
+        .replace("{reason}", reason_phrase(status))
+        .replace("{message}", &escape_html(message))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestError;
+
+    #[test]
+    fn test_template_placeholders() {
+        let mut pages = ErrorPages::new();
+        pages.template(500, "<p>{status}: {message}</p>");
+
+        let response = pages.render(500, "<oops>");
+        assert_eq!(response.status, 500);
+        assert_eq!(response.body, b"<p>500: &lt;oops&gt;</p>");
+    }
+
+    #[test]
+    fn test_default_page_when_unset() {
+        let body = String::from_utf8(ErrorPages::new().render(404, "gone").body).unwrap();
+        assert!(body.contains("<h1>404 Not Found</h1>"));
+        assert!(body.contains("<p>gone</p>"));
+    }
+
+    #[test]
+    fn test_problem_json_document() {
+        let error = RequestError::BodyTooLarge("Body exceeds 10 bytes".into());
+        assert_eq!(error.status_code(), 413);
+        let response = problem_json(error.status_code(), "Body \"too\" big\n");
+        assert_eq!(response.status, 413);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/problem+json")
+        );
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            r#"{"type":"about:blank","title":"Content Too Large","status":413,"detail":"Body \"too\" big\n"}"#
+        );
+    }
+}
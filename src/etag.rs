@@ -0,0 +1,153 @@
+use std::fmt;
+
+/// An entity tag (RFC 7232 section 2.3), either strong or weak.
+///
+/// Strong tags promise byte-for-byte identical representations and may
+/// validate range requests; weak tags only promise semantic equivalence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    weak: bool,
+    tag: String,
+}
+
+impl ETag {
+    /// Create a strong tag. `tag` is the opaque value without quotes.
+    pub fn strong(tag: impl Into<String>) -> ETag {
+        ETag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// Create a weak tag. `tag` is the opaque value without quotes.
+    pub fn weak(tag: impl Into<String>) -> ETag {
+        ETag {
+            weak: true,
+            tag: tag.into(),
+        }
+    }
+
+    /// Create a strong tag from a hash of `contents`.
+    pub fn from_contents(contents: &[u8]) -> ETag {
+        // FNV-1a: cheap, stable across runs, and good enough to tell
+        // representations apart.
+        let hash = contents.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        ETag::strong(format!("{hash:016x}"))
+    }
+
+    /// Parses a single tag such as `"abc"` or `W/"abc"`.
+    pub fn parse(value: &str) -> Option<ETag> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+        Some(ETag {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Returns `true` for a weak (`W/`) tag.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Strong comparison: both tags are strong and their values are equal.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison: the values are equal, ignoring weakness.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+/// Returns `true` if the `If-None-Match` value `header` matches `current`.
+///
+/// `If-None-Match` uses weak comparison, so `W/"a"` matches `"a"`.
+pub(crate) fn none_match(header: &str, current: &ETag) -> bool {
+    header.trim() == "*" || parse_list(header).iter().any(|tag| tag.weak_eq(current))
+}
+
+/// Returns `true` if the `If-Match` value `header` matches `current`.
+///
+/// `If-Match` uses strong comparison, and `*` matches any existing
+/// representation.
+pub(crate) fn any_match(header: &str, current: &ETag) -> bool {
+    header.trim() == "*" || parse_list(header).iter().any(|tag| tag.strong_eq(current))
+}
+
+/// Splits a comma-separated list of entity tags, skipping malformed entries.
+fn parse_list(header: &str) -> Vec<ETag> {
+    let mut tags = Vec::new();
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return tags;
+        }
+        // A tag ends at its closing quote; commas may appear inside it.
+        let opening = match rest.starts_with("W/") {
+            true => 3,
+            false => 1,
+        };
+        let end = rest
+            .get(opening..)
+            .and_then(|tail| tail.find('"'))
+            .map(|i| opening + i + 1);
+        let Some(end) = end else {
+            return tags;
+        };
+        if let Some(tag) = ETag::parse(&rest[..end]) {
+            tags.push(tag);
+        }
+        rest = &rest[end..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let strong = ETag::parse("\"abc\"").unwrap();
+        let weak = ETag::parse("W/\"abc\"").unwrap();
+        assert!(!strong.is_weak());
+        assert!(weak.is_weak());
+        assert_eq!(weak.to_string(), "W/\"abc\"");
+        assert!(ETag::parse("abc").is_none());
+    }
+
+    #[test]
+    fn test_strong_and_weak_comparison() {
+        let strong = ETag::strong("1");
+        let weak = ETag::weak("1");
+        assert!(strong.strong_eq(&ETag::strong("1")));
+        assert!(!strong.strong_eq(&weak));
+        assert!(!weak.strong_eq(&weak));
+        assert!(strong.weak_eq(&weak));
+
+        assert!(none_match("\"x\", W/\"1\"", &strong));
+        assert!(none_match("*", &strong));
+        assert!(!none_match("\"a,b\"", &ETag::strong("a")));
+    }
+}
@@ -0,0 +1,115 @@
+/// An ordered collection of HTTP header fields.
+///
+/// Header names are compared case-insensitively, as required by RFC 7230,
+/// but are stored exactly as they were received or inserted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Create an empty header collection.
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Returns the value of the first header with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the values of every header with the given name, in the
+    /// order they were added.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Returns `true` if at least one header with the given name is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a header, keeping any existing headers with the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Sets a header, replacing every existing header with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Removes every header with the given name.
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    /// Iterates over every header field as `(name, value)`, in the order
+    /// they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of header fields.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no header fields.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert!(headers.contains("CONTENT-TYPE"));
+    }
+
+    #[test]
+    fn test_insert_replaces_existing() {
+        let mut headers = Headers::new();
+        headers.append("X-Test", "a");
+        headers.append("x-test", "b");
+        headers.insert("X-TEST", "c");
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("x-test"), Some("c"));
+    }
+
+    #[test]
+    fn test_get_all_returns_repeated_values() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Content-Type", "text/html");
+        headers.append("set-cookie", "b=2");
+        assert_eq!(headers.get_all("SET-COOKIE"), ["a=1", "b=2"]);
+        assert!(headers.get_all("X-Missing").is_empty());
+    }
+
+    #[test]
+    fn test_iter_keeps_insertion_order() {
+        let mut headers = Headers::new();
+        headers.append("Host", "localhost");
+        headers.append("X-B", "2");
+        headers.append("X-A", "1");
+        headers.insert("host", "example.com");
+        let all: Vec<_> = headers.iter().collect();
+        assert_eq!(all, [("X-B", "2"), ("X-A", "1"), ("host", "example.com")]);
+    }
+}
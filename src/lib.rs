@@ -1,14 +1,74 @@
+pub mod circuit_breaker;
+pub mod client;
+pub mod clock;
+pub mod compression;
+pub mod error_pages;
+pub mod etag;
+pub mod headers;
+pub mod logging;
+pub mod middleware;
+pub mod proxy;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod signals;
+pub mod static_files;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+
+pub use circuit_breaker::{CircuitBreaker, CircuitError, CircuitState};
+pub use client::{ClientError, HttpClient};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use compression::Compression;
+pub use error_pages::{ErrorPage, ErrorPages};
+pub use etag::ETag;
+pub use headers::Headers;
+pub use logging::{FileSink, Level, LogSink, Logger, Record, StdoutSink, set_logger};
+pub use middleware::{BasicAuth, Middleware};
+pub use proxy::{proxy_to, proxy_with};
+pub use request::{
+    BodyReader, ConnectionContext, ExpectRejection, Method, MinBodyRate, ParseConfig, Request,
+    RequestError, Version,
+};
+pub use response::{BodySender, ChunkWriter, EventStream, Response};
+pub use router::{Handler, MatchStrategy, RouteError, Router, TaggedHandler, VHostRouter};
+pub use server::{
+    ListenerConfig, Protocol, Server, ServerConfig, ServerError, TlsHandler, UpgradeHandler,
+    Upgraded, detect_protocol,
+};
+pub use static_files::{FallbackPage, StaticAssets};
+#[cfg(any(test, feature = "test-util"))]
+pub use testing::{ConformanceFailure, TestClient};
+
 use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
     fmt,
-    sync::{Arc, Mutex, mpsc},
+    hash::Hash,
+    io,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc,
+    },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 
+use logging::{error, warning};
+
 /// Custom error type for ThreadPool operations.
 #[derive(Debug)]
 pub enum PoolError {
     CreationError(String),
     SendError(String),
+    QueueFull,
+    JobFailed(String),
 }
 
 impl fmt::Display for PoolError {
@@ -16,19 +76,632 @@ impl fmt::Display for PoolError {
         match self {
             PoolError::CreationError(msg) => write!(f, "Pool Creation Error: {msg}"),
             PoolError::SendError(msg) => write!(f, "Job Dispatch Error: {msg}"),
+            PoolError::QueueFull => write!(f, "Job Dispatch Error: queue is full"),
+            PoolError::JobFailed(msg) => write!(f, "Job Failed: {msg}"),
         }
     }
 }
 
+/// Pool size used when the available parallelism cannot be determined.
+const DEFAULT_POOL_SIZE: usize = 4;
+
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// A job together with the moment it was handed to the pool.
+struct QueuedJob {
+    job: Job,
+    enqueued: Instant,
+    /// Tells the worker that receives it to exit instead of running `job`;
+    /// sent by `ThreadPool::set_size` to shrink the pool.
+    retire: bool,
+    /// Set by `ThreadPool::execute_with_deadline`: a worker that dequeues
+    /// the job later than this drops it unrun.
+    deadline: Option<Instant>,
+}
+
+impl QueuedJob {
+    fn new(job: Job) -> QueuedJob {
+        QueuedJob {
+            job,
+            enqueued: Instant::now(),
+            retire: false,
+            deadline: None,
+        }
+    }
+
+    fn retire() -> QueuedJob {
+        QueuedJob {
+            retire: true,
+            ..QueuedJob::new(Box::new(|| {}))
+        }
+    }
+}
+
+/// How often the autotuner samples the queue.
+const AUTOTUNE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Consecutive samples above the target depth before the autotuner adds a
+/// worker.
+const AUTOTUNE_GROW_AFTER: u32 = 2;
+
+/// Consecutive idle samples before the autotuner retires a worker. Longer
+/// than `AUTOTUNE_GROW_AFTER` so a brief lull between bursts keeps the
+/// workers around.
+const AUTOTUNE_SHRINK_AFTER: u32 = 10;
+
+/// Upper bounds of the wait-time histogram buckets. Waits at or above the
+/// last bound fall into a final overflow bucket.
+const WAIT_BUCKET_BOUNDS: [Duration; 5] = [
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// How many panics `ThreadPool::recent_panics` remembers.
+const RECENT_PANICS_CAPACITY: usize = 32;
+
+/// How long dropping a pool waits for each worker, unless the builder
+/// sets `drop_timeout`.
+const DEFAULT_DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a timed join checks whether a worker has exited.
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Longest an idle worker or a schedule timer sleeps before rereading the
+/// pool's clock, which may be a `MockClock` that moves without waking
+/// anyone.
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A job panic caught by a worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicRecord {
+    /// Index of the worker that ran the job.
+    pub worker: usize,
+    /// The panic message, when the payload was a string.
+    pub message: String,
+    /// When the panic was caught.
+    pub at: SystemTime,
+}
+
+/// Counters shared between the pool handle and its workers.
+#[derive(Default)]
+struct PoolStats {
+    wait_buckets: [AtomicU64; WAIT_BUCKET_BOUNDS.len() + 1],
+    completed: Mutex<usize>,
+    completed_changed: Condvar,
+    /// Jobs sent but not yet picked up by a worker.
+    queued: AtomicUsize,
+    /// Workers currently running a job.
+    busy: AtomicUsize,
+    /// Jobs that panicked.
+    panics: AtomicU64,
+    /// The latest `RECENT_PANICS_CAPACITY` panics, oldest first.
+    recent_panics: Mutex<VecDeque<PanicRecord>>,
+    /// Errors returned by `execute_fallible` jobs, awaiting `take_errors`.
+    errors: Mutex<Vec<Box<dyn Any + Send>>>,
+    /// Set by `shutdown_now`: workers discard jobs instead of running them.
+    abandoning: AtomicBool,
+    /// Jobs discarded since `abandoning` was set.
+    abandoned: AtomicUsize,
+    /// Deadline jobs dropped because they were dequeued too late.
+    dropped_expired: AtomicU64,
+    /// Times a `fair` worker yielded after a job.
+    #[cfg(test)]
+    fair_yields: AtomicUsize,
+}
+
+impl PoolStats {
+    fn record_completed(&self) {
+        *self.completed.lock().expect("Mutex poisoned") += 1;
+        self.completed_changed.notify_all();
+    }
+
+    fn record_panic(&self, worker: usize, payload: &(dyn Any + Send)) {
+        self.panics.fetch_add(1, Ordering::SeqCst);
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("Box<dyn Any>"));
+
+        let mut recent = self.recent_panics.lock().expect("Mutex poisoned");
+        if recent.len() == RECENT_PANICS_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(PanicRecord {
+            worker,
+            message,
+            at: SystemTime::now(),
+        });
+    }
+
+    fn record_wait(&self, waited: Duration) {
+        let bucket = WAIT_BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| waited < bound)
+            .unwrap_or(WAIT_BUCKET_BOUNDS.len());
+        self.wait_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// How long jobs waited in the queue before a worker picked them up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitTimeHistogram {
+    /// Exclusive upper bound of each bucket except the last.
+    pub bounds: Vec<Duration>,
+    /// Job counts per bucket; one longer than `bounds`, ending with the
+    /// count of waits at or above the last bound.
+    pub counts: Vec<u64>,
+}
+
+impl WaitTimeHistogram {
+    /// Returns the total number of recorded waits.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Estimates the wait that fraction `p` (0.0 to 1.0) of jobs stayed
+    /// under, as the upper bound of the bucket that percentile falls in.
+    ///
+    /// Waits in the overflow bucket report the last bound. Returns `None`
+    /// before any wait is recorded.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self
+            .counts
+            .iter()
+            .position(|&count| {
+                seen += count;
+                seen >= rank
+            })
+            .unwrap_or(self.bounds.len());
+        self.bounds
+            .get(bucket)
+            .or_else(|| self.bounds.last())
+            .copied()
+    }
+}
+
+/// A point-in-time view of a pool's activity, from
+/// `ThreadPool::metrics_snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// Number of worker threads.
+    pub size: usize,
+    /// Workers running a job.
+    pub busy: usize,
+    /// Jobs waiting for a free worker.
+    pub queued: usize,
+    /// Jobs that ran to completion.
+    pub completed: usize,
+    /// Jobs that panicked.
+    pub panics: u64,
+    /// Deadline jobs dropped unrun because they expired in the queue.
+    pub dropped_expired: u64,
+    /// Median queue wait, as estimated by `WaitTimeHistogram::percentile`.
+    pub wait_p50: Option<Duration>,
+    /// 90th-percentile queue wait.
+    pub wait_p90: Option<Duration>,
+    /// 99th-percentile queue wait.
+    pub wait_p99: Option<Duration>,
+}
+
+impl PoolMetrics {
+    /// Serializes the metrics as a JSON object, with waits in whole
+    /// milliseconds (`null` before any job has been picked up).
+    pub fn to_json(&self) -> String {
+        let millis = |wait: Option<Duration>| {
+            wait.map_or_else(|| String::from("null"), |wait| wait.as_millis().to_string())
+        };
+        format!(
+            "{{\"size\":{},\"busy\":{},\"queued\":{},\"completed\":{},\"panics\":{},\
+             \"dropped_expired\":{},\"wait_ms\":{{\"p50\":{},\"p90\":{},\"p99\":{}}}}}",
+            self.size,
+            self.busy,
+            self.queued,
+            self.completed,
+            self.panics,
+            self.dropped_expired,
+            millis(self.wait_p50),
+            millis(self.wait_p90),
+            millis(self.wait_p99),
+        )
+    }
+}
+
+/// What `ThreadPool::execute` does when a bounded queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Fail with `PoolError::QueueFull`.
+    Reject,
+    /// Wait until a worker frees a slot in the queue.
+    #[default]
+    Block,
+    /// Run the job on the calling thread instead of queueing it.
+    CallerRuns,
+}
+
+/// What a worker does after a job panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Exit and spawn a fresh worker with the same index in its place.
+    #[default]
+    Restart,
+    /// Exit without a replacement, leaving the pool one worker smaller.
+    Ignore,
+    /// Abort the whole process, for deployments where a supervisor should
+    /// restart it from a clean state.
+    AbortProcess,
+}
+
+/// Which queued job a free worker takes next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueOrder {
+    /// The oldest, so jobs start in the order they were sent.
+    #[default]
+    Fifo,
+    /// The newest. Under load this favors fresh requests, and the data a
+    /// job just touched is more likely to still be in cache, at the cost of
+    /// leaving older jobs waiting.
+    Lifo,
+}
+
+/// The jobs waiting for a worker, shared by every sender and worker.
+struct JobQueue {
+    state: Mutex<QueueState>,
+    /// Signalled when a job arrives or the last sender goes away.
+    available: Condvar,
+    /// Signalled when a bounded queue may have room again.
+    space: Condvar,
+    capacity: Option<usize>,
+    order: QueueOrder,
+}
+
+struct QueueState {
+    jobs: VecDeque<QueuedJob>,
+    /// Live `JobSender`s; the queue closes once this reaches zero.
+    senders: usize,
+    /// Workers parked waiting for a job.
+    waiting: usize,
+}
+
+impl JobQueue {
+    /// Creates a queue and its first sender.
+    fn new(capacity: Option<usize>, order: QueueOrder) -> (Arc<JobQueue>, JobSender) {
+        let queue = Arc::new(JobQueue {
+            state: Mutex::new(QueueState {
+                jobs: VecDeque::new(),
+                senders: 1,
+                waiting: 0,
+            }),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            capacity,
+            order,
+        });
+        let sender = JobSender(Arc::clone(&queue));
+        (queue, sender)
+    }
+
+    /// Whether a bounded queue has no room. A parked worker always takes a
+    /// job directly, which also makes a capacity of 0 a hand-off.
+    fn is_full(&self, state: &QueueState) -> bool {
+        self.capacity
+            .is_some_and(|capacity| state.jobs.len() >= capacity.max(state.waiting))
+    }
+
+    /// Adds `job`, waiting for room if `block` is set; otherwise a full
+    /// queue hands the job back.
+    fn push(&self, job: QueuedJob, block: bool) -> Result<(), QueuedJob> {
+        self.insert(job, block, false)
+    }
+
+    /// Adds `job` where `pop` reaches it only after every job already
+    /// queued, whatever the order, waiting for room in a bounded queue.
+    fn push_last(&self, job: QueuedJob) {
+        // Blocking inserts never hand the job back.
+        let _ = self.insert(job, true, true);
+    }
+
+    fn insert(&self, job: QueuedJob, block: bool, last: bool) -> Result<(), QueuedJob> {
+        let mut state = self.state.lock().expect("Mutex poisoned");
+        while self.is_full(&state) {
+            if !block {
+                return Err(job);
+            }
+            state = self.space.wait(state).expect("Mutex poisoned");
+        }
+        if last && self.order == QueueOrder::Lifo {
+            state.jobs.push_front(job);
+        } else {
+            state.jobs.push_back(job);
+        }
+        drop(state);
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Takes the next job in `order`, waiting for one to arrive, or for at
+    /// most `wait` if it is set.
+    fn pop(&self, wait: Option<Duration>) -> Popped {
+        let mut state = self.state.lock().expect("Mutex poisoned");
+        let mut waited = false;
+        loop {
+            let job = match self.order {
+                QueueOrder::Fifo => state.jobs.pop_front(),
+                QueueOrder::Lifo => state.jobs.pop_back(),
+            };
+            if job.is_some() || state.senders == 0 {
+                drop(state);
+                if self.capacity.is_some() {
+                    self.space.notify_one();
+                }
+                return job.map_or(Popped::Closed, Popped::Job);
+            }
+            if waited {
+                return Popped::Idle;
+            }
+            state.waiting += 1;
+            if self.capacity.is_some() {
+                // A parked worker is room for a hand-off.
+                self.space.notify_one();
+            }
+            state = match wait {
+                Some(wait) => {
+                    waited = true;
+                    self.available
+                        .wait_timeout(state, wait)
+                        .expect("Mutex poisoned")
+                        .0
+                }
+                None => self.available.wait(state).expect("Mutex poisoned"),
+            };
+            state.waiting -= 1;
+        }
+    }
+}
+
+/// What a worker got from `JobQueue::pop`.
+enum Popped {
+    Job(QueuedJob),
+    /// The queue is empty and every sender is gone.
+    Closed,
+    /// The wait ran out with no job.
+    Idle,
+}
+
+/// A handle for adding jobs to a `JobQueue`. Dropping the last one closes
+/// the queue, so workers exit once it is empty.
+struct JobSender(Arc<JobQueue>);
+
+impl JobSender {
+    /// Sends `job`, waiting for room in a bounded queue.
+    fn send(&self, job: QueuedJob) {
+        // Blocking pushes never hand the job back.
+        let _ = self.0.push(job, true);
+    }
+
+    /// Sends `job` to be taken after everything already queued, waiting
+    /// for room in a bounded queue.
+    fn send_last(&self, job: QueuedJob) {
+        self.0.push_last(job);
+    }
+
+    /// Sends `job` unless a bounded queue is full, in which case the job
+    /// is handed back.
+    fn try_send(&self, job: QueuedJob) -> Result<(), QueuedJob> {
+        self.0.push(job, false)
+    }
+}
+
+impl Clone for JobSender {
+    fn clone(&self) -> JobSender {
+        self.0.state.lock().expect("Mutex poisoned").senders += 1;
+        JobSender(Arc::clone(&self.0))
+    }
+}
+
+impl Drop for JobSender {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().expect("Mutex poisoned");
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.0.available.notify_all();
+        }
+    }
+}
+
+/// Callback run on a worker after each job, with the worker index and how
+/// long the job took.
+#[derive(Clone)]
+struct AfterEach(Arc<dyn Fn(usize, Duration) + Send + Sync>);
+
+impl fmt::Debug for AfterEach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AfterEach")
+    }
+}
+
+/// Configures and creates a `ThreadPool`.
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    size: usize,
+    queue_capacity: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    fair: bool,
+    pin_to_cores: bool,
+    panic_policy: PanicPolicy,
+    queue_order: QueueOrder,
+    after_each: Option<AfterEach>,
+    drop_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ThreadPoolBuilder {
+    /// Bounds the job queue to `capacity` pending jobs.
+    ///
+    /// By default the queue is unbounded.
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets how `execute` behaves when the bounded queue is full.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> ThreadPoolBuilder {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Sets which queued job a free worker takes next; FIFO by default.
+    pub fn queue_order(mut self, order: QueueOrder) -> ThreadPoolBuilder {
+        self.queue_order = order;
+        self
+    }
+
+    /// Makes a worker yield after each job before contending for the next.
+    ///
+    /// Without this, a worker that just finished a short job can win the
+    /// job queue's lock again immediately and pop the next job, leaving
+    /// workers parked on its condition variable idle. Yielding gives them a
+    /// chance to take the lock first.
+    pub fn fair(mut self, fair: bool) -> ThreadPoolBuilder {
+        self.fair = fair;
+        self
+    }
+
+    /// Pins each worker to one CPU: worker `id` runs only on CPU
+    /// `id % n` of the `n` the process may use.
+    ///
+    /// Only takes effect on Linux, through `sched_setaffinity`; elsewhere,
+    /// or if the call fails, workers float as usual.
+    pub fn pin_to_cores(mut self, pin: bool) -> ThreadPoolBuilder {
+        self.pin_to_cores = pin;
+        self
+    }
+
+    /// Sets what a worker does after a job panics.
+    ///
+    /// The panic is recorded either way, and the job's `JobHandle` reports
+    /// `PoolError::JobFailed`. With `PanicPolicy::Ignore`, a pool whose
+    /// workers have all panicked stops running jobs until `set_size` adds
+    /// more.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> ThreadPoolBuilder {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Runs `hook` on the worker thread right after every job, including
+    /// jobs that panicked, with the worker index and the job's duration.
+    ///
+    /// The hook itself must not panic.
+    pub fn after_each<F>(mut self, hook: F) -> ThreadPoolBuilder
+    where
+        F: Fn(usize, Duration) + Send + Sync + 'static,
+    {
+        self.after_each = Some(AfterEach(Arc::new(hook)));
+        self
+    }
+
+    /// Sets how long dropping the pool waits for each worker to finish;
+    /// five seconds by default.
+    ///
+    /// A worker still busy after that is logged and detached, so one stuck
+    /// job cannot hang process exit. Call `shutdown` first to wait for
+    /// every job however long it takes.
+    pub fn drop_timeout(mut self, timeout: Duration) -> ThreadPoolBuilder {
+        self.drop_timeout = timeout;
+        self
+    }
+
+    /// Lets a worker exit once it has waited `timeout` without a job, as
+    /// long as another worker remains.
+    ///
+    /// An idle pool shrinks to a single worker; `set_size` or the
+    /// autotuner grow it again. Workers always stay by default.
+    pub fn idle_timeout(mut self, timeout: Duration) -> ThreadPoolBuilder {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the clock that idle timeouts, job deadlines and schedule
+    /// intervals are measured against; `SystemClock` by default.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> ThreadPoolBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// Create the configured ThreadPool.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `PoolError::CreationError` if the size is 0.
+    pub fn build(self) -> Result<ThreadPool, PoolError> {
+        self.spawn(Arc::new(PoolStats::default()))
+    }
+
+    fn spawn(self, stats: Arc<PoolStats>) -> Result<ThreadPool, PoolError> {
+        if self.size == 0 {
+            return Err(PoolError::CreationError(
+                "Pool size must be greater than zero".into(),
+            ));
+        }
+
+        let (queue, sender) = JobQueue::new(self.queue_capacity, self.queue_order);
+        let workers = Arc::new(WorkerSet {
+            queue,
+            threads: Mutex::new(Vec::with_capacity(self.size)),
+            size: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            stats: Arc::clone(&stats),
+            fair: self.fair,
+            pin_to_cores: self.pin_to_cores,
+            panic_policy: self.panic_policy,
+            after_each: self.after_each.clone(),
+            idle_timeout: self.idle_timeout,
+            clock: Arc::clone(&self.clock),
+        });
+        workers.resize(&sender, self.size)?;
+
+        Ok(ThreadPool {
+            workers,
+            sender: Some(sender),
+            overflow_policy: self.overflow_policy,
+            stats,
+            settings: self,
+            schedules: Mutex::new(Vec::new()),
+            autotune: Mutex::new(None),
+            ordered: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
 /// A group of spawned threads that are waiting and ready to handle tasks.
 ///
-/// This manages a collection of `Worker` instances and uses a channel to
-/// dispatch closures to those workers.
+/// This manages a collection of `Worker` instances that take closures from
+/// a shared `JobQueue`, a `Mutex<VecDeque>` paired with a `Condvar`.
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    workers: Arc<WorkerSet>,
+    sender: Option<JobSender>,
+    overflow_policy: OverflowPolicy,
+    stats: Arc<PoolStats>,
+    /// The options this pool was built with, reused by `drain_and_replace`.
+    settings: ThreadPoolBuilder,
+    /// Recurring jobs started with `schedule_every`.
+    schedules: Mutex<Vec<Schedule>>,
+    /// The controller started by `enable_autotune`, if any.
+    autotune: Mutex<Option<Autotune>>,
+    /// Per-key job chains for `execute_ordered`, one `KeyedChains<K>` per
+    /// key type.
+    ordered: Mutex<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
 }
 
 impl ThreadPool {
@@ -40,109 +713,2163 @@ impl ThreadPool {
     ///
     /// This function will return a `PoolError::CreationError` if the size is 0.
     pub fn build(size: usize) -> Result<ThreadPool, PoolError> {
-        if size == 0 {
-            return Err(PoolError::CreationError("Pool size must be greater than zero".into()));
-        }
+        ThreadPool::builder(size).build()
+    }
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
+    /// Create a ThreadPool with one thread per available CPU.
+    ///
+    /// Falls back to a pool of 4 threads if `std::thread::available_parallelism`
+    /// cannot determine the CPU count.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `ThreadPool::build`.
+    pub fn with_available_parallelism() -> Result<ThreadPool, PoolError> {
+        ThreadPool::build(parallelism_or_default(thread::available_parallelism()))
+    }
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+    /// Returns a builder for a pool of `size` threads with further options.
+    pub fn builder(size: usize) -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size,
+            queue_capacity: None,
+            overflow_policy: OverflowPolicy::default(),
+            fair: false,
+            pin_to_cores: false,
+            panic_policy: PanicPolicy::default(),
+            queue_order: QueueOrder::default(),
+            after_each: None,
+            drop_timeout: DEFAULT_DROP_TIMEOUT,
+            idle_timeout: None,
+            clock: Arc::new(SystemClock),
         }
+    }
 
-        Ok(ThreadPool {
-            workers,
-            sender: Some(sender),
-        })
+    /// Create a ThreadPool that runs `after_each` after every job, as set up
+    /// by `ThreadPoolBuilder::after_each`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `ThreadPool::build`.
+    pub fn build_with_hooks<F>(size: usize, after_each: F) -> Result<ThreadPool, PoolError>
+    where
+        F: Fn(usize, Duration) + Send + Sync + Clone + 'static,
+    {
+        ThreadPool::builder(size).after_each(after_each).build()
     }
 
     /// Sends a closure to the pool for execution.
     ///
     /// # Errors
     ///
-    /// Returns `PoolError::SendError` if the receiving side of the channel has been closed.
+    /// Returns `PoolError::SendError` if the pool has shut down, or
+    /// `PoolError::QueueFull` if a bounded queue is full and the pool uses
+    /// `OverflowPolicy::Reject`.
     pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        self.enqueue(QueuedJob::new(Box::new(f)))
+    }
 
-        self.sender
-            .as_ref()
-            .ok_or_else(|| PoolError::SendError("ThreadPool sender is missing".into()))?
-            .send(job)
-            .map_err(|e| PoolError::SendError(e.to_string()))
+    /// Queues `f` like `execute`, but only if a worker picks it up by
+    /// `deadline`.
+    ///
+    /// A job still queued when its deadline passes is dropped unrun when
+    /// a worker reaches it, and counted in `PoolMetrics::dropped_expired`.
+    /// A job that starts in time runs to completion however long it takes.
+    ///
+    /// `deadline` is a real `Instant`; the time left until it is counted
+    /// on the pool's clock.
+    ///
+    /// # Errors
+    ///
+    /// As for `execute`.
+    pub fn execute_with_deadline<F>(&self, deadline: Instant, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let left = deadline.saturating_duration_since(Instant::now());
+        self.enqueue(QueuedJob {
+            deadline: Some(self.workers.clock.now() + left),
+            ..QueuedJob::new(Box::new(f))
+        })
     }
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        drop(self.sender.take());
+    fn enqueue(&self, job: QueuedJob) -> Result<(), PoolError> {
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or_else(|| PoolError::SendError("ThreadPool sender is missing".into()))?;
 
-        for worker in self.workers.drain(..) {
-            if let Some(thread) = worker.thread {
-                thread.join().expect("Thread failed to join");
-            }
+        // Count the job before sending so a worker never decrements first.
+        self.stats.queued.fetch_add(1, Ordering::SeqCst);
+        if self.overflow_policy == OverflowPolicy::Block {
+            sender.send(job);
+            return Ok(());
+        }
+        let Err(job) = sender.try_send(job) else {
+            return Ok(());
+        };
+        self.stats.queued.fetch_sub(1, Ordering::SeqCst);
+        if self.overflow_policy == OverflowPolicy::CallerRuns {
+            (job.job)();
+            return Ok(());
         }
+        Err(PoolError::QueueFull)
     }
-}
 
-struct Worker {
-    _id: usize,
-    thread: Option<thread::JoinHandle<()>>,
-}
+    /// Queues `f`, or runs it on the calling thread if a bounded queue is
+    /// full, whatever the pool's `OverflowPolicy`. Returns whether `f` ran
+    /// inline.
+    ///
+    /// An unbounded queue is never full, so there `f` is always queued.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolError::SendError` if the pool has shut down.
+    pub fn try_execute_or_run<F>(&self, f: F) -> Result<bool, PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = QueuedJob::new(Box::new(f));
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or_else(|| PoolError::SendError("ThreadPool sender is missing".into()))?;
 
-impl Worker {
-    fn new(_id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            
-            let message = receiver.lock().expect("Mutex poisoned").recv();
+        self.stats.queued.fetch_add(1, Ordering::SeqCst);
+        let Err(job) = sender.try_send(job) else {
+            return Ok(false);
+        };
+        self.stats.queued.fetch_sub(1, Ordering::SeqCst);
+        (job.job)();
+        Ok(true)
+    }
 
-            match message {
-                Ok(job) => job(),
-                Err(_) => break,
+    /// Sends a fallible closure to the pool, keeping its error for
+    /// `take_errors` if it fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `execute`.
+    pub fn execute_fallible<F, E>(&self, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() -> Result<(), E> + Send + 'static,
+        E: Send + 'static,
+    {
+        let stats = Arc::clone(&self.stats);
+        self.execute(move || {
+            if let Err(e) = f() {
+                stats
+                    .errors
+                    .lock()
+                    .expect("Mutex poisoned")
+                    .push(Box::new(e));
             }
-        });
+        })
+    }
 
-        Worker {
-            _id,
-            thread: Some(thread),
+    /// Removes and returns the errors of type `E` collected from
+    /// `execute_fallible` jobs, oldest first.
+    ///
+    /// Errors of other types stay queued for their own `take_errors` call.
+    pub fn take_errors<E: 'static>(&self) -> Vec<E> {
+        let mut errors = self.stats.errors.lock().expect("Mutex poisoned");
+        let mut taken = Vec::new();
+        let mut kept = Vec::new();
+        for error in errors.drain(..) {
+            match error.downcast::<E>() {
+                Ok(error) => taken.push(*error),
+                Err(other) => kept.push(other),
+            }
         }
+        *errors = kept;
+        taken
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    /// Sends a closure to the pool that runs only after every earlier job
+    /// sent with an equal `key` has finished.
+    ///
+    /// Jobs with different keys still run in parallel. While a key has
+    /// jobs pending they run back to back on one worker, in the order they
+    /// were sent. A panicking job does not stop the ones after it; the
+    /// first panic is re-raised once the key's queue is empty, so it is
+    /// still counted by `metrics_snapshot`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `execute`. A job
+    /// queued behind a running one is accepted without touching the pool.
+    pub fn execute_ordered<K, F>(&self, key: K, f: F) -> Result<(), PoolError>
+    where
+        K: Hash + Eq + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let chains: Arc<KeyedChains<K>> = {
+            let mut ordered = self.ordered.lock().expect("Mutex poisoned");
+            let chains = ordered
+                .entry(TypeId::of::<K>())
+                .or_insert_with(|| Arc::new(KeyedChains::<K>::default()));
+            Arc::clone(chains)
+                .downcast()
+                .expect("chains are stored under their key type")
+        };
 
-    #[test]
-    fn test_pool_creation() {
-        let pool = ThreadPool::build(4);
-        assert!(pool.is_ok());
-    }
+        let chain = {
+            let mut active = chains.active.lock().expect("Mutex poisoned");
+            if let Some(chain) = active.get(&key) {
+                chain.lock().expect("Mutex poisoned").push_back(Box::new(f));
+                return Ok(());
+            }
+            let chain = Arc::new(Mutex::new(VecDeque::<Job>::from([Box::new(f) as Job])));
+            active.insert(key, Arc::clone(&chain));
+            chain
+        };
 
-    #[test]
-    fn test_zero_size_pool_fails() {
-        let pool = ThreadPool::build(0);
-        assert!(pool.is_err());
+        let runner = {
+            let chains = Arc::clone(&chains);
+            let chain = Arc::clone(&chain);
+            move || chains.run(&chain)
+        };
+        self.execute(runner).inspect_err(|_| chains.remove(&chain))
     }
 
-    #[test]
-    fn test_execution() {
-        let pool = ThreadPool::build(2).unwrap();
-        let counter = Arc::new(AtomicUsize::new(0));
-
-        for _ in 0..10 {
-            let c = Arc::clone(&counter);
-            pool.execute(move || {
-                c.fetch_add(1, Ordering::SeqCst);
-            }).unwrap();
+    /// Sends `n` clones of `f` to the pool.
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first clone that cannot be queued and returns its
+    /// `PoolError`; clones queued before it still run.
+    pub fn execute_many<F>(&self, n: usize, f: F) -> Result<(), PoolError>
+    where
+        F: Fn() + Send + Sync + Clone + 'static,
+    {
+        for _ in 0..n {
+            self.execute(f.clone())?;
         }
-
-        drop(pool);
-        assert_eq!(counter.load(Ordering::SeqCst), 10);
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Calls `f` on every item of `items`, `chunk` items per job, and
+    /// blocks until all of them have been processed.
+    ///
+    /// Grouping items keeps the dispatch cost to one job per chunk; a
+    /// `chunk` of zero is treated as one. Must not be called from one of
+    /// this pool's own jobs, which could leave no worker free to run the
+    /// chunks it waits for.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `execute`, after
+    /// waiting for the chunks already queued, or `PoolError::JobFailed` if
+    /// `f` panicked in some chunk.
+    pub fn par_for_each<T, F>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        chunk: usize,
+        f: F,
+    ) -> Result<(), PoolError>
+    where
+        T: Send + 'static,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let chunk = chunk.max(1);
+        let f = Arc::new(f);
+        let mut items = items.into_iter();
+        let mut handles = Vec::new();
+        let mut sent = Ok(());
+        loop {
+            let batch: Vec<T> = items.by_ref().take(chunk).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let f = Arc::clone(&f);
+            match self.submit(move || batch.into_iter().for_each(|item| f(item))) {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    sent = Err(e);
+                    break;
+                }
+            }
+        }
+
+        let mut result = sent;
+        for handle in handles {
+            if let Err(e) = handle.join() {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Sends a closure to the pool and returns a handle to its result.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `execute`.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        self.execute(move || {
+            let _ = sender.send(f());
+        })?;
+
+        Ok(JobHandle {
+            receiver,
+            result: RefCell::new(None),
+        })
+    }
+
+    /// Sends a closure to the pool and returns a channel that receives its
+    /// return value.
+    ///
+    /// If the job panics, its sender is dropped, so `recv` on the receiver
+    /// returns an error instead of blocking forever.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoolError` under the same conditions as `execute`.
+    pub fn spawn_with_result<F, T>(&self, f: F) -> Result<mpsc::Receiver<T>, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        self.execute(move || {
+            let _ = sender.send(f());
+        })?;
+        Ok(receiver)
+    }
+
+    /// Returns the number of worker threads the pool is running, or is
+    /// converging on after a resize.
+    pub fn worker_count(&self) -> usize {
+        self.workers.size.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of workers currently running a job.
+    pub fn active_count(&self) -> usize {
+        self.stats.busy.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of jobs waiting for a free worker.
+    pub fn queued_jobs(&self) -> usize {
+        self.stats.queued.load(Ordering::SeqCst)
+    }
+
+    /// Returns the pool's current activity counters.
+    pub fn metrics_snapshot(&self) -> PoolMetrics {
+        let waits = self.wait_time_histogram();
+        PoolMetrics {
+            size: self.worker_count(),
+            busy: self.active_count(),
+            queued: self.queued_jobs(),
+            completed: *self.stats.completed.lock().expect("Mutex poisoned"),
+            panics: self.stats.panics.load(Ordering::SeqCst),
+            dropped_expired: self.stats.dropped_expired.load(Ordering::SeqCst),
+            wait_p50: waits.percentile(0.5),
+            wait_p90: waits.percentile(0.9),
+            wait_p99: waits.percentile(0.99),
+        }
+    }
+
+    /// Returns the most recent job panics, oldest first.
+    ///
+    /// Only the last 32 are kept; `metrics_snapshot` counts them all.
+    pub fn recent_panics(&self) -> Vec<PanicRecord> {
+        let recent = self.stats.recent_panics.lock().expect("Mutex poisoned");
+        recent.iter().cloned().collect()
+    }
+
+    /// Returns a snapshot of how long jobs have waited in the queue.
+    pub fn wait_time_histogram(&self) -> WaitTimeHistogram {
+        WaitTimeHistogram {
+            bounds: WAIT_BUCKET_BOUNDS.to_vec(),
+            counts: self
+                .stats
+                .wait_buckets
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+
+    /// Blocks until at least `count` jobs have finished since the pool was
+    /// created, or `timeout` elapses.
+    ///
+    /// Returns `true` if the count was reached.
+    pub fn wait_for_completed(&self, count: usize, timeout: Duration) -> bool {
+        let completed = self.stats.completed.lock().expect("Mutex poisoned");
+        let (completed, _) = self
+            .stats
+            .completed_changed
+            .wait_timeout_while(completed, timeout, |completed| *completed < count)
+            .expect("Mutex poisoned");
+        *completed >= count
+    }
+
+    /// Replaces the workers with a fresh set of `new_size` threads.
+    ///
+    /// Jobs already queued are run to completion by the old workers before
+    /// they are joined; the queue options and statistics carry over. Taking
+    /// `&mut self` means no new jobs can arrive while this happens.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolError::CreationError` if `new_size` is 0, in which case
+    /// the current pool is left untouched.
+    pub fn drain_and_replace(&mut self, new_size: usize) -> Result<(), PoolError> {
+        let settings = ThreadPoolBuilder {
+            size: new_size,
+            ..self.settings.clone()
+        };
+        let replacement = settings.spawn(Arc::clone(&self.stats))?;
+
+        // Timers hold a sender for the old queue, so they must stop before
+        // the old workers can be joined; they restart on the new pool.
+        let mut schedules = std::mem::take(self.schedules.get_mut().expect("Mutex poisoned"));
+        for schedule in &mut schedules {
+            schedule.stop();
+        }
+        let autotune = self.stop_autotune();
+        let mut old = std::mem::replace(self, replacement);
+        old.shutdown();
+        for schedule in &mut schedules {
+            self.start_timer(schedule);
+        }
+        *self.schedules.get_mut().expect("Mutex poisoned") = schedules;
+        if let Some(limits) = autotune {
+            self.enable_autotune(limits.min, limits.max, limits.target_queue_depth)?;
+        }
+        Ok(())
+    }
+
+    /// Changes the number of workers to `size`.
+    ///
+    /// New workers start right away. Surplus workers retire once the jobs
+    /// queued ahead of this call have been picked up, in either
+    /// `QueueOrder`, and are joined lazily by later resizes or `shutdown`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolError::CreationError` if `size` is 0 and
+    /// `PoolError::SendError` if the pool has shut down.
+    pub fn set_size(&self, size: usize) -> Result<(), PoolError> {
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or_else(|| PoolError::SendError("ThreadPool sender is missing".into()))?;
+        self.workers.resize(sender, size)
+    }
+
+    /// Starts a background controller that keeps the pool between `min`
+    /// and `max` workers based on queue depth.
+    ///
+    /// Every 50ms it samples `queued_jobs`. After two samples in a row
+    /// above `target_queue_depth` it adds a worker; after ten samples in a
+    /// row with an empty queue and an idle worker (half a second) it
+    /// retires one. The pool is first clamped into range. Calling this
+    /// again replaces the earlier limits, and the controller survives
+    /// `drain_and_replace`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolError::CreationError` if `min` is 0 or greater than
+    /// `max`, and `PoolError::SendError` if the pool has shut down.
+    pub fn enable_autotune(
+        &self,
+        min: usize,
+        max: usize,
+        target_queue_depth: usize,
+    ) -> Result<(), PoolError> {
+        if min == 0 || min > max {
+            return Err(PoolError::CreationError(format!(
+                "Invalid autotune range {min}..={max}"
+            )));
+        }
+        let sender = self
+            .sender
+            .clone()
+            .ok_or_else(|| PoolError::SendError("ThreadPool sender is missing".into()))?;
+
+        self.stop_autotune();
+        let size = self.workers.size.load(Ordering::SeqCst);
+        self.workers.resize(&sender, size.clamp(min, max))?;
+
+        let limits = AutotuneLimits {
+            min,
+            max,
+            target_queue_depth,
+        };
+        let state = Arc::new(ScheduleState::default());
+        let thread = {
+            let workers = Arc::clone(&self.workers);
+            let state = Arc::clone(&state);
+            thread::spawn(move || autotune(&workers, &sender, limits, &state))
+        };
+        *self.autotune.lock().expect("Mutex poisoned") = Some(Autotune {
+            limits,
+            state,
+            thread: Some(thread),
+        });
+        Ok(())
+    }
+
+    /// Stops the controller started by `enable_autotune`, leaving the pool
+    /// at its current size.
+    pub fn disable_autotune(&self) {
+        self.stop_autotune();
+    }
+
+    /// Stops the autotuner and returns the limits it was running with.
+    fn stop_autotune(&self) -> Option<AutotuneLimits> {
+        let mut autotune = self.autotune.lock().expect("Mutex poisoned").take()?;
+        autotune.state.lock().stopped = true;
+        autotune.state.changed.notify_all();
+        if let Some(thread) = autotune.thread.take() {
+            let _ = thread.join();
+        }
+        Some(autotune.limits)
+    }
+
+    /// Runs `f` on the pool every `interval` until the returned handle is
+    /// cancelled or the pool shuts down.
+    ///
+    /// A tick is skipped while the previous run is still queued or running,
+    /// so a slow job never overlaps itself; use `schedule_every_overlapping`
+    /// to allow that. Ticks are also skipped while a bounded queue is full.
+    /// The schedule survives `drain_and_replace`. `interval` is measured on
+    /// the pool's clock.
+    pub fn schedule_every<F>(&self, interval: Duration, f: F) -> ScheduleHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.schedule(interval, false, Arc::new(f))
+    }
+
+    /// Like `schedule_every`, but submits `f` on every tick even if earlier
+    /// runs have not finished.
+    pub fn schedule_every_overlapping<F>(&self, interval: Duration, f: F) -> ScheduleHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.schedule(interval, true, Arc::new(f))
+    }
+
+    fn schedule(
+        &self,
+        interval: Duration,
+        overlap: bool,
+        task: Arc<dyn Fn() + Send + Sync>,
+    ) -> ScheduleHandle {
+        let mut schedule = Schedule {
+            interval,
+            overlap,
+            task,
+            running: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(ScheduleState::default()),
+            timer: None,
+        };
+        self.start_timer(&mut schedule);
+        let handle = ScheduleHandle {
+            state: Arc::clone(&schedule.state),
+        };
+
+        let mut schedules = self.schedules.lock().expect("Mutex poisoned");
+        schedules.retain(|schedule| !schedule.state.lock().cancelled);
+        schedules.push(schedule);
+        handle
+    }
+
+    /// Spawns the thread that submits `schedule`'s job on each tick.
+    fn start_timer(&self, schedule: &mut Schedule) {
+        let Some(sender) = self.sender.clone() else {
+            return;
+        };
+        let stats = Arc::clone(&self.stats);
+        let (interval, overlap) = (schedule.interval, schedule.overlap);
+        let task = Arc::clone(&schedule.task);
+        let running = Arc::clone(&schedule.running);
+        let state = Arc::clone(&schedule.state);
+        let clock = Arc::clone(&self.workers.clock);
+
+        schedule.timer = Some(thread::spawn(move || {
+            let mut next_tick = clock.now() + interval;
+            loop {
+                let left = next_tick.saturating_duration_since(clock.now());
+                let (flags, _) = state
+                    .changed
+                    .wait_timeout_while(state.lock(), left.min(CLOCK_CHECK_INTERVAL), |flags| {
+                        !flags.cancelled && !flags.stopped
+                    })
+                    .expect("Mutex poisoned");
+                if flags.cancelled || flags.stopped {
+                    break;
+                }
+                drop(flags);
+                if clock.now() < next_tick {
+                    continue;
+                }
+                next_tick = clock.now() + interval;
+
+                if !overlap && running.swap(true, Ordering::SeqCst) {
+                    continue;
+                }
+                let task = Arc::clone(&task);
+                let guard = RunningGuard(Arc::clone(&running));
+                let job = QueuedJob::new(Box::new(move || {
+                    let _guard = guard;
+                    task();
+                }));
+
+                stats.queued.fetch_add(1, Ordering::SeqCst);
+                // Never block the timer; a full queue skips this tick.
+                if sender.try_send(job).is_err() {
+                    stats.queued.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        }));
+    }
+
+    /// Stops the pool without running the jobs still queued, and returns
+    /// how many were discarded.
+    ///
+    /// Unlike `shutdown`, which lets the workers drain the queue first,
+    /// this only waits for the jobs already running. Discarded jobs are
+    /// dropped unrun, so their `JobHandle`s report `PoolError::JobFailed`.
+    pub fn shutdown_now(&mut self) -> usize {
+        self.stats.abandoning.store(true, Ordering::SeqCst);
+        self.shutdown();
+        self.stats.abandoning.store(false, Ordering::SeqCst);
+        self.stats.abandoned.swap(0, Ordering::SeqCst)
+    }
+
+    /// Stops accepting jobs, lets queued jobs finish, and joins every worker.
+    ///
+    /// Idle workers are parked on the job queue's condition variable.
+    /// Dropping the last sender is what wakes them: it closes the queue and
+    /// wakes every parked worker, and each exits once no jobs are left.
+    ///
+    /// Calling this more than once is a no-op. Dropping the pool does the
+    /// same, but waits only `ThreadPoolBuilder::drop_timeout` per worker.
+    pub fn shutdown(&mut self) {
+        self.stop(None);
+    }
+
+    /// Shuts down as `shutdown` does, detaching any worker that is still
+    /// running after `budget`.
+    fn stop(&mut self, budget: Option<Duration>) {
+        // Timers and the autotuner hold senders of their own; stop them so
+        // the workers see the queue close.
+        for mut schedule in self.schedules.get_mut().expect("Mutex poisoned").drain(..) {
+            schedule.stop();
+        }
+        self.stop_autotune();
+        drop(self.sender.take());
+
+        // A worker that panics while the queue drains adds its replacement
+        // before exiting, so keep joining until no workers are left.
+        loop {
+            let threads =
+                std::mem::take(&mut *self.workers.threads.lock().expect("Mutex poisoned"));
+            if threads.is_empty() {
+                break;
+            }
+            for worker in threads {
+                let Some(thread) = worker.thread else {
+                    continue;
+                };
+                if let Some(budget) = budget
+                    && !finishes_within(&thread, budget)
+                {
+                    // Dropping the handle detaches the thread.
+                    warning!(
+                        "Worker {} still running after {:?}; detaching it",
+                        worker.id,
+                        budget
+                    );
+                    continue;
+                }
+                // Job panics are caught, so a worker only fails to join
+                // if the pool itself is broken; panicking here could
+                // abort during unwinding.
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// The worker threads of a pool, shared with the autotuner so it can
+/// resize them.
+struct WorkerSet {
+    queue: Arc<JobQueue>,
+    /// Every worker not yet joined, including retired ones that may still
+    /// be exiting.
+    threads: Mutex<Vec<Worker>>,
+    /// Workers that have not been told to retire.
+    size: AtomicUsize,
+    next_id: AtomicUsize,
+    stats: Arc<PoolStats>,
+    fair: bool,
+    pin_to_cores: bool,
+    panic_policy: PanicPolicy,
+    after_each: Option<AfterEach>,
+    idle_timeout: Option<Duration>,
+    clock: Arc<dyn Clock>,
+}
+
+impl WorkerSet {
+    /// Spawns or retires workers until `size` remain.
+    fn resize(self: &Arc<Self>, sender: &JobSender, size: usize) -> Result<(), PoolError> {
+        if size == 0 {
+            return Err(PoolError::CreationError(
+                "Pool size must be greater than zero".into(),
+            ));
+        }
+
+        // Holding `threads` until `size` is stored keeps idle and panicked
+        // workers from counting themselves out in between.
+        let mut threads = self.threads.lock().expect("Mutex poisoned");
+        // Join the workers that already retired.
+        threads.retain_mut(|worker| match worker.thread.take() {
+            Some(thread) if thread.is_finished() => {
+                let _ = thread.join();
+                false
+            }
+            thread => {
+                worker.thread = thread;
+                true
+            }
+        });
+
+        let current = self.size.load(Ordering::SeqCst);
+        for _ in current..size {
+            threads.push(Worker::new(
+                self.next_id.fetch_add(1, Ordering::SeqCst),
+                self,
+            ));
+        }
+        self.size.store(size, Ordering::SeqCst);
+        // Sending can block on a full queue. Do it without the lock, which a
+        // worker whose job panicked needs before it can exit.
+        drop(threads);
+        for _ in size..current {
+            sender.send_last(QueuedJob::retire());
+        }
+        Ok(())
+    }
+
+    /// Counts out a worker that timed out idle, unless it is the last one.
+    /// Returns whether it may exit.
+    ///
+    /// Takes the `threads` lock, as `resize` does, so a resize in progress
+    /// cannot overwrite the decrement and retire workers that are gone.
+    fn retire_idle(&self) -> bool {
+        let _threads = self.threads.lock().expect("Mutex poisoned");
+        let size = self.size.load(Ordering::SeqCst);
+        if size <= 1 {
+            return false;
+        }
+        self.size.store(size - 1, Ordering::SeqCst);
+        true
+    }
+
+    /// Applies the panic policy for worker `id`, whose job just panicked
+    /// and which is about to exit.
+    fn worker_panicked(self: &Arc<Self>, id: usize) {
+        match self.panic_policy {
+            PanicPolicy::Restart => {
+                let replacement = Worker::new(id, self);
+                self.threads
+                    .lock()
+                    .expect("Mutex poisoned")
+                    .push(replacement);
+            }
+            PanicPolicy::Ignore => {
+                let _threads = self.threads.lock().expect("Mutex poisoned");
+                let size = self.size.load(Ordering::SeqCst);
+                self.size.store(size.saturating_sub(1), Ordering::SeqCst);
+            }
+            PanicPolicy::AbortProcess => {
+                error!("Worker {} panicked; aborting the process", id);
+                std::process::abort();
+            }
+        }
+    }
+}
+
+/// The jobs waiting behind each busy key of one `execute_ordered` key type.
+struct KeyedChains<K> {
+    /// Keys with a job running; a key's chain holds the jobs sent after it.
+    /// Lock this before any chain.
+    active: Mutex<HashMap<K, Arc<Mutex<VecDeque<Job>>>>>,
+}
+
+impl<K> Default for KeyedChains<K> {
+    fn default() -> KeyedChains<K> {
+        KeyedChains {
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Hash + Eq> KeyedChains<K> {
+    /// Runs `chain`'s jobs in order until it is empty, then retires its key.
+    fn run(&self, chain: &Mutex<VecDeque<Job>>) {
+        let mut first_panic = None;
+        loop {
+            let next = chain.lock().expect("Mutex poisoned").pop_front();
+            let job = match next {
+                Some(job) => job,
+                None => {
+                    // Recheck under the map lock, which senders hold while
+                    // appending, so no job slips in after the key is gone.
+                    let mut active = self.active.lock().expect("Mutex poisoned");
+                    match chain.lock().expect("Mutex poisoned").pop_front() {
+                        Some(job) => job,
+                        None => {
+                            active.retain(|_, other| !ptr::eq(Arc::as_ptr(other), chain));
+                            break;
+                        }
+                    }
+                }
+            };
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                first_panic.get_or_insert(payload);
+            }
+        }
+        if let Some(payload) = first_panic {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn remove(&self, chain: &Arc<Mutex<VecDeque<Job>>>) {
+        let mut active = self.active.lock().expect("Mutex poisoned");
+        active.retain(|_, other| !Arc::ptr_eq(other, chain));
+    }
+}
+
+/// Bounds given to `ThreadPool::enable_autotune`.
+#[derive(Clone, Copy)]
+struct AutotuneLimits {
+    min: usize,
+    max: usize,
+    target_queue_depth: usize,
+}
+
+/// A running autotuner and what it needs to be stopped.
+struct Autotune {
+    limits: AutotuneLimits,
+    state: Arc<ScheduleState>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// The autotuner's loop: samples the queue until `state` is stopped.
+fn autotune(
+    workers: &Arc<WorkerSet>,
+    sender: &JobSender,
+    limits: AutotuneLimits,
+    state: &ScheduleState,
+) {
+    let (mut above, mut idle) = (0, 0);
+    loop {
+        let (flags, _) = state
+            .changed
+            .wait_timeout_while(state.lock(), AUTOTUNE_INTERVAL, |flags| !flags.stopped)
+            .expect("Mutex poisoned");
+        if flags.stopped {
+            break;
+        }
+        drop(flags);
+
+        let size = workers.size.load(Ordering::SeqCst);
+        let queued = workers.stats.queued.load(Ordering::SeqCst);
+        let busy = workers.stats.busy.load(Ordering::SeqCst);
+        if queued > limits.target_queue_depth {
+            (above, idle) = (above + 1, 0);
+        } else if queued == 0 && busy < size {
+            (above, idle) = (0, idle + 1);
+        } else {
+            (above, idle) = (0, 0);
+        }
+
+        let resized = if above >= AUTOTUNE_GROW_AFTER && size < limits.max {
+            above = 0;
+            workers.resize(sender, size + 1)
+        } else if idle >= AUTOTUNE_SHRINK_AFTER && size > limits.min {
+            idle = 0;
+            workers.resize(sender, size - 1)
+        } else {
+            Ok(())
+        };
+        if resized.is_err() {
+            break;
+        }
+    }
+}
+
+/// A recurring job and the timer thread that submits it.
+struct Schedule {
+    interval: Duration,
+    overlap: bool,
+    task: Arc<dyn Fn() + Send + Sync>,
+    /// Set while a run is queued or executing, for skipping overlaps.
+    running: Arc<AtomicBool>,
+    state: Arc<ScheduleState>,
+    timer: Option<thread::JoinHandle<()>>,
+}
+
+impl Schedule {
+    /// Stops the timer thread and waits for it, leaving the schedule ready
+    /// to be started again.
+    fn stop(&mut self) {
+        self.state.lock().stopped = true;
+        self.state.changed.notify_all();
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.join();
+        }
+        self.state.lock().stopped = false;
+    }
+}
+
+#[derive(Default)]
+struct ScheduleFlags {
+    /// Set by `ScheduleHandle::cancel`; permanent.
+    cancelled: bool,
+    /// Set by the pool to stop the timer thread, possibly to restart it.
+    stopped: bool,
+}
+
+/// Wakes a schedule's timer thread, or the autotuner, early when its flags
+/// change.
+#[derive(Default)]
+struct ScheduleState {
+    flags: Mutex<ScheduleFlags>,
+    changed: Condvar,
+}
+
+impl ScheduleState {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ScheduleFlags> {
+        self.flags.lock().expect("Mutex poisoned")
+    }
+}
+
+/// Clears a schedule's running flag when its run ends, even by panicking.
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Controls a recurring job started with `ThreadPool::schedule_every`.
+pub struct ScheduleHandle {
+    state: Arc<ScheduleState>,
+}
+
+impl ScheduleHandle {
+    /// Stops submitting the job. A run already queued or executing still
+    /// finishes.
+    pub fn cancel(&self) {
+        self.state.lock().cancelled = true;
+        self.state.changed.notify_all();
+    }
+}
+
+/// A handle to the return value of a job sent with `ThreadPool::submit`.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<T>,
+    result: RefCell<Option<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Returns `true` once the job has finished, without blocking.
+    ///
+    /// A job that panicked also counts as finished. Any value received here
+    /// is kept so that `join` still returns it.
+    pub fn is_finished(&self) -> bool {
+        if self.result.borrow().is_some() {
+            return true;
+        }
+
+        match self.receiver.try_recv() {
+            Ok(value) => {
+                *self.result.borrow_mut() = Some(value);
+                true
+            }
+            Err(mpsc::TryRecvError::Empty) => false,
+            Err(mpsc::TryRecvError::Disconnected) => true,
+        }
+    }
+
+    /// Blocks until the job finishes and returns its value.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolError::JobFailed` if the job panicked or was dropped
+    /// before it could run.
+    pub fn join(self) -> Result<T, PoolError> {
+        if let Some(value) = self.result.into_inner() {
+            return Ok(value);
+        }
+
+        self.receiver
+            .recv()
+            .map_err(|_| PoolError::JobFailed("job ended without producing a result".into()))
+    }
+}
+
+fn parallelism_or_default(detected: io::Result<NonZeroUsize>) -> usize {
+    detected.map_or(DEFAULT_POOL_SIZE, NonZeroUsize::get)
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.stop(Some(self.settings.drop_timeout));
+    }
+}
+
+/// Waits up to `budget` for `thread` to exit, returning whether it did.
+fn finishes_within(thread: &thread::JoinHandle<()>, budget: Duration) -> bool {
+    let deadline = Instant::now() + budget;
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(JOIN_POLL_INTERVAL);
+    }
+    true
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, workers: &Arc<WorkerSet>) -> Worker {
+        let queue = Arc::clone(&workers.queue);
+        let stats = Arc::clone(&workers.stats);
+        let fair = workers.fair;
+        let pin_to_cores = workers.pin_to_cores;
+        let after_each = workers.after_each.clone();
+        let idle_timeout = workers.idle_timeout;
+        let clock = Arc::clone(&workers.clock);
+        // Read before spawning, so a slow thread start is not idle time.
+        let mut idle_since = clock.now();
+        // A worker must not keep its own set alive, or the set would never
+        // drop.
+        let workers = Arc::downgrade(workers);
+        let thread = thread::spawn(move || {
+            if pin_to_cores {
+                affinity::pin_current_thread(id);
+            }
+            loop {
+                let wait = idle_timeout.map(|timeout| {
+                    let idle = clock.now().saturating_duration_since(idle_since);
+                    timeout.saturating_sub(idle).min(CLOCK_CHECK_INTERVAL)
+                });
+                // `pop` releases the queue's lock before returning. Running
+                // a job under it would make every other worker wait for it,
+                // serializing the pool.
+                let message = queue.pop(wait);
+
+                let message = match message {
+                    Popped::Job(queued) => Some(queued),
+                    Popped::Closed => None,
+                    Popped::Idle => {
+                        let timed_out = idle_timeout.is_some_and(|timeout| {
+                            clock.now().saturating_duration_since(idle_since) >= timeout
+                        });
+                        if timed_out && workers.upgrade().is_some_and(|set| set.retire_idle()) {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                match message {
+                    Some(queued) if queued.retire => break,
+                    Some(queued) if stats.abandoning.load(Ordering::SeqCst) => {
+                        stats.queued.fetch_sub(1, Ordering::SeqCst);
+                        stats.abandoned.fetch_add(1, Ordering::SeqCst);
+                        drop(queued);
+                    }
+                    Some(queued) if queued.deadline.is_some_and(|d| clock.now() > d) => {
+                        stats.queued.fetch_sub(1, Ordering::SeqCst);
+                        stats.dropped_expired.fetch_add(1, Ordering::SeqCst);
+                        drop(queued);
+                    }
+                    Some(queued) => {
+                        stats.queued.fetch_sub(1, Ordering::SeqCst);
+                        stats.record_wait(queued.enqueued.elapsed());
+                        stats.busy.fetch_add(1, Ordering::SeqCst);
+                        // Catching the panic lets the pool's panic policy
+                        // decide what happens next; the panic hook has
+                        // already reported it.
+                        let started = Instant::now();
+                        let outcome = panic::catch_unwind(AssertUnwindSafe(queued.job));
+                        if let Some(AfterEach(hook)) = &after_each {
+                            hook(id, started.elapsed());
+                        }
+                        stats.busy.fetch_sub(1, Ordering::SeqCst);
+                        match outcome {
+                            Ok(()) => stats.record_completed(),
+                            Err(payload) => {
+                                stats.record_panic(id, payload.as_ref());
+                                if let Some(workers) = workers.upgrade() {
+                                    workers.worker_panicked(id);
+                                }
+                                break;
+                            }
+                        }
+                    }
+                    None => break,
+                }
+
+                if fair {
+                    #[cfg(test)]
+                    stats.fair_yields.fetch_add(1, Ordering::SeqCst);
+                    thread::yield_now();
+                }
+                idle_since = clock.now();
+            }
+        });
+
+        Worker {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// CPU pinning for `ThreadPoolBuilder::pin_to_cores`.
+#[cfg(target_os = "linux")]
+mod affinity {
+    use std::os::raw::c_int;
+
+    /// glibc's `cpu_set_t`: a bit per CPU, 1024 CPUs.
+    pub(crate) type CpuSet = [u64; 16];
+
+    unsafe extern "C" {
+        fn sched_setaffinity(pid: c_int, size: usize, mask: *const CpuSet) -> c_int;
+        fn sched_getaffinity(pid: c_int, size: usize, mask: *mut CpuSet) -> c_int;
+    }
+
+    /// Returns the CPUs the calling thread may run on, in ascending order.
+    pub(crate) fn allowed_cpus() -> Vec<usize> {
+        let mut mask: CpuSet = [0; 16];
+        // SAFETY: `mask` is a writable `cpu_set_t`-sized buffer, and pid 0
+        // means the calling thread.
+        if unsafe { sched_getaffinity(0, size_of::<CpuSet>(), &mut mask) } != 0 {
+            return Vec::new();
+        }
+        (0..mask.len() * 64)
+            .filter(|&cpu| mask[cpu / 64] & (1 << (cpu % 64)) != 0)
+            .collect()
+    }
+
+    /// Binds the calling thread to the `id % n`-th of its `n` allowed CPUs.
+    pub(crate) fn pin_current_thread(id: usize) {
+        let allowed = allowed_cpus();
+        if allowed.is_empty() {
+            return;
+        }
+        let cpu = allowed[id % allowed.len()];
+        let mut mask: CpuSet = [0; 16];
+        mask[cpu / 64] |= 1 << (cpu % 64);
+        // SAFETY: `mask` is an initialized `cpu_set_t`-sized buffer, and
+        // pid 0 means the calling thread. A failure leaves it unpinned.
+        let _ = unsafe { sched_setaffinity(0, size_of::<CpuSet>(), &mask) };
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod affinity {
+    pub(crate) fn pin_current_thread(_id: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Polls `condition` for up to five seconds; returns whether it held.
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+
+    #[test]
+    fn test_pool_creation() {
+        let pool = ThreadPool::build(4);
+        assert!(pool.is_ok());
+    }
+
+    #[test]
+    fn test_zero_size_pool_fails() {
+        let pool = ThreadPool::build(0);
+        assert!(pool.is_err());
+    }
+
+    #[test]
+    fn test_execution() {
+        let pool = ThreadPool::build(2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..10 {
+            let c = Arc::clone(&counter);
+            pool.execute(move || {
+                c.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+
+        drop(pool);
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_shutdown_wakes_idle_workers() {
+        let mut pool = ThreadPool::build(8).unwrap();
+        thread::sleep(std::time::Duration::from_millis(20));
+
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            pool.shutdown();
+            done_tx
+                .send(pool.workers.threads.lock().unwrap().len())
+                .unwrap();
+        });
+
+        let remaining = done_rx
+            .recv_timeout(std::time::Duration::from_secs(2))
+            .expect("idle workers did not join in time");
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn test_jobs_run_outside_the_queue_lock() {
+        let mut pool = ThreadPool::build(2).unwrap();
+        // Each job waits for the other, so both must be running at once; a
+        // job holding the queue's lock would keep the second from starting.
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let (done_tx, done_rx) = mpsc::channel();
+        for _ in 0..2 {
+            let barrier = Arc::clone(&barrier);
+            let done_tx = done_tx.clone();
+            pool.execute(move || {
+                barrier.wait();
+                done_tx.send(()).unwrap();
+            })
+            .unwrap();
+        }
+        for _ in 0..2 {
+            done_rx
+                .recv_timeout(Duration::from_secs(2))
+                .expect("jobs were serialized by the queue lock");
+        }
+
+        // Workers leaving on a closed queue release the lock as well.
+        pool.shutdown();
+        assert!(pool.workers.queue.state.try_lock().is_ok());
+    }
+
+    #[test]
+    fn test_execute_after_shutdown_fails() {
+        let mut pool = ThreadPool::build(2).unwrap();
+        pool.shutdown();
+        pool.shutdown();
+        assert!(matches!(pool.execute(|| {}), Err(PoolError::SendError(_))));
+    }
+
+    #[test]
+    fn test_job_handle_is_finished() {
+        let pool = ThreadPool::build(1).unwrap();
+        let handle = pool
+            .submit(|| {
+                thread::sleep(std::time::Duration::from_millis(100));
+                42
+            })
+            .unwrap();
+
+        assert!(!handle.is_finished());
+        thread::sleep(std::time::Duration::from_millis(200));
+        assert!(handle.is_finished());
+        assert!(handle.is_finished());
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_job_handle_join_blocks() {
+        let pool = ThreadPool::build(2).unwrap();
+        let handle = pool.submit(|| "done").unwrap();
+        assert_eq!(handle.join().unwrap(), "done");
+    }
+
+    #[test]
+    fn test_fair_workers_yield_after_each_job() {
+        use std::collections::HashMap;
+
+        let pool = ThreadPool::builder(4).fair(true).build().unwrap();
+        let stats = Arc::clone(&pool.stats);
+        let counts = Arc::new(Mutex::new(HashMap::new()));
+
+        for _ in 0..4000 {
+            let counts = Arc::clone(&counts);
+            pool.execute(move || {
+                *counts
+                    .lock()
+                    .unwrap()
+                    .entry(thread::current().id())
+                    .or_insert(0usize) += 1;
+            })
+            .unwrap();
+        }
+        drop(pool);
+
+        // Which worker wins each job is up to the scheduler; what is
+        // certain is that every job ran and was followed by a yield.
+        let counts = counts.lock().unwrap();
+        assert!((1..=4).contains(&counts.len()), "{counts:?}");
+        assert_eq!(counts.values().sum::<usize>(), 4000);
+        assert_eq!(stats.fair_yields.load(Ordering::SeqCst), 4000);
+    }
+
+    #[test]
+    fn test_wait_time_histogram() {
+        let pool = ThreadPool::build(1).unwrap();
+        pool.execute(|| thread::sleep(Duration::from_millis(60)))
+            .unwrap();
+        let handles: Vec<_> = (0..3).map(|_| pool.submit(|| ()).unwrap()).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let histogram = pool.wait_time_histogram();
+        assert_eq!(histogram.total(), 4);
+        assert_eq!(histogram.counts.len(), histogram.bounds.len() + 1);
+        // The three jobs queued behind the sleeper waited at least 10ms.
+        let waited_long: u64 = histogram.counts[2..].iter().sum();
+        assert!(waited_long >= 3, "{histogram:?}");
+    }
+
+    #[test]
+    fn test_spawn_with_result() {
+        let pool = ThreadPool::build(2).unwrap();
+        let receiver = pool
+            .spawn_with_result(|| (1..=10).product::<u64>())
+            .unwrap();
+        assert_eq!(receiver.recv().unwrap(), 3_628_800);
+
+        let receiver = pool
+            .spawn_with_result(|| -> u64 { panic!("job failed") })
+            .unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_queued_jobs() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        let handles: Vec<_> = (0..3).map(|_| pool.submit(|| ()).unwrap()).collect();
+        assert!(pool.queued_jobs() >= 3);
+
+        release.send(()).unwrap();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(pool.queued_jobs(), 0);
+    }
+
+    #[test]
+    fn test_worker_and_active_counts() {
+        let pool = ThreadPool::build(3).unwrap();
+        assert_eq!(pool.worker_count(), 3);
+        assert_eq!(pool.active_count(), 0);
+
+        let (started_tx, started) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        started.recv().unwrap();
+        assert_eq!(pool.active_count(), 1);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_completed() {
+        let pool = ThreadPool::build(2).unwrap();
+        for _ in 0..5 {
+            pool.execute(|| thread::sleep(Duration::from_millis(5)))
+                .unwrap();
+        }
+
+        assert!(pool.wait_for_completed(5, Duration::from_secs(5)));
+        assert!(!pool.wait_for_completed(6, Duration::from_millis(20)));
+        pool.execute(|| ()).unwrap();
+        assert!(pool.wait_for_completed(6, Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_execute_fallible_collects_errors() {
+        let mut pool = ThreadPool::build(1).unwrap();
+        for i in 0..6u32 {
+            pool.execute_fallible(move || if i % 2 == 0 { Ok(()) } else { Err(i) })
+                .unwrap();
+        }
+        pool.execute_fallible(|| Err("other type")).unwrap();
+
+        pool.shutdown();
+        assert_eq!(pool.take_errors::<u32>(), vec![1, 3, 5]);
+        assert!(pool.take_errors::<u32>().is_empty());
+        assert_eq!(pool.take_errors::<&str>(), vec!["other type"]);
+    }
+
+    #[test]
+    fn test_execute_many() {
+        let mut pool = ThreadPool::build(4).unwrap();
+        let counter = Arc::new(AtomicU64::new(0));
+        let job_counter = Arc::clone(&counter);
+        pool.execute_many(1000, move || {
+            job_counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        pool.shutdown();
+        assert_eq!(counter.load(Ordering::SeqCst), 1000);
+        assert!(pool.execute_many(1, || ()).is_err());
+    }
+
+    #[test]
+    fn test_par_for_each() {
+        let pool = ThreadPool::build(4).unwrap();
+        let total = Arc::new(AtomicU64::new(0));
+        let sum = Arc::clone(&total);
+        pool.par_for_each(1..=10_000u64, 256, move |n| {
+            sum.fetch_add(n, Ordering::SeqCst);
+        })
+        .unwrap();
+        assert_eq!(total.load(Ordering::SeqCst), 10_000 * 10_001 / 2);
+        // 40 jobs of 256 items, not one per item.
+        assert!(pool.wait_for_completed(40, Duration::from_secs(1)));
+        assert_eq!(pool.metrics_snapshot().completed, 40);
+
+        let result = pool.par_for_each(0..10, 0, |n| assert_ne!(n, 7));
+        assert!(matches!(result, Err(PoolError::JobFailed(_))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin_to_cores() {
+        let allowed = affinity::allowed_cpus();
+        let pool = ThreadPool::builder(2).pin_to_cores(true).build().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        // Hold both workers at once so each reports its own mask.
+        for _ in 0..2 {
+            let sender = sender.clone();
+            let barrier = Arc::clone(&barrier);
+            pool.execute(move || {
+                barrier.wait();
+                sender.send(affinity::allowed_cpus()).unwrap();
+            })
+            .unwrap();
+        }
+
+        let mut masks: Vec<Vec<usize>> = receiver.iter().take(2).collect();
+        masks.sort();
+        let mut expected = vec![vec![allowed[0]], vec![allowed[1 % allowed.len()]]];
+        expected.sort();
+        assert_eq!(masks, expected);
+    }
+
+    #[test]
+    fn test_shutdown_now_discards_queued_jobs() {
+        let mut pool = ThreadPool::build(1).unwrap();
+        let (started_tx, started) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        started.recv().unwrap();
+
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..99 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+
+        // Let the running job finish only once the queue is being discarded.
+        let stats = Arc::clone(&pool.stats);
+        let releaser = thread::spawn(move || {
+            assert!(wait_until(|| stats.abandoning.load(Ordering::SeqCst)));
+            release.send(()).unwrap();
+        });
+        let discarded = pool.shutdown_now();
+        releaser.join().unwrap();
+        assert_eq!(discarded, 99);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        assert_eq!(pool.queued_jobs(), 0);
+        assert!(pool.execute(|| ()).is_err());
+    }
+
+    #[test]
+    fn test_expired_deadline_job_is_dropped() {
+        let mut pool = ThreadPool::build(1).unwrap();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&ran);
+        pool.execute_with_deadline(Instant::now() + Duration::from_millis(20), move || {
+            flag.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+        let on_time = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&on_time);
+        pool.execute_with_deadline(Instant::now() + Duration::from_secs(60), move || {
+            flag.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+        release_tx.send(()).unwrap();
+        pool.shutdown();
+
+        assert!(!ran.load(Ordering::SeqCst));
+        assert!(on_time.load(Ordering::SeqCst));
+        let metrics = pool.metrics_snapshot();
+        assert_eq!(metrics.dropped_expired, 1);
+        assert_eq!(metrics.queued, 0);
+    }
+
+    #[test]
+    fn test_deadline_counts_down_on_the_pool_clock() {
+        let clock = Arc::new(MockClock::new());
+        let mut pool = ThreadPool::builder(1)
+            .clock(Arc::clone(&clock) as _)
+            .build()
+            .unwrap();
+        clock.advance(Duration::from_secs(3600));
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        for (left, bit) in [(120, 1), (30, 2)] {
+            let ran = Arc::clone(&ran);
+            pool.execute_with_deadline(Instant::now() + Duration::from_secs(left), move || {
+                ran.fetch_or(bit, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        clock.advance(Duration::from_secs(61));
+        release_tx.send(()).unwrap();
+        pool.shutdown();
+
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.metrics_snapshot().dropped_expired, 1);
+    }
+
+    #[test]
+    fn test_drop_detaches_stuck_worker() {
+        let pool = ThreadPool::builder(1)
+            .drop_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let (started_tx, started) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = blocked.recv_timeout(Duration::from_secs(10));
+        })
+        .unwrap();
+        started.recv().unwrap();
+
+        let dropping = Instant::now();
+        drop(pool);
+        assert!(dropping.elapsed() < Duration::from_secs(5));
+        assert!(
+            logging::tests::captured()
+                .iter()
+                .any(|(level, line)| *level == Level::Warn
+                    && line == "Worker 0 still running after 50ms; detaching it")
+        );
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_idle_workers_exit_when_clock_passes_timeout() {
+        let clock = Arc::new(MockClock::new());
+        let pool = ThreadPool::builder(3)
+            .idle_timeout(Duration::from_secs(60))
+            .clock(Arc::clone(&clock) as _)
+            .build()
+            .unwrap();
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(pool.worker_count(), 3);
+
+        clock.advance(Duration::from_secs(1));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.worker_count() > 1 && Instant::now() < deadline {
+            thread::yield_now();
+        }
+        assert_eq!(pool.worker_count(), 1);
+
+        // The last worker stays to run new jobs.
+        assert_eq!(pool.submit(|| 7).unwrap().join().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_idle_retirement_races_with_set_size() {
+        let pool = ThreadPool::builder(4)
+            .idle_timeout(Duration::ZERO)
+            .build()
+            .unwrap();
+        for round in 0..200 {
+            pool.set_size(if round % 2 == 0 { 1 } else { 4 }).unwrap();
+        }
+        pool.set_size(2).unwrap();
+
+        // Every retire job must have reached a live worker, leaving at least
+        // one to run these.
+        for _ in 0..10 {
+            let (tx, rx) = mpsc::channel();
+            pool.execute(move || tx.send(()).unwrap()).unwrap();
+            rx.recv_timeout(Duration::from_secs(5))
+                .expect("no live worker left");
+        }
+        assert!((1..=2).contains(&pool.worker_count()));
+    }
+
+    #[test]
+    fn test_drain_and_replace_keeps_queued_jobs() {
+        let mut pool = ThreadPool::build(2).unwrap();
+        let counter = Arc::new(AtomicU64::new(0));
+        for _ in 0..20 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(2));
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+
+        pool.drain_and_replace(3).unwrap();
+        assert_eq!(counter.load(Ordering::SeqCst), 20);
+        assert_eq!(pool.metrics_snapshot().size, 3);
+
+        pool.submit(|| ()).unwrap().join().unwrap();
+        assert!(pool.drain_and_replace(0).is_err());
+        assert_eq!(pool.metrics_snapshot().size, 3);
+    }
+
+    #[test]
+    fn test_execute_ordered_serializes_per_key() {
+        let mut pool = ThreadPool::build(4).unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for seq in 0..5 {
+            for key in ["a", "b"] {
+                let log = Arc::clone(&log);
+                pool.execute_ordered(key, move || {
+                    let started = Instant::now();
+                    thread::sleep(Duration::from_millis(20));
+                    log.lock()
+                        .unwrap()
+                        .push((key, seq, started, Instant::now()));
+                })
+                .unwrap();
+            }
+        }
+        // A panic inside a chain does not hold up the rest of it.
+        pool.execute_ordered("a", || panic!("job failed")).unwrap();
+        let log_a = Arc::clone(&log);
+        pool.execute_ordered("a", move || {
+            let now = Instant::now();
+            log_a.lock().unwrap().push(("a", 5, now, now));
+        })
+        .unwrap();
+        pool.shutdown();
+
+        let log = log.lock().unwrap();
+        let runs = |key| -> Vec<_> { log.iter().filter(|run| run.0 == key).collect() };
+        let (a, b) = (runs("a"), runs("b"));
+        assert_eq!(
+            a.iter().map(|run| run.1).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            b.iter().map(|run| run.1).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4]
+        );
+        for runs in [&a, &b] {
+            assert!(runs.windows(2).all(|pair| pair[0].3 <= pair[1].2));
+        }
+        // The two keys ran side by side.
+        assert!(a[0].2 < b[0].3 && b[0].2 < a[0].3);
+        assert_eq!(pool.metrics_snapshot().panics, 1);
+    }
+
+    #[test]
+    fn test_set_size() {
+        let pool = ThreadPool::build(1).unwrap();
+        pool.set_size(3).unwrap();
+        assert_eq!(pool.metrics_snapshot().size, 3);
+
+        // Three jobs that wait for each other need three workers.
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || barrier.wait()).unwrap()
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        pool.set_size(1).unwrap();
+        assert_eq!(pool.metrics_snapshot().size, 1);
+        assert_eq!(pool.submit(|| 7).unwrap().join().unwrap(), 7);
+        assert!(matches!(pool.set_size(0), Err(PoolError::CreationError(_))));
+    }
+
+    #[test]
+    fn test_autotune_follows_load() {
+        let pool = ThreadPool::build(1).unwrap();
+        assert!(pool.enable_autotune(2, 1, 0).is_err());
+        pool.enable_autotune(1, 4, 1).unwrap();
+
+        let jobs = 200;
+        for _ in 0..jobs {
+            pool.execute(|| thread::sleep(Duration::from_millis(20)))
+                .unwrap();
+        }
+        let size_within = |target: usize, timeout: Duration| {
+            let deadline = Instant::now() + timeout;
+            loop {
+                let size = pool.metrics_snapshot().size;
+                assert!((1..=4).contains(&size), "{size}");
+                if size == target || Instant::now() >= deadline {
+                    return size;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        assert_eq!(size_within(4, Duration::from_secs(5)), 4);
+        assert!(pool.wait_for_completed(jobs, Duration::from_secs(10)));
+        assert_eq!(size_within(1, Duration::from_secs(5)), 1);
+
+        pool.disable_autotune();
+        assert_eq!(pool.submit(|| 7).unwrap().join().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_schedule_every_until_cancelled() {
+        let pool = ThreadPool::build(2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let ticks = Arc::clone(&counter);
+        let handle = pool.schedule_every(Duration::from_millis(100), move || {
+            ticks.fetch_add(1, Ordering::SeqCst);
+        });
+
+        thread::sleep(Duration::from_millis(350));
+        handle.cancel();
+        thread::sleep(Duration::from_millis(50));
+        let fired = counter.load(Ordering::SeqCst);
+        assert!((2..=4).contains(&fired), "fired {fired} times");
+
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(counter.load(Ordering::SeqCst), fired);
+    }
+
+    #[test]
+    fn test_schedule_every_ticks_on_the_pool_clock() {
+        let clock = Arc::new(MockClock::new());
+        let pool = ThreadPool::builder(1)
+            .clock(Arc::clone(&clock) as _)
+            .build()
+            .unwrap();
+        let (tick_tx, tick_rx) = mpsc::channel();
+        let tick_tx = Mutex::new(tick_tx);
+        let handle = pool.schedule_every(Duration::from_secs(60), move || {
+            let _ = tick_tx.lock().unwrap().send(());
+        });
+
+        assert!(tick_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        clock.advance(Duration::from_secs(60));
+        assert!(tick_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        handle.cancel();
+    }
+
+    #[test]
+    fn test_slow_scheduled_job_does_not_overlap() {
+        let mut pool = ThreadPool::build(4).unwrap();
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (a, m, r) = (
+            Arc::clone(&active),
+            Arc::clone(&max_active),
+            Arc::clone(&runs),
+        );
+        let _handle = pool.schedule_every(Duration::from_millis(5), move || {
+            let now = a.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(30));
+            a.fetch_sub(1, Ordering::SeqCst);
+            r.fetch_add(1, Ordering::SeqCst);
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        pool.drain_and_replace(2).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        pool.shutdown();
+
+        assert_eq!(max_active.load(Ordering::SeqCst), 1);
+        assert!(runs.load(Ordering::SeqCst) >= 4);
+    }
+
+    #[test]
+    fn test_metrics_snapshot_json() {
+        let pool = ThreadPool::build(2).unwrap();
+        assert_eq!(
+            pool.metrics_snapshot().to_json(),
+            r#"{"size":2,"busy":0,"queued":0,"completed":0,"panics":0,"dropped_expired":0,"wait_ms":{"p50":null,"p90":null,"p99":null}}"#
+        );
+
+        for _ in 0..5 {
+            pool.execute(|| ()).unwrap();
+        }
+        pool.execute(|| panic!("job failed")).unwrap();
+        assert!(pool.wait_for_completed(5, Duration::from_secs(5)));
+        assert!(wait_until(|| pool.metrics_snapshot().panics > 0));
+
+        let metrics = pool.metrics_snapshot();
+        assert_eq!(metrics.completed, 5);
+        assert_eq!(metrics.panics, 1);
+        assert_eq!(metrics.busy, 0);
+        assert!(metrics.wait_p50.is_some());
+        assert!(metrics.wait_p50 <= metrics.wait_p99);
+
+        let json = metrics.to_json();
+        for key in [
+            "\"size\":2",
+            "\"completed\":5",
+            "\"panics\":1",
+            "\"wait_ms\":{\"p50\":",
+        ] {
+            assert!(json.contains(key), "{json}");
+        }
+    }
+
+    #[test]
+    fn test_recent_panics_are_recorded() {
+        let pool = ThreadPool::build(1).unwrap();
+        let before = SystemTime::now();
+        pool.execute(|| panic!("first")).unwrap();
+        pool.execute(|| panic!("second: {}", 2)).unwrap();
+
+        // The single worker is replaced after each panic, under the same
+        // index.
+        pool.submit(|| ()).unwrap().join().unwrap();
+        let panics = pool.recent_panics();
+        let messages: Vec<_> = panics.iter().map(|p| p.message.as_str()).collect();
+        assert_eq!(messages, ["first", "second: 2"]);
+        assert!(panics.iter().all(|p| p.worker == 0 && p.at >= before));
+    }
+
+    #[test]
+    fn test_restart_policy_replaces_panicked_worker() {
+        let pool = ThreadPool::builder(2)
+            .panic_policy(PanicPolicy::Restart)
+            .build()
+            .unwrap();
+        pool.submit(|| panic!("job failed"))
+            .unwrap()
+            .join()
+            .unwrap_err();
+
+        // Both jobs block until the other arrives, so they only finish if
+        // two workers are running.
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                })
+                .unwrap()
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(pool.metrics_snapshot().size, 2);
+    }
+
+    #[test]
+    fn test_blocked_shrink_does_not_hold_up_a_panicked_worker() {
+        let pool = ThreadPool::builder(3)
+            .queue_capacity(1)
+            .panic_policy(PanicPolicy::Ignore)
+            .build()
+            .unwrap();
+        let (started_tx, started) = mpsc::channel();
+        let (panic_tx, panic_rx) = mpsc::channel::<()>();
+        let release = Arc::new(std::sync::Barrier::new(3));
+        let started_panicking = started_tx.clone();
+        pool.execute(move || {
+            started_panicking.send(()).unwrap();
+            panic_rx.recv().unwrap();
+            panic!("job failed");
+        })
+        .unwrap();
+        for _ in 0..2 {
+            let started = started_tx.clone();
+            let release = Arc::clone(&release);
+            pool.execute(move || {
+                started.send(()).unwrap();
+                release.wait();
+            })
+            .unwrap();
+        }
+        for _ in 0..3 {
+            started.recv().unwrap();
+        }
+        pool.execute(|| {}).unwrap();
+
+        let (shrinking, counted_out) = thread::scope(|scope| {
+            // The queue is full, so the retire job waits for room.
+            let shrink = scope.spawn(|| pool.set_size(2));
+            let shrinking = wait_until(|| pool.metrics_snapshot().size == 2);
+            // The panicked worker counts itself out while the shrink is
+            // still blocked and every other worker is busy.
+            panic_tx.send(()).unwrap();
+            let counted_out = wait_until(|| pool.metrics_snapshot().size == 1);
+            release.wait();
+            shrink.join().unwrap().unwrap();
+            (shrinking, counted_out)
+        });
+        assert!(shrinking);
+        assert!(counted_out);
+    }
+
+    #[test]
+    fn test_ignore_policy_shrinks_pool() {
+        let pool = ThreadPool::builder(2)
+            .panic_policy(PanicPolicy::Ignore)
+            .build()
+            .unwrap();
+        pool.submit(|| panic!("job failed"))
+            .unwrap()
+            .join()
+            .unwrap_err();
+
+        // The handle reports the panic before the worker has exited.
+        assert!(wait_until(|| pool.metrics_snapshot().size == 1));
+        let results: Vec<_> = (0..4)
+            .map(|i| pool.submit(move || i * 2).unwrap())
+            .collect();
+        let results: Vec<_> = results.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn test_after_each_hook_runs_after_every_job() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&calls);
+        let mut pool = ThreadPool::build_with_hooks(1, move |worker, took| {
+            recorded.lock().unwrap().push((worker, took));
+        })
+        .unwrap();
+
+        pool.execute(|| thread::sleep(Duration::from_millis(20)))
+            .unwrap();
+        pool.execute(|| panic!("hooked")).unwrap();
+        pool.execute(|| ()).unwrap();
+        pool.shutdown();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.iter().all(|&(worker, _)| worker == 0));
+        assert!(calls[0].1 >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_available_parallelism_pool() {
+        let pool = ThreadPool::with_available_parallelism().unwrap();
+        assert!(pool.metrics_snapshot().size > 0);
+    }
+
+    #[test]
+    fn test_parallelism_fallback() {
+        let unavailable = Err(io::Error::new(io::ErrorKind::Unsupported, "unknown"));
+        assert_eq!(parallelism_or_default(unavailable), DEFAULT_POOL_SIZE);
+        assert_eq!(parallelism_or_default(Ok(NonZeroUsize::new(3).unwrap())), 3);
+    }
+
+    /// Builds a one-worker pool with a one-slot queue, then occupies the
+    /// worker and the slot. Sending on the returned channel releases the worker.
+    fn saturated_pool(policy: OverflowPolicy) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::builder(1)
+            .queue_capacity(1)
+            .overflow_policy(policy)
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        pool.execute(|| {}).unwrap();
+
+        (pool, release_tx)
+    }
+
+    #[test]
+    fn test_overflow_reject() {
+        let (pool, release) = saturated_pool(OverflowPolicy::Reject);
+        assert!(matches!(pool.execute(|| {}), Err(PoolError::QueueFull)));
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_overflow_caller_runs() {
+        let (pool, release) = saturated_pool(OverflowPolicy::CallerRuns);
+        let caller = thread::current().id();
+        let (ran_on_tx, ran_on_rx) = mpsc::channel();
+
+        pool.execute(move || ran_on_tx.send(thread::current().id()).unwrap())
+            .unwrap();
+        assert_eq!(ran_on_rx.try_recv().unwrap(), caller);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_try_execute_or_run_runs_inline_when_full() {
+        let (pool, release) = saturated_pool(OverflowPolicy::Reject);
+        let caller = thread::current().id();
+        let (ran_on_tx, ran_on_rx) = mpsc::channel();
+
+        let inline = pool
+            .try_execute_or_run(move || ran_on_tx.send(thread::current().id()).unwrap())
+            .unwrap();
+        assert!(inline);
+        assert_eq!(ran_on_rx.try_recv().unwrap(), caller);
+
+        release.send(()).unwrap();
+        assert!(pool.wait_for_completed(2, Duration::from_secs(5)));
+        assert!(!pool.try_execute_or_run(|| {}).unwrap());
+    }
+
+    #[test]
+    fn test_queue_order() {
+        for (order, expected) in [
+            (QueueOrder::Fifo, [0, 1, 2, 3, 4]),
+            (QueueOrder::Lifo, [4, 3, 2, 1, 0]),
+        ] {
+            let mut pool = ThreadPool::builder(1).queue_order(order).build().unwrap();
+            let (started_tx, started_rx) = mpsc::channel();
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            })
+            .unwrap();
+            started_rx.recv().unwrap();
+
+            // Queued behind the blocked job, so the order they run in is
+            // the queue's alone.
+            let ran = Arc::new(Mutex::new(Vec::new()));
+            for seq in 0..5 {
+                let ran = Arc::clone(&ran);
+                pool.execute(move || ran.lock().unwrap().push(seq)).unwrap();
+            }
+            release_tx.send(()).unwrap();
+            pool.shutdown();
+            assert_eq!(*ran.lock().unwrap(), expected, "{order:?}");
+        }
+    }
+
+    #[test]
+    fn test_lifo_shrink_retires_after_queued_jobs() {
+        let pool = ThreadPool::builder(2)
+            .queue_order(QueueOrder::Lifo)
+            .build()
+            .unwrap();
+        let (started_tx, started) = mpsc::channel();
+        let mut releases = Vec::new();
+        for _ in 0..2 {
+            let started = started_tx.clone();
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            releases.push(release_tx);
+            pool.execute(move || {
+                started.send(()).unwrap();
+                release_rx.recv().unwrap();
+            })
+            .unwrap();
+        }
+        started.recv().unwrap();
+        started.recv().unwrap();
+
+        let (ran_tx, ran) = mpsc::channel();
+        pool.execute(move || ran_tx.send(()).unwrap()).unwrap();
+        pool.set_size(1).unwrap();
+
+        // One worker is freed; it must take the queued job, not retire.
+        releases[0].send(()).unwrap();
+        assert!(ran.recv_timeout(Duration::from_secs(5)).is_ok());
+        releases[1].send(()).unwrap();
+    }
+
+    #[test]
+    fn test_overflow_block() {
+        let (pool, release) = saturated_pool(OverflowPolicy::Block);
+        let releaser = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            release.send(()).unwrap();
+        });
+
+        let start = std::time::Instant::now();
+        pool.execute(|| {}).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(40));
+        releaser.join().unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+/// Severity of a log message, from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One diagnostic emitted by the server or pool.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub level: Level,
+    /// Module that produced the message, e.g. `hello::server`.
+    pub target: &'a str,
+    pub args: fmt::Arguments<'a>,
+}
+
+/// A destination for the crate's diagnostics.
+///
+/// Install one with `set_logger` to forward messages into an application's
+/// own logging. Until then, messages at `Info` and above go to stderr.
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record<'_>);
+}
+
+static LOGGER: OnceLock<Box<dyn Logger>> = OnceLock::new();
+
+/// Installs the process-wide logger.
+///
+/// # Errors
+///
+/// Returns the logger back if one has already been installed.
+pub fn set_logger(logger: Box<dyn Logger>) -> Result<(), Box<dyn Logger>> {
+    LOGGER.set(logger)
+}
+
+#[doc(hidden)]
+pub fn log(level: Level, target: &str, args: fmt::Arguments<'_>) {
+    let record = Record {
+        level,
+        target,
+        args,
+    };
+    match LOGGER.get() {
+        Some(logger) => logger.log(&record),
+        None if level >= Level::Info => eprintln!("{}", record.args),
+        None => {}
+    }
+}
+
+/// A destination for access log lines, set with
+/// `ServerConfig::access_log`.
+///
+/// Workers share one sink, so `write_line` is called from many threads at
+/// once.
+pub trait LogSink: Send + Sync + fmt::Debug {
+    /// Writes one line; the sink adds the line terminator.
+    fn write_line(&self, line: &str) -> io::Result<()>;
+}
+
+/// Writes lines to standard output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        writeln!(io::stdout().lock(), "{line}")
+    }
+}
+
+/// Appends lines to a file, rotating it once it reaches a size limit.
+///
+/// On rotation `access.log` becomes `access.log.1`, an existing
+/// `access.log.1` becomes `access.log.2`, and so on; the oldest file past
+/// the retention count is deleted.
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: Mutex<OpenLog>,
+}
+
+#[derive(Debug)]
+struct OpenLog {
+    file: File,
+    written: u64,
+}
+
+impl FileSink {
+    /// How many rotated files are kept unless `keep` says otherwise.
+    pub const DEFAULT_KEEP: usize = 5;
+
+    /// Opens `path` for appending, rotating before a line would take it
+    /// past `max_bytes`. A single line longer than that gets a file of its
+    /// own.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from opening the file.
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64) -> io::Result<FileSink> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(FileSink {
+            path,
+            max_bytes,
+            keep: FileSink::DEFAULT_KEEP,
+            file: Mutex::new(OpenLog { file, written }),
+        })
+    }
+
+    /// Keeps at most `files` rotated files; 0 deletes the log on rotation.
+    pub fn keep(mut self, files: usize) -> FileSink {
+        self.keep = files;
+        self
+    }
+
+    /// Returns the path of the `n`th rotated file.
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    /// Shifts every rotated file up by one and moves the live file to `.1`.
+    fn rotate(&self, log: &mut OpenLog) -> io::Result<()> {
+        log.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        log.file = open_append(&self.path)?;
+        log.written = 0;
+        Ok(())
+    }
+}
+
+impl LogSink for FileSink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut log = self.file.lock().expect("Mutex poisoned");
+        let len = line.len() as u64 + 1;
+        if log.written > 0 && log.written + len > self.max_bytes {
+            self.rotate(&mut log)?;
+        }
+        // One write per line, so lines from other processes appending to
+        // the same file do not interleave.
+        let mut buf = Vec::with_capacity(line.len() + 1);
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+        log.file.write_all(&buf)?;
+        log.written += len;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Info, module_path!(), format_args!($($arg)+))
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Warn, module_path!(), format_args!($($arg)+))
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Error, module_path!(), format_args!($($arg)+))
+    };
+}
+
+pub(crate) use {error, info, warning};
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Records every message so tests can assert on them.
+    struct CapturingLogger {
+        lines: Mutex<Vec<(Level, String)>>,
+    }
+
+    impl Logger for CapturingLogger {
+        fn log(&self, record: &Record<'_>) {
+            let line = record.args.to_string();
+            self.lines.lock().unwrap().push((record.level, line));
+        }
+    }
+
+    static CAPTURED: OnceLock<&'static CapturingLogger> = OnceLock::new();
+
+    struct Forward(&'static CapturingLogger);
+
+    impl Logger for Forward {
+        fn log(&self, record: &Record<'_>) {
+            self.0.log(record);
+        }
+    }
+
+    /// Installs the capturing logger (once per test binary) and returns every
+    /// line logged so far.
+    pub(crate) fn captured() -> Vec<(Level, String)> {
+        let logger = CAPTURED.get_or_init(|| {
+            let logger: &'static CapturingLogger = Box::leak(Box::new(CapturingLogger {
+                lines: Mutex::new(Vec::new()),
+            }));
+            // Only this helper installs a logger in tests.
+            assert!(set_logger(Box::new(Forward(logger))).is_ok());
+            logger
+        });
+        logger.lines.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_macros_reach_installed_logger() {
+        captured();
+        warning!("disk {} is full", "/dev/null");
+
+        assert!(
+            captured()
+                .iter()
+                .any(|(level, line)| *level == Level::Warn && line == "disk /dev/null is full")
+        );
+    }
+
+    #[test]
+    fn test_file_sink_rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("hello-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let sink = FileSink::open(&path, 20).unwrap().keep(2);
+
+        // Each line is 10 bytes with its newline, so two fit in a file.
+        for i in 0..7 {
+            sink.write_line(&format!("line {i:04}")).unwrap();
+        }
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "line 0006\n");
+        assert_eq!(read(sink.rotated_path(1)), "line 0004\nline 0005\n");
+        assert_eq!(read(sink.rotated_path(2)), "line 0002\nline 0003\n");
+        // Lines 0 and 1 were rotated past the two files kept.
+        assert!(!sink.rotated_path(3).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{request::Request, response::Response};
+
+/// Code that runs around a router's handlers.
+///
+/// Implementations inspect or modify the request, then either call `next`
+/// to continue down the chain or return a response of their own.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response;
+}
+
+type Validator = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// HTTP Basic Authentication (RFC 7617).
+///
+/// Requests without valid `Authorization: Basic ...` credentials receive a
+/// `401 Unauthorized` carrying a `WWW-Authenticate` challenge.
+pub struct BasicAuth {
+    realm: String,
+    validator: Validator,
+}
+
+impl BasicAuth {
+    /// Accepts any of the given `(username, password)` pairs.
+    ///
+    /// Credentials are compared in constant time.
+    pub fn new<I, U, P>(realm: impl Into<String>, credentials: I) -> BasicAuth
+    where
+        I: IntoIterator<Item = (U, P)>,
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let credentials: HashMap<String, String> = credentials
+            .into_iter()
+            .map(|(u, p)| (u.into(), p.into()))
+            .collect();
+
+        BasicAuth::with_validator(realm, move |user, password| {
+            // Check every entry so timing does not reveal which user exists.
+            credentials.iter().fold(false, |found, (u, p)| {
+                let matched = constant_time_eq(u.as_bytes(), user.as_bytes())
+                    & constant_time_eq(p.as_bytes(), password.as_bytes());
+                found | matched
+            })
+        })
+    }
+
+    /// Accepts credentials for which `validator(username, password)` is true.
+    pub fn with_validator<F>(realm: impl Into<String>, validator: F) -> BasicAuth
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        BasicAuth {
+            realm: realm.into(),
+            validator: Arc::new(validator),
+        }
+    }
+
+    fn credentials(req: &Request) -> Option<(String, String)> {
+        let value = req.headers.get("Authorization")?;
+        let (scheme, encoded) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+
+        let decoded = String::from_utf8(base64_decode(encoded.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+
+    fn challenge(&self) -> Response {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        Response::error(401, "Valid credentials are required.")
+            .with_header("WWW-Authenticate", format!("Basic realm=\"{realm}\""))
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, req: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response {
+        match BasicAuth::credentials(req) {
+            Some((user, password)) if (self.validator)(&user, &password) => next(req),
+            _ => self.challenge(),
+        }
+    }
+}
+
+/// Compares two byte strings without short-circuiting on the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Decodes standard (RFC 4648) base64 with padding.
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let bytes = input.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let is_last = i == bytes.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            n = (n << 6) | value(c)?;
+        }
+        n <<= 6 * padding as u32;
+
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        out.extend_from_slice(&decoded[..3 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{request::Method, router::Router};
+
+    fn protected_router() -> Router {
+        let mut router = Router::new();
+        router.get("/secret", |_| Response::text(200, "secret"));
+        router.wrap(BasicAuth::new("admin area", [("alice", "hunter2")]));
+        router
+    }
+
+    fn request(authorization: Option<&str>) -> Request {
+        let mut req = Request::new(Method::Get, "/secret");
+        if let Some(value) = authorization {
+            req.headers.insert("Authorization", value);
+        }
+        req
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(
+            base64_decode("YWxpY2U6aHVudGVyMg==").unwrap(),
+            b"alice:hunter2"
+        );
+        assert_eq!(base64_decode("YWI=").unwrap(), b"ab");
+        assert!(base64_decode("YW=I").is_none());
+        assert!(base64_decode("abc").is_none());
+    }
+
+    #[test]
+    fn test_missing_header_is_unauthorized() {
+        let response = protected_router().handle(&mut request(None));
+        assert_eq!(response.status, 401);
+        assert_eq!(
+            response.headers.get("WWW-Authenticate"),
+            Some("Basic realm=\"admin area\"")
+        );
+    }
+
+    #[test]
+    fn test_wrong_password_is_unauthorized() {
+        // alice:wrong
+        let response = protected_router().handle(&mut request(Some("Basic YWxpY2U6d3Jvbmc=")));
+        assert_eq!(response.status, 401);
+    }
+
+    #[test]
+    fn test_valid_credentials_pass_through() {
+        // alice:hunter2
+        let response = protected_router().handle(&mut request(Some("Basic YWxpY2U6aHVudGVyMg==")));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"secret");
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::Read;
+
+use crate::{
+    client::{ClientError, HttpClient, Url},
+    headers::Headers,
+    request::{Method, Request},
+    response::{ChunkedReader, Framing, Response},
+    router::{Handler, handler},
+};
+
+/// Headers that describe a single connection and are never forwarded
+/// (RFC 9110, section 7.6.1).
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Returns a handler that forwards each request to `upstream`, an
+/// `http://` origin such as `http://127.0.0.1:9000`.
+///
+/// The method, path, query, headers, and body are sent on, minus
+/// hop-by-hop headers. The client's address is appended to
+/// `X-Forwarded-For` and its `Host` is passed as `X-Forwarded-Host`. The
+/// upstream response is streamed back as it arrives; redirects are
+/// returned to the client rather than followed. An unreachable upstream
+/// gives `502 Bad Gateway` and a silent one `504 Gateway Timeout`.
+pub fn proxy_to(upstream: &str) -> Handler {
+    proxy_with(HttpClient::new(), upstream)
+}
+
+/// Like `proxy_to`, with `client`'s timeout.
+pub fn proxy_with(client: HttpClient, upstream: &str) -> Handler {
+    let client = client.with_max_redirects(0);
+    let upstream = upstream.trim_end_matches('/').to_string();
+    handler(move |req| forward(&client, &upstream, req))
+}
+
+fn forward(client: &HttpClient, upstream: &str, req: &Request) -> Response {
+    let target = match &req.query {
+        Some(query) => format!("{upstream}{}?{query}", req.path),
+        None => format!("{upstream}{}", req.path),
+    };
+    let url = match Url::parse(&target) {
+        Ok(url) => url,
+        Err(e) => return Response::error(502, e.to_string()),
+    };
+
+    let mut headers = without_hop_by_hop(&req.headers);
+    headers.remove("Host");
+    headers.remove("Content-Length");
+    if let Some(host) = req.headers.get("Host") {
+        headers.insert("X-Forwarded-Host", host);
+    }
+    if let Some(peer) = req.peer_addr {
+        let forwarded = match req.headers.get("X-Forwarded-For") {
+            Some(chain) => format!("{chain}, {}", peer.ip()),
+            None => peer.ip().to_string(),
+        };
+        headers.insert("X-Forwarded-For", forwarded);
+    }
+
+    let (upstream_response, reader) = match client.open(&req.method, &url, &headers, &req.body) {
+        Ok(opened) => opened,
+        Err(ClientError::Timeout) => {
+            return Response::error(504, "The upstream server did not respond in time.");
+        }
+        Err(e) => return Response::error(502, e.to_string()),
+    };
+    let framing = match upstream_response.framing(req.method != Method::Head) {
+        Ok(framing) => framing,
+        Err(e) => return Response::error(502, e.to_string()),
+    };
+
+    let mut response = Response::new(upstream_response.status);
+    response.headers = without_hop_by_hop(&upstream_response.headers);
+    match framing {
+        // A `HEAD` reply keeps the upstream `Content-Length`.
+        Framing::Empty => response,
+        Framing::Length(length) => {
+            response.headers.remove("Content-Length");
+            response.with_body_reader(reader.take(length), Some(length))
+        }
+        Framing::Chunked => {
+            response.headers.remove("Content-Length");
+            response.with_body_reader(ChunkedReader::new(reader), None)
+        }
+        Framing::Close => response.with_body_reader(reader, None),
+    }
+}
+
+/// Copies `headers` without hop-by-hop headers, including any named in
+/// `Connection`.
+fn without_hop_by_hop(headers: &Headers) -> Headers {
+    let listed: Vec<&str> = headers
+        .get("Connection")
+        .map(|value| value.split(',').map(str::trim).collect())
+        .unwrap_or_default();
+    let mut kept = Headers::new();
+    for (name, value) in headers.iter() {
+        let hop = HOP_BY_HOP
+            .iter()
+            .chain(&listed)
+            .any(|hop| hop.eq_ignore_ascii_case(name));
+        if !hop {
+            kept.append(name, value);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{router::Router, server::ServerConfig, testing::TestClient};
+    use std::io::Write;
+
+    #[test]
+    fn test_proxies_to_origin() {
+        let mut origin = Router::new();
+        origin.get("/hello", |req| {
+            let forwarded = req.headers.get("X-Forwarded-For").unwrap_or("-");
+            let host = req.headers.get("X-Forwarded-Host").unwrap_or("-");
+            Response::text(
+                200,
+                format!(
+                    "origin {} {forwarded} {host}",
+                    req.query.as_deref().unwrap_or("")
+                ),
+            )
+            .with_header("X-Origin", "yes")
+            .with_header("Keep-Alive", "timeout=9")
+        });
+        origin.post("/echo", |req| {
+            Response::new(201).with_body(req.body.clone())
+        });
+        origin.get("/stream", |_| {
+            Response::chunked(200, |out| {
+                out.write_all(b"one ")?;
+                out.write_all(b"two")
+            })
+        });
+        let origin = TestClient::new(ServerConfig::default(), origin);
+
+        let mut gateway = Router::new();
+        let upstream = format!("http://{}/", origin.server().local_addr());
+        let proxy = proxy_to(&upstream);
+        for method in [Method::Get, Method::Post] {
+            let proxy = proxy.clone();
+            gateway.add(method, "/*path", move |req| proxy(req));
+        }
+        let gateway = TestClient::new(ServerConfig::default(), gateway);
+
+        let response = gateway.get("/hello?x=1");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"origin x=1 127.0.0.1 localhost");
+        assert_eq!(response.headers.get("X-Origin"), Some("yes"));
+        assert_eq!(response.headers.get("Keep-Alive"), None);
+
+        let response = gateway.post("/echo", "payload");
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, b"payload");
+
+        let response = gateway.get("/stream");
+        assert_eq!(response.headers.get("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response.body, b"one two");
+
+        let response = gateway.request(Method::Head, "/hello", &[], b"");
+        assert_eq!(response.headers.get("Content-Length"), Some("27"));
+    }
+
+    #[test]
+    fn test_truncated_upstream_body_fails_the_write() {
+        use std::io::{self, BufRead, BufReader};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = format!("http://{}", listener.local_addr().unwrap());
+        let origin = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort")
+                .unwrap();
+        });
+
+        let response = proxy_to(&upstream)(&mut Request::new(Method::Get, "/"));
+        origin.join().unwrap();
+        assert_eq!(response.status, 200);
+        let error = response.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_unreachable_upstream_is_bad_gateway() {
+        // Bind and drop a listener to find a port nobody is serving.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let proxy = proxy_to(&upstream);
+        let response = proxy(&mut Request::new(Method::Get, "/"));
+        assert_eq!(response.status, 502);
+        let response = proxy_to("ftp://example.com")(&mut Request::new(Method::Get, "/"));
+        assert_eq!(response.status, 502);
+    }
+}
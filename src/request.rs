@@ -0,0 +1,2086 @@
+use std::{
+    any::{Any, TypeId},
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Read, Write},
+    mem,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    compression::{self, GunzipError},
+    etag::{self, ETag},
+    headers::Headers,
+    response::{ChunkedReader, Response},
+};
+
+/// Custom error type for request parsing and for interpreting a parsed
+/// request's body.
+///
+/// `status_code` gives the response each failure deserves, so the server
+/// and handlers map them all the same way.
+#[derive(Debug)]
+pub enum RequestError {
+    /// Reading the request or storing its body failed.
+    Io(io::Error),
+    /// The request line is not `method SP target SP version`, or one of its
+    /// parts is invalid.
+    MalformedRequestLine(String),
+    /// An HTTP/1.1 request without a `Host` header (RFC 9112, section 3.2).
+    MissingHost,
+    /// A header line is malformed, or a header's value is unacceptable.
+    InvalidHeader(String),
+    /// Other malformed input, such as a request cut short or bytes that
+    /// are not UTF-8.
+    BadRequest(String),
+    NotImplemented(String),
+    /// A well-formed protocol version the server cannot speak.
+    VersionNotSupported(String),
+    /// The request target exceeds `ParseConfig::max_uri_length`.
+    UriTooLong(String),
+    /// A header line exceeds `ParseConfig::max_single_header_bytes`.
+    HeaderTooLarge(String),
+    /// The body arrived slower than `ParseConfig::min_body_rate`.
+    Timeout(String),
+    /// The declared body, or the decompressed one, exceeds
+    /// `ParseConfig::max_body_size`.
+    BodyTooLarge(String),
+    /// The body's `Content-Encoding` is one the server cannot decode.
+    UnsupportedMediaType(String),
+    /// As `BodyTooLarge`, for a request that sent `Expect: 100-continue`
+    /// when the server answers those with `417`.
+    ExpectationFailed(String),
+    /// The body is not valid in its declared charset.
+    InvalidEncoding(String),
+    /// The body's declared charset cannot be decoded.
+    UnsupportedCharset(String),
+}
+
+impl RequestError {
+    /// Returns the status of the response this error calls for.
+    ///
+    /// `Io` maps to `500`. The server sends no response when reading the
+    /// connection fails, since the peer is gone.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            RequestError::MalformedRequestLine(_)
+            | RequestError::MissingHost
+            | RequestError::InvalidHeader(_)
+            | RequestError::BadRequest(_)
+            | RequestError::InvalidEncoding(_) => 400,
+            RequestError::Timeout(_) => 408,
+            RequestError::BodyTooLarge(_) => 413,
+            RequestError::UriTooLong(_) => 414,
+            RequestError::UnsupportedMediaType(_) | RequestError::UnsupportedCharset(_) => 415,
+            RequestError::ExpectationFailed(_) => 417,
+            RequestError::HeaderTooLarge(_) => 431,
+            RequestError::NotImplemented(_) => 501,
+            RequestError::VersionNotSupported(_) => 505,
+            RequestError::Io(_) => 500,
+        }
+    }
+
+    /// Returns the explanation for the error page, without the status.
+    pub(crate) fn detail(&self) -> String {
+        match self {
+            RequestError::Io(e) => e.to_string(),
+            RequestError::MissingHost => String::from("Missing Host header"),
+            RequestError::MalformedRequestLine(msg)
+            | RequestError::InvalidHeader(msg)
+            | RequestError::BadRequest(msg)
+            | RequestError::NotImplemented(msg)
+            | RequestError::VersionNotSupported(msg)
+            | RequestError::UriTooLong(msg)
+            | RequestError::HeaderTooLarge(msg)
+            | RequestError::Timeout(msg)
+            | RequestError::BodyTooLarge(msg)
+            | RequestError::UnsupportedMediaType(msg)
+            | RequestError::ExpectationFailed(msg)
+            | RequestError::InvalidEncoding(msg)
+            | RequestError::UnsupportedCharset(msg) => msg.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Io(e) => write!(f, "I/O Error: {e}"),
+            RequestError::MalformedRequestLine(msg) => write!(f, "Malformed Request Line: {msg}"),
+            RequestError::MissingHost => write!(f, "Bad Request: Missing Host header"),
+            RequestError::InvalidHeader(msg) => write!(f, "Invalid Header: {msg}"),
+            RequestError::BadRequest(msg) => write!(f, "Bad Request: {msg}"),
+            RequestError::NotImplemented(msg) => write!(f, "Not Implemented: {msg}"),
+            RequestError::VersionNotSupported(msg) => write!(f, "Version Not Supported: {msg}"),
+            RequestError::UriTooLong(msg) => write!(f, "URI Too Long: {msg}"),
+            RequestError::HeaderTooLarge(msg) => {
+                write!(f, "Request Header Fields Too Large: {msg}")
+            }
+            RequestError::Timeout(msg) => write!(f, "Request Timeout: {msg}"),
+            RequestError::BodyTooLarge(msg) => write!(f, "Content Too Large: {msg}"),
+            RequestError::UnsupportedMediaType(msg) => write!(f, "Unsupported Media Type: {msg}"),
+            RequestError::ExpectationFailed(msg) => write!(f, "Expectation Failed: {msg}"),
+            RequestError::InvalidEncoding(msg) => write!(f, "Invalid Encoding: {msg}"),
+            RequestError::UnsupportedCharset(charset) => {
+                write!(f, "Unsupported Charset: {charset}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> RequestError {
+        RequestError::Io(e)
+    }
+}
+
+/// Size of the writes `Request::save_body_to` makes.
+const SAVE_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Why `copy_capped` stopped early.
+enum CopyError {
+    TooLarge,
+    Io(io::Error),
+}
+
+/// Copies `reader` to `writer` in `SAVE_BLOCK_SIZE` blocks, failing once
+/// more than `max` bytes have arrived.
+fn copy_capped<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    max: u64,
+) -> Result<u64, CopyError> {
+    let mut buf = vec![0; SAVE_BLOCK_SIZE];
+    let mut written = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(written),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CopyError::Io(e)),
+        };
+        written += n as u64;
+        if written > max {
+            return Err(CopyError::TooLarge);
+        }
+        writer.write_all(&buf[..n]).map_err(CopyError::Io)?;
+    }
+}
+
+/// The request method token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Trace,
+    Connect,
+    Other(String),
+}
+
+impl FromStr for Method {
+    type Err = RequestError;
+
+    fn from_str(s: &str) -> Result<Method, RequestError> {
+        if s.is_empty() {
+            return Err(RequestError::MalformedRequestLine("Missing method".into()));
+        }
+        if !s.bytes().all(is_tchar) {
+            return Err(RequestError::MalformedRequestLine(format!(
+                "Invalid method {s:?}"
+            )));
+        }
+
+        let method = match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "CONNECT" => Method::Connect,
+            other => Method::Other(other.to_string()),
+        };
+        // Methods are case-sensitive; `get` is not `GET`, and an
+        // intermediary that folds case would route it differently.
+        if let Method::Other(other) = &method
+            && KNOWN_METHODS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(other))
+        {
+            return Err(RequestError::MalformedRequestLine(format!(
+                "Method {other:?} must be upper case"
+            )));
+        }
+        Ok(method)
+    }
+}
+
+const KNOWN_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "TRACE", "CONNECT",
+];
+
+/// Returns `true` for bytes allowed in a token (RFC 9110 `tchar`).
+fn is_tchar(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Other(s) => s,
+        };
+        f.write_str(s)
+    }
+}
+
+/// The HTTP protocol version of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl FromStr for Version {
+    type Err = RequestError;
+
+    /// Parses an `HTTP/major.minor` token.
+    ///
+    /// Later 1.x minor versions are served as HTTP/1.1, as RFC 7230 allows.
+    /// Other well-formed versions fail with `VersionNotSupported`; anything
+    /// else is a `BadRequest`.
+    fn from_str(s: &str) -> Result<Version, RequestError> {
+        let numbers = s
+            .strip_prefix("HTTP/")
+            .and_then(|numbers| numbers.split_once('.'))
+            .filter(|(major, minor)| {
+                let is_digit = |n: &str| n.len() == 1 && n.as_bytes()[0].is_ascii_digit();
+                is_digit(major) && is_digit(minor)
+            });
+
+        match numbers {
+            Some(("1", "0")) => Ok(Version::Http10),
+            Some(("1", _)) => Ok(Version::Http11),
+            Some(_) => Err(RequestError::VersionNotSupported(format!(
+                "Unsupported version {s:?}"
+            ))),
+            None => Err(RequestError::MalformedRequestLine(format!(
+                "Malformed version {s:?}"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => f.write_str("HTTP/1.0"),
+            Version::Http11 => f.write_str("HTTP/1.1"),
+        }
+    }
+}
+
+/// Limits applied while parsing a request.
+#[derive(Debug, Clone)]
+pub struct ParseConfig {
+    /// Longest request target accepted, in bytes; `None` is unlimited.
+    pub max_uri_length: Option<usize>,
+    /// Longest header line accepted, in bytes, name and value together;
+    /// `None` is unlimited. Reading stops once a line passes it.
+    pub max_single_header_bytes: Option<usize>,
+    /// Slowest acceptable body upload; `None` waits as long as reads
+    /// succeed.
+    pub min_body_rate: Option<MinBodyRate>,
+    /// Largest `Content-Length` accepted; `None` is unlimited. Bigger
+    /// requests are refused before any of the body is read.
+    pub max_body_size: Option<u64>,
+    /// How a too-large request that sent `Expect: 100-continue` is refused.
+    pub expect_rejection: ExpectRejection,
+    /// Measures the windows of `min_body_rate`.
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for ParseConfig {
+    fn default() -> ParseConfig {
+        ParseConfig {
+            max_uri_length: None,
+            max_single_header_bytes: None,
+            min_body_rate: None,
+            max_body_size: None,
+            expect_rejection: ExpectRejection::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// The status for refusing an oversized body that the client offered with
+/// `Expect: 100-continue` and has not sent yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpectRejection {
+    /// `417 Expectation Failed`: the expectation itself is refused.
+    #[default]
+    ExpectationFailed,
+    /// `413 Content Too Large`, as for a body sent without `Expect`.
+    ContentTooLarge,
+}
+
+/// A minimum throughput for request bodies: at least `bytes` in every
+/// `window`.
+///
+/// Unlike a total timeout this lets a large upload take as long as it
+/// needs, while a client trickling bytes to tie up a worker is cut off
+/// after one window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinBodyRate {
+    pub bytes: usize,
+    pub window: Duration,
+}
+
+impl MinBodyRate {
+    pub fn new(bytes: usize, window: Duration) -> MinBodyRate {
+        MinBodyRate { bytes, window }
+    }
+}
+
+/// Room on the request line for the method, spaces, and version.
+const REQUEST_LINE_OVERHEAD: usize = 64;
+
+/// A parsed HTTP request head.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    /// The request body, read according to `Content-Length` or chunked
+    /// `Transfer-Encoding`.
+    pub body: Vec<u8>,
+    /// Path parameters captured by the router, e.g. `id` for `/users/:id`.
+    pub params: HashMap<String, String>,
+    /// Address of the TCP peer, which may be a proxy.
+    pub peer_addr: Option<SocketAddr>,
+    /// Address of the originating client, taken from `X-Forwarded-For` when
+    /// the peer is a trusted proxy and from `peer_addr` otherwise.
+    pub real_ip: Option<IpAddr>,
+    /// State kept for the TCP connection the request arrived on.
+    pub connection: ConnectionContext,
+    /// The unread body, for routes added with `Router::add_streaming`;
+    /// `body` is then empty.
+    pub body_reader: Option<BodyReader>,
+}
+
+/// A request body read from the connection as the handler asks for it.
+///
+/// Reads return the decoded body, then `Ok(0)`. Once more than
+/// `ParseConfig::max_body_size` bytes have arrived, every read fails.
+/// Clones share the one stream.
+#[derive(Clone)]
+pub struct BodyReader {
+    state: Arc<Mutex<BodyState>>,
+}
+
+struct BodyState {
+    inner: Box<dyn Read + Send>,
+    read: u64,
+    max: Option<u64>,
+    exceeded: bool,
+}
+
+impl BodyReader {
+    fn new(inner: Box<dyn Read + Send>, max: Option<u64>) -> BodyReader {
+        BodyReader {
+            state: Arc::new(Mutex::new(BodyState {
+                inner,
+                read: 0,
+                max,
+                exceeded: false,
+            })),
+        }
+    }
+
+    /// Returns `true` once the body has run past the size limit.
+    pub fn exceeded(&self) -> bool {
+        self.state.lock().expect("Mutex poisoned").exceeded
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().expect("Mutex poisoned");
+        let too_large = |max| io::Error::other(format!("Body exceeds the {max} byte limit"));
+        if let Some(max) = state.max
+            && state.exceeded
+        {
+            return Err(too_large(max));
+        }
+        let n = state.inner.read(buf)?;
+        state.read += n as u64;
+        if let Some(max) = state.max
+            && state.read > max
+        {
+            state.exceeded = true;
+            return Err(too_large(max));
+        }
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().expect("Mutex poisoned");
+        f.debug_struct("BodyReader")
+            .field("read", &state.read)
+            .field("max", &state.max)
+            .field("exceeded", &state.exceeded)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Values a handler stores for the rest of a keep-alive connection, keyed
+/// by type.
+///
+/// The server hands the same context to every request read from one
+/// socket and drops it when the connection closes, so a handler can, say,
+/// check a token once and find the result on later requests.
+#[derive(Clone, Default)]
+pub struct ConnectionContext {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl ConnectionContext {
+    /// Creates an empty context.
+    pub fn new() -> ConnectionContext {
+        ConnectionContext::default()
+    }
+
+    /// Stores `value`, replacing any earlier value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns the stored value of type `T`, if any.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Removes the stored value of type `T`, returning whether there was one.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> bool {
+        self.values.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns `true` if nothing is stored.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for ConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionContext")
+            .field("values", &self.values.len())
+            .finish()
+    }
+}
+
+impl Request {
+    /// Create an HTTP/1.1 request with no headers.
+    ///
+    /// `target` may include a query string.
+    pub fn new(method: Method, target: &str) -> Request {
+        let (path, query) = split_target(target);
+        Request {
+            method,
+            path,
+            query,
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer_addr: None,
+            real_ip: None,
+            connection: ConnectionContext::default(),
+            body_reader: None,
+        }
+    }
+
+    /// Reads and parses one request (line, headers, and body) from `reader`.
+    ///
+    /// Returns `Ok(None)` if the stream ends, or the read times out, before
+    /// any bytes of a request arrive; this is how a client closes an idle
+    /// connection. Blank lines before the request line are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RequestError` describing the first problem found: for
+    /// instance `MalformedRequestLine`, `InvalidHeader`, `MissingHost` for
+    /// an HTTP/1.1 request without `Host` (RFC 9112, section 3.2),
+    /// `BadRequest` for a request cut short, and `NotImplemented` for a body
+    /// in a `Transfer-Encoding` other than chunked.
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<Option<Request>, RequestError> {
+        Request::parse_with(reader, &ParseConfig::default())
+    }
+
+    /// Like `parse`, but enforces the limits in `config`.
+    ///
+    /// An over-long request line is rejected once the limit is passed,
+    /// without buffering the rest of it.
+    pub fn parse_with<R: BufRead>(
+        reader: &mut R,
+        config: &ParseConfig,
+    ) -> Result<Option<Request>, RequestError> {
+        let Some(mut request) = Request::parse_head(reader, config, &mut Headers::new())? else {
+            return Ok(None);
+        };
+        request.read_body(reader, config)?;
+        Ok(Some(request))
+    }
+
+    /// Reads exactly one request from `reader`, as `parse_with` does, for
+    /// callers that have a request's bytes in hand rather than a
+    /// connection to watch.
+    ///
+    /// # Errors
+    ///
+    /// As `parse_with`, and `RequestError::Io` with `UnexpectedEof` if the
+    /// reader ends, or times out, before a request starts. Every error maps
+    /// to a response through `RequestError::status_code`.
+    pub fn parse_from_reader<R: BufRead>(
+        reader: &mut R,
+        config: &ParseConfig,
+    ) -> Result<Request, RequestError> {
+        Request::parse_with(reader, config)?
+            .ok_or_else(|| RequestError::Io(io::ErrorKind::UnexpectedEof.into()))
+    }
+
+    /// Reads the request line and headers, leaving the body on `reader`.
+    ///
+    /// A body declared larger than `config.max_body_size` is refused here,
+    /// before any of it is read.
+    ///
+    /// Headers are collected into `headers`, which still holds those read
+    /// so far if parsing fails, so the error can honour `Accept`.
+    pub(crate) fn parse_head<R: BufRead>(
+        reader: &mut R,
+        config: &ParseConfig,
+        headers: &mut Headers,
+    ) -> Result<Option<Request>, RequestError> {
+        let line_limit = config
+            .max_uri_length
+            .map(|max| max.saturating_add(REQUEST_LINE_OVERHEAD));
+        let request_line = loop {
+            match read_line(reader, line_limit, |limit| {
+                RequestError::UriTooLong(format!("Request line exceeds {limit} bytes"))
+            }) {
+                Ok(Some(line)) if line.is_empty() => continue,
+                Ok(Some(line)) => break line,
+                Ok(None) => return Ok(None),
+                Err(RequestError::Io(e)) if is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        };
+
+        if !request_line.is_ascii() {
+            return Err(RequestError::MalformedRequestLine(
+                "Request line contains non-ASCII bytes".into(),
+            ));
+        }
+
+        // Exactly one space between parts, and nothing a lenient parser
+        // elsewhere might read differently: tabs, stray CRs, other controls.
+        let mut parts = request_line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(m), Some(t), v, None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+                _ => {
+                    return Err(RequestError::MalformedRequestLine(
+                        "Malformed request line".into(),
+                    ));
+                }
+            };
+        if target.bytes().any(|b| b.is_ascii_control()) {
+            return Err(RequestError::MalformedRequestLine(
+                "Request target contains control characters".into(),
+            ));
+        }
+        let method: Method = method.parse()?;
+        let Some(version) = version else {
+            // HTTP/0.9 sent only `GET /path`.
+            return Err(RequestError::VersionNotSupported(
+                "HTTP/0.9 requests are not supported".into(),
+            ));
+        };
+
+        if let Some(max) = config.max_uri_length
+            && target.len() > max
+        {
+            return Err(RequestError::UriTooLong(format!(
+                "Request target exceeds {max} bytes"
+            )));
+        }
+
+        let version = version.parse()?;
+        let (path, query) = split_target(target);
+
+        loop {
+            let line = read_line(reader, config.max_single_header_bytes, |limit| {
+                RequestError::HeaderTooLarge(format!("Header line exceeds {limit} bytes"))
+            })?
+            .ok_or_else(|| RequestError::BadRequest("Unexpected end of headers".into()))?;
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| RequestError::InvalidHeader(format!("Malformed header {line:?}")))?;
+            if !name.is_ascii() {
+                return Err(RequestError::InvalidHeader(format!(
+                    "Header name {name:?} contains non-ASCII bytes"
+                )));
+            }
+            let name = name.trim();
+            // A second `Host` could route the request differently through
+            // a proxy than here.
+            if name.eq_ignore_ascii_case("Host") && headers.contains("Host") {
+                return Err(RequestError::InvalidHeader("Duplicate Host header".into()));
+            }
+            headers.append(name, value.trim());
+        }
+
+        if version == Version::Http11 && !headers.contains("Host") {
+            return Err(RequestError::MissingHost);
+        }
+
+        if let BodyFraming::Length(length) = body_framing(headers)? {
+            check_declared_size(length, headers, config)?;
+        }
+
+        Ok(Some(Request {
+            method,
+            path,
+            query,
+            version,
+            headers: mem::take(headers),
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer_addr: None,
+            real_ip: None,
+            connection: ConnectionContext::default(),
+            body_reader: None,
+        }))
+    }
+
+    /// Reads the body announced by the headers into `self.body`.
+    ///
+    /// A `gzip` body is decompressed, with `max_body_size` applied to the
+    /// decompressed size, and its `Content-Encoding` is removed. The body of
+    /// a `GET` or `HEAD` is consumed, within the same limits, and dropped.
+    pub(crate) fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        config: &ParseConfig,
+    ) -> Result<(), RequestError> {
+        let gzipped = content_coding(&self.headers)?;
+        match body_framing(&self.headers)? {
+            BodyFraming::Empty => {}
+            BodyFraming::Length(length) => {
+                read_to_end(&mut reader.take(length), &mut self.body, config)?;
+                if (self.body.len() as u64) < length {
+                    return Err(RequestError::BadRequest("Incomplete body".into()));
+                }
+            }
+            BodyFraming::Chunked => {
+                // One byte past the limit tells an oversized body from one
+                // that fits exactly.
+                let limit = config.max_body_size.map_or(u64::MAX, |max| max + 1);
+                let mut chunks =
+                    ChunkedReader::with_line_limit(&mut *reader, config.max_single_header_bytes)
+                        .take(limit);
+                match read_to_end(&mut chunks, &mut self.body, config) {
+                    Err(RequestError::Io(e))
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                        ) =>
+                    {
+                        return Err(RequestError::BadRequest(format!(
+                            "Malformed chunked body: {e}"
+                        )));
+                    }
+                    result => result?,
+                }
+                if let Some(max) = config.max_body_size
+                    && self.body.len() as u64 > max
+                {
+                    return Err(RequestError::BodyTooLarge(format!(
+                        "Chunked body exceeds the {max} byte limit"
+                    )));
+                }
+            }
+        }
+        // A body has no meaning on GET or HEAD (RFC 9110, section 9.3.1);
+        // it is read only to find where the next request starts.
+        if matches!(self.method, Method::Get | Method::Head) {
+            self.body = Vec::new();
+        }
+        if gzipped && !self.body.is_empty() {
+            self.body =
+                compression::gunzip(&self.body, config.max_body_size).map_err(|e| match e {
+                    GunzipError::TooLarge => RequestError::BodyTooLarge(format!(
+                        "Decompressed body exceeds the {} byte limit",
+                        config.max_body_size.unwrap_or_default()
+                    )),
+                    GunzipError::Malformed(_) => RequestError::BadRequest(e.to_string()),
+                })?;
+            self.headers.remove("Content-Encoding");
+            if self.headers.contains("Content-Length") {
+                self.headers
+                    .insert("Content-Length", self.body.len().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the body on `reader` for the handler, in `self.body_reader`.
+    pub(crate) fn stream_body<R: BufRead + Send + 'static>(
+        &mut self,
+        reader: R,
+        config: &ParseConfig,
+    ) -> Result<(), RequestError> {
+        let inner: Box<dyn Read + Send> = match body_framing(&self.headers)? {
+            BodyFraming::Empty => Box::new(io::empty()),
+            BodyFraming::Length(length) => Box::new(reader.take(length)),
+            BodyFraming::Chunked => Box::new(ChunkedReader::with_line_limit(
+                reader,
+                config.max_single_header_bytes,
+            )),
+        };
+        self.body_reader = Some(BodyReader::new(inner, config.max_body_size));
+        Ok(())
+    }
+
+    /// Returns `true` if the client waits for `100 Continue` before sending
+    /// its body.
+    pub(crate) fn expects_continue(&self) -> bool {
+        self.version == Version::Http11 && expects_continue(&self.headers)
+    }
+
+    /// Returns `true` if the headers announce a body.
+    pub(crate) fn has_body(&self) -> bool {
+        !matches!(
+            body_framing(&self.headers),
+            Ok(BodyFraming::Empty | BodyFraming::Length(0))
+        )
+    }
+
+    /// Returns `true` if the client wants the connection kept open after
+    /// this request: the default for HTTP/1.1, opt-in for HTTP/1.0.
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.headers.get("Connection").unwrap_or_default();
+        let has_token = |token: &str| {
+            connection
+                .split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        };
+
+        match self.version {
+            Version::Http11 => !has_token("close"),
+            Version::Http10 => has_token("keep-alive"),
+        }
+    }
+
+    /// Returns the value of the `Host` header, if present.
+    pub fn host(&self) -> Option<&str> {
+        self.headers.get("Host")
+    }
+
+    /// Writes the body to a new file at `path`, releasing it from memory,
+    /// and returns the number of bytes written.
+    ///
+    /// On a route added with `Router::add_streaming` the body is copied
+    /// from `body_reader` as it arrives, so it is never held in memory, and
+    /// `max` is enforced as the bytes come in. The file is written in
+    /// blocks and never left behind half-written: on any failure the file
+    /// this call created is removed. An existing file at `path` is never
+    /// touched.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RequestError::BodyTooLarge` if the body is longer than `max`
+    /// bytes, and `RequestError::Io` if `path` already exists or the file
+    /// cannot be written. A buffered body is kept in either case, and one
+    /// too large is refused before the file is created; a streamed body
+    /// has been consumed up to the failure.
+    pub fn save_body_to(&mut self, path: &Path, max: usize) -> Result<u64, RequestError> {
+        let too_large = || RequestError::BodyTooLarge(format!("Body exceeds {max} bytes"));
+        if self.body_reader.is_none() && self.body.len() > max {
+            return Err(too_large());
+        }
+
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let written = match &mut self.body_reader {
+            Some(reader) => copy_capped(reader, &mut file, max as u64).map_err(|e| match e {
+                CopyError::TooLarge => too_large(),
+                CopyError::Io(_) if reader.exceeded() => too_large(),
+                CopyError::Io(e) => RequestError::Io(e),
+            }),
+            None => self
+                .body
+                .chunks(SAVE_BLOCK_SIZE)
+                .try_for_each(|block| file.write_all(block))
+                .map(|()| self.body.len() as u64)
+                .map_err(RequestError::Io),
+        };
+        let synced = written.and_then(|written| {
+            file.sync_all()?;
+            Ok(written)
+        });
+        match synced {
+            Ok(written) => {
+                self.body = Vec::new();
+                Ok(written)
+            }
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns `true` if the `Accept` header allows `mime`, such as
+    /// `application/json`. A request without `Accept` accepts anything.
+    pub fn accepts(&self, mime: &str) -> bool {
+        accept_quality(&self.headers, mime) > 0.0
+    }
+
+    /// Picks the type from `options` the client prefers according to
+    /// `Accept` and its q-values, or `None` if it accepts none of them.
+    ///
+    /// `options` is in server preference order, which breaks ties. Each
+    /// option is weighed by the most specific range that matches it, so
+    /// `text/html` beats `text/*`, which beats `*/*`.
+    pub fn preferred<'a>(&self, options: &[&'a str]) -> Option<&'a str> {
+        preferred_media(&self.headers, options)
+    }
+
+    /// Returns the `If-Match` header, if present.
+    pub fn if_match(&self) -> Option<&str> {
+        self.headers.get("If-Match")
+    }
+
+    /// Returns the `If-None-Match` header, if present.
+    pub fn if_none_match(&self) -> Option<&str> {
+        self.headers.get("If-None-Match")
+    }
+
+    /// Evaluates `If-Match` and `If-None-Match` against the resource's
+    /// current tag, or `None` if the resource does not exist.
+    ///
+    /// Returns the response to send instead of performing the request:
+    /// `412 Precondition Failed` when `If-Match` does not match (strong
+    /// comparison) or when `If-None-Match` matches on a method other than
+    /// `GET` or `HEAD`, which get `304 Not Modified` instead. `If-None-Match`
+    /// is ignored when `If-Match` is present. Returns `None` when the
+    /// handler should go ahead.
+    pub fn check_precondition(&self, current: Option<&ETag>) -> Option<Response> {
+        if let Some(header) = self.if_match() {
+            let matched = current.is_some_and(|current| etag::any_match(header, current));
+            return (!matched).then(Response::precondition_failed);
+        }
+
+        let header = self.if_none_match()?;
+        if !current.is_some_and(|current| etag::none_match(header, current)) {
+            return None;
+        }
+        match self.method {
+            Method::Get | Method::Head => {
+                let mut response = Response::not_modified();
+                if let Some(current) = current {
+                    response.headers.insert("ETag", current.to_string());
+                }
+                Some(response)
+            }
+            _ => Some(Response::precondition_failed()),
+        }
+    }
+
+    /// Returns a path parameter captured by the router.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Returns the non-empty segments of the path, percent-decoded, so
+    /// `/users/42/posts` yields `users`, `42`, `posts` and `/` yields none.
+    ///
+    /// Decoding borrows unless a segment contains an escape. A segment whose
+    /// escapes are malformed or do not decode to UTF-8 is returned as sent.
+    pub fn path_segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(percent_decode)
+    }
+
+    /// Decodes the body using the `charset` of its `Content-Type`.
+    ///
+    /// UTF-8 is assumed when no charset is given. `us-ascii` and
+    /// `iso-8859-1` (Latin-1) are also understood.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RequestError::InvalidEncoding` if the body is not valid in
+    /// the charset, and `RequestError::UnsupportedCharset` for any other
+    /// charset.
+    pub fn text(&self) -> Result<String, RequestError> {
+        let charset = self
+            .headers
+            .get("Content-Type")
+            .and_then(|content_type| {
+                content_type.split(';').skip(1).find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("charset")
+                        .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+                })
+            })
+            .unwrap_or_else(|| String::from("utf-8"));
+
+        match charset.as_str() {
+            "utf-8" | "utf8" => String::from_utf8(self.body.clone())
+                .map_err(|e| RequestError::InvalidEncoding(e.to_string())),
+            "us-ascii" | "ascii" => match self.body.iter().position(|b| !b.is_ascii()) {
+                Some(i) => Err(RequestError::InvalidEncoding(format!(
+                    "non-ASCII byte at offset {i}"
+                ))),
+                None => Ok(self.body.iter().map(|&b| b as char).collect()),
+            },
+            // Latin-1 bytes are exactly the first 256 Unicode code points.
+            "iso-8859-1" | "latin1" | "latin-1" | "l1" => {
+                Ok(self.body.iter().map(|&b| b as char).collect())
+            }
+            _ => Err(RequestError::UnsupportedCharset(charset)),
+        }
+    }
+}
+
+/// Determines the originating client address behind a chain of proxies.
+///
+/// `X-Forwarded-For` is only consulted when `peer` is in `trusted`, since
+/// anyone can send the header. Entries are walked from the right, skipping
+/// trusted proxies; the first untrusted address is the client. Entries to
+/// its left were supplied by the client itself and are ignored.
+pub(crate) fn resolve_real_ip(
+    peer: IpAddr,
+    forwarded_for: Option<&str>,
+    trusted: &[IpAddr],
+) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for entry in forwarded_for.unwrap_or_default().rsplit(',') {
+        match entry.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// Decodes `%XX` escapes, returning `s` unchanged if any are malformed or
+/// the result is not UTF-8.
+fn percent_decode(s: &str) -> Cow<'_, str> {
+    if !s.contains('%') {
+        return Cow::Borrowed(s);
+    }
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte != b'%' {
+            bytes.push(byte);
+            rest = tail;
+            continue;
+        }
+        // `from_str_radix` would also take a sign, as in `%+1`.
+        let decoded = tail
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(decoded) => bytes.push(decoded),
+            None => return Cow::Borrowed(s),
+        }
+        rest = &tail[2..];
+    }
+    String::from_utf8(bytes).map_or(Cow::Borrowed(s), Cow::Owned)
+}
+
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.split_once('?') {
+        Some((p, q)) => (p.to_string(), Some(q.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+/// Reads one line, stripping the trailing CRLF (or bare LF).
+///
+/// Returns `Ok(None)` at end of stream, and an error if the stream ends in
+/// the middle of a line or the line is not valid UTF-8. With a `limit`, a
+/// line longer than that is a `UriTooLong` error and its remainder is left
+/// unread.
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    limit: Option<usize>,
+    too_long: fn(usize) -> RequestError,
+) -> Result<Option<String>, RequestError> {
+    let mut line = Vec::new();
+    let read = match limit {
+        // Room for the limit plus CRLF; one byte more means it was exceeded.
+        Some(limit) => reader
+            .by_ref()
+            .take(limit as u64 + 3)
+            .read_until(b'\n', &mut line)?,
+        None => reader.read_until(b'\n', &mut line)?,
+    };
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if let Some(limit) = limit
+        && line.trim_ascii_end().len() > limit
+    {
+        return Err(too_long(limit));
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(RequestError::BadRequest("Incomplete request".into()));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    let line = String::from_utf8(line)
+        .map_err(|_| RequestError::BadRequest("Request is not valid UTF-8".into()))?;
+    Ok(Some(line))
+}
+
+/// Picks the option `Accept` in `headers` rates highest, as
+/// `Request::preferred` does.
+pub(crate) fn preferred_media<'a>(headers: &Headers, options: &[&'a str]) -> Option<&'a str> {
+    let mut best: Option<(&str, f32)> = None;
+    for &option in options {
+        let quality = accept_quality(headers, option);
+        if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+            best = Some((option, quality));
+        }
+    }
+    best.map(|(option, _)| option)
+}
+
+/// Returns the q-value `Accept` gives `mime`, from the most specific
+/// matching media range.
+fn accept_quality(headers: &Headers, mime: &str) -> f32 {
+    let Some(header) = headers.get("Accept") else {
+        return 1.0;
+    };
+    let Some((kind, subtype)) = mime.trim().split_once('/') else {
+        return 0.0;
+    };
+
+    let mut best: Option<(u8, f32)> = None;
+    for item in header.split(',') {
+        let mut params = item.split(';');
+        let Some((range_kind, range_subtype)) =
+            params.next().and_then(|range| range.trim().split_once('/'))
+        else {
+            continue;
+        };
+        let specificity = match (range_kind.trim(), range_subtype.trim()) {
+            ("*", "*") => 0,
+            (k, "*") if k.eq_ignore_ascii_case(kind) => 1,
+            (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => 2,
+            _ => continue,
+        };
+
+        let mut quality = 1.0;
+        for param in params {
+            if let Some((name, value)) = param.split_once('=')
+                && name.trim().eq_ignore_ascii_case("q")
+            {
+                quality = value.trim().parse::<f32>().unwrap_or(0.0).clamp(0.0, 1.0);
+            }
+        }
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, quality));
+        }
+    }
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+/// How a request's body is delimited.
+enum BodyFraming {
+    Empty,
+    Length(u64),
+    Chunked,
+}
+
+fn body_framing(headers: &Headers) -> Result<BodyFraming, RequestError> {
+    if let Some(coding) = headers.get("Transfer-Encoding") {
+        if !coding.eq_ignore_ascii_case("chunked") {
+            return Err(RequestError::NotImplemented(format!(
+                "Transfer-Encoding {coding:?} is not supported"
+            )));
+        }
+        // A proxy could frame such a body differently than we do.
+        if headers.contains("Content-Length") {
+            return Err(RequestError::BadRequest(
+                "Both Transfer-Encoding and Content-Length are present".into(),
+            ));
+        }
+        return Ok(BodyFraming::Chunked);
+    }
+
+    // Every value, across repeated headers and lists, must be digits only
+    // and agree; a proxy might pick a different one or read a sign.
+    let mut framing = BodyFraming::Empty;
+    for value in headers.get_all("Content-Length") {
+        for length in value.split(',').map(str::trim) {
+            let invalid =
+                || RequestError::InvalidHeader(format!("Invalid Content-Length {value:?}"));
+            if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let length = length.parse().map_err(|_| invalid())?;
+            match framing {
+                BodyFraming::Length(seen) if seen != length => {
+                    return Err(RequestError::InvalidHeader(
+                        "Conflicting Content-Length values".into(),
+                    ));
+                }
+                _ => framing = BodyFraming::Length(length),
+            }
+        }
+    }
+    Ok(framing)
+}
+
+/// Returns whether the body is gzip-compressed, refusing codings other
+/// than `gzip` and `identity`.
+fn content_coding(headers: &Headers) -> Result<bool, RequestError> {
+    let Some(value) = headers.get("Content-Encoding") else {
+        return Ok(false);
+    };
+    let codings: Vec<&str> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"))
+        .collect();
+    match codings[..] {
+        [] => Ok(false),
+        [coding]
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") =>
+        {
+            Ok(true)
+        }
+        _ => Err(RequestError::UnsupportedMediaType(format!(
+            "Content-Encoding {value:?} is not supported"
+        ))),
+    }
+}
+
+fn expects_continue(headers: &Headers) -> bool {
+    headers
+        .get("Expect")
+        .is_some_and(|value| value.eq_ignore_ascii_case("100-continue"))
+}
+
+/// Refuses a `Content-Length` over `config.max_body_size`.
+fn check_declared_size(
+    length: u64,
+    headers: &Headers,
+    config: &ParseConfig,
+) -> Result<(), RequestError> {
+    let Some(max) = config.max_body_size else {
+        return Ok(());
+    };
+    if length <= max {
+        return Ok(());
+    }
+    let msg = format!("Body of {length} bytes exceeds the {max} byte limit");
+    Err(
+        if expects_continue(headers)
+            && config.expect_rejection == ExpectRejection::ExpectationFailed
+        {
+            RequestError::ExpectationFailed(msg)
+        } else {
+            RequestError::BodyTooLarge(msg)
+        },
+    )
+}
+
+fn read_to_end<R: Read>(
+    reader: &mut R,
+    body: &mut Vec<u8>,
+    config: &ParseConfig,
+) -> Result<(), RequestError> {
+    match config.min_body_rate {
+        Some(rate) => read_body_at_rate(reader, body, rate, &*config.clock),
+        None => {
+            reader.read_to_end(body)?;
+            Ok(())
+        }
+    }
+}
+
+/// Reads `reader` to the end like `read_to_end`, failing with
+/// `RequestError::Timeout` once a window passes with too few bytes.
+///
+/// The rate is checked as reads return, so a client that sends nothing at
+/// all is still bounded by the socket's read timeout.
+fn read_body_at_rate<R: Read>(
+    reader: &mut R,
+    body: &mut Vec<u8>,
+    rate: MinBodyRate,
+    clock: &dyn Clock,
+) -> Result<(), RequestError> {
+    let too_slow = || {
+        RequestError::Timeout(format!(
+            "Body arrived slower than {} bytes per {:?}",
+            rate.bytes, rate.window
+        ))
+    };
+    let mut window_start = clock.now();
+    let mut in_window = 0;
+    let mut buf = [0; 8 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if is_timeout(&e) => return Err(too_slow()),
+            Err(e) => return Err(e.into()),
+        };
+        body.extend_from_slice(&buf[..n]);
+        in_window += n;
+        let now = clock.now();
+        if now.saturating_duration_since(window_start) >= rate.window {
+            if in_window < rate.bytes {
+                return Err(too_slow());
+            }
+            window_start = now;
+            in_window = 0;
+        }
+    }
+}
+
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::io::{BufReader, Cursor};
+
+    fn parse(raw: &str) -> Result<Request, RequestError> {
+        Request::parse(&mut Cursor::new(raw.as_bytes()))
+            .map(|request| request.expect("request should be present"))
+    }
+
+    #[test]
+    fn test_parse_from_reader() {
+        let config = ParseConfig::default();
+        let raw = "\r\nGET /search?q=rust&page=2 HTTP/1.1\r\nHost: example.com\r\n\
+                   Accept: text/html\r\n\r\n";
+        let request = Request::parse_from_reader(&mut Cursor::new(raw), &config).unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/search");
+        assert_eq!(request.query.as_deref(), Some("q=rust&page=2"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.get("host"), Some("example.com"));
+        assert_eq!(request.headers.get("Accept"), Some("text/html"));
+        assert!(request.body.is_empty());
+
+        // The reader is left at the start of the next pipelined request.
+        let raw = "POST /echo HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello\
+                   DELETE /items/7 HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut reader = Cursor::new(raw);
+        let first = Request::parse_from_reader(&mut reader, &config).unwrap();
+        assert_eq!(first.method, Method::Post);
+        assert_eq!(first.version, Version::Http10);
+        assert_eq!(first.body, b"hello");
+        let second = Request::parse_from_reader(&mut reader, &config).unwrap();
+        assert_eq!(second.method, Method::Delete);
+        assert_eq!(second.path, "/items/7");
+
+        let error = Request::parse_from_reader(&mut reader, &config).unwrap_err();
+        assert!(
+            matches!(error, RequestError::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof)
+        );
+        let error = Request::parse_from_reader(&mut Cursor::new("GET / HTTP/3.0\r\n\r\n"), &config)
+            .unwrap_err();
+        assert_eq!(error.status_code(), 505);
+    }
+
+    #[test]
+    fn test_missing_host_on_http11_is_rejected() {
+        let result = parse("GET / HTTP/1.1\r\n\r\n");
+        assert!(matches!(result, Err(RequestError::MissingHost)));
+    }
+
+    #[test]
+    fn test_duplicate_host_is_rejected() {
+        let result = parse("GET / HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n");
+        assert!(matches!(result, Err(RequestError::InvalidHeader(_))));
+
+        let request = parse("GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(request.headers.get("Host"), Some("a"));
+    }
+
+    #[test]
+    fn test_strict_request_line() {
+        for raw in [
+            "GET\t/\tHTTP/1.1\r\nHost: x\r\n\r\n",
+            "GET  / HTTP/1.1\r\nHost: x\r\n\r\n",
+            "GET / HTTP/1.1 \r\nHost: x\r\n\r\n",
+            "GET /a\rb HTTP/1.1\r\nHost: x\r\n\r\n",
+            "GET /a\x00 HTTP/1.1\r\nHost: x\r\n\r\n",
+            "get / HTTP/1.1\r\nHost: x\r\n\r\n",
+            "G(T / HTTP/1.1\r\nHost: x\r\n\r\n",
+            "GET / http/1.1\r\nHost: x\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(RequestError::MalformedRequestLine(_))),
+                "{raw:?} was accepted"
+            );
+        }
+
+        let request = parse("PROPFIND /dav HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        assert_eq!(request.method, Method::Other("PROPFIND".into()));
+    }
+
+    #[test]
+    fn test_parse_error_status_codes() {
+        let msg = || String::from("x");
+        let cases = [
+            (RequestError::Io(io::ErrorKind::UnexpectedEof.into()), 500),
+            (RequestError::MalformedRequestLine(msg()), 400),
+            (RequestError::MissingHost, 400),
+            (RequestError::InvalidHeader(msg()), 400),
+            (RequestError::BadRequest(msg()), 400),
+            (RequestError::Timeout(msg()), 408),
+            (RequestError::BodyTooLarge(msg()), 413),
+            (RequestError::UriTooLong(msg()), 414),
+            (RequestError::UnsupportedMediaType(msg()), 415),
+            (RequestError::ExpectationFailed(msg()), 417),
+            (RequestError::HeaderTooLarge(msg()), 431),
+            (RequestError::NotImplemented(msg()), 501),
+            (RequestError::VersionNotSupported(msg()), 505),
+            (RequestError::InvalidEncoding(msg()), 400),
+            (RequestError::UnsupportedCharset(msg()), 415),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status_code(), status, "{error}");
+        }
+
+        let error = parse("GET / HTTP/1.1\r\nContent-Length: x\r\nHost: a\r\n\r\n").unwrap_err();
+        assert!(matches!(error, RequestError::InvalidHeader(_)));
+        assert_eq!(error.detail(), "Invalid Content-Length \"x\"");
+        for raw in [
+            "GET / HTTP/1.1\r\nContent-Length: +5\r\nHost: a\r\n\r\nhello",
+            "GET / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\nHost: a\r\n\r\nhello!",
+            "GET / HTTP/1.1\r\nContent-Length: 5, 6\r\nHost: a\r\n\r\nhello!",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(RequestError::InvalidHeader(_))),
+                "{raw:?}"
+            );
+        }
+        let repeated =
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\nHost: a\r\n\r\nhello";
+        assert_eq!(parse(repeated).unwrap().body, b"hello");
+    }
+
+    #[test]
+    fn test_gzip_body_is_decompressed() {
+        let parse = |body: &[u8], encoding: &str, max_body_size| {
+            let mut raw = format!(
+                "POST / HTTP/1.1\r\nHost: x\r\nContent-Encoding: {encoding}\r\n\
+                 Content-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            raw.extend_from_slice(body);
+            let config = ParseConfig {
+                max_body_size,
+                ..ParseConfig::default()
+            };
+            Request::parse_with(&mut Cursor::new(raw), &config).map(Option::unwrap)
+        };
+
+        let request = parse(&compression::gzip(b"hello, gzip"), "gzip", Some(64)).unwrap();
+        assert_eq!(request.body, b"hello, gzip");
+        assert_eq!(request.headers.get("Content-Encoding"), None);
+        assert_eq!(request.headers.get("Content-Length"), Some("11"));
+
+        // A megabyte of zeros compresses to about a kilobyte; the limit
+        // applies to what it expands to.
+        let bomb = compression::gzip(&vec![0; 1 << 20]);
+        assert!(matches!(
+            parse(&bomb, "gzip", Some(64 * 1024)),
+            Err(RequestError::BodyTooLarge(_))
+        ));
+        assert!(matches!(
+            parse(b"not gzip", "gzip", None),
+            Err(RequestError::BadRequest(_))
+        ));
+
+        let error = parse(b"data", "br", None).unwrap_err();
+        assert!(matches!(error, RequestError::UnsupportedMediaType(_)));
+        assert_eq!(error.status_code(), 415);
+        assert_eq!(parse(b"data", "identity", None).unwrap().body, b"data");
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let parse = |raw: &str, max_body_size| {
+            let config = ParseConfig {
+                max_body_size,
+                ..ParseConfig::default()
+            };
+            Request::parse_with(&mut Cursor::new(raw.as_bytes()), &config)
+        };
+        let chunked = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: t\r\n\r\n";
+        assert_eq!(parse(chunked, None).unwrap().unwrap().body, b"hello world");
+        assert_eq!(
+            parse(chunked, Some(11)).unwrap().unwrap().body,
+            b"hello world"
+        );
+        // The limit applies to the decoded total as it arrives.
+        assert!(matches!(
+            parse(chunked, Some(10)),
+            Err(RequestError::BodyTooLarge(_))
+        ));
+
+        let truncated = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel";
+        assert!(matches!(
+            parse(truncated, None),
+            Err(RequestError::BadRequest(_))
+        ));
+        // Nothing a front end might frame differently is accepted.
+        let head = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n";
+        for body in [
+            "5\r\nhelloXX0\r\n\r\n",
+            "+5\r\nhello\r\n0\r\n\r\n",
+            "\r\n0\r\n\r\n",
+        ] {
+            assert!(
+                matches!(
+                    parse(&format!("{head}{body}"), None),
+                    Err(RequestError::BadRequest(_))
+                ),
+                "{body:?}"
+            );
+        }
+        // Size and trailer lines are held to the header line limit.
+        let config = ParseConfig {
+            max_single_header_bytes: Some(64),
+            ..ParseConfig::default()
+        };
+        let long_size = format!("{head}5;{}\r\nhello\r\n0\r\n\r\n", "x".repeat(100));
+        let long_trailer = format!("{head}0\r\nX-T: {}\r\n\r\n", "x".repeat(100));
+        for raw in [long_size, long_trailer] {
+            assert!(matches!(
+                Request::parse_with(&mut Cursor::new(raw.as_bytes()), &config),
+                Err(RequestError::BadRequest(_))
+            ));
+        }
+
+        let gzip = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\r\n";
+        assert!(matches!(
+            parse(gzip, None),
+            Err(RequestError::NotImplemented(_))
+        ));
+        let both = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\
+                    Content-Length: 3\r\n\r\n0\r\n\r\n";
+        assert!(matches!(
+            parse(both, None),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_path_segments() {
+        let segments = |target: &str| -> Vec<String> {
+            Request::new(Method::Get, target)
+                .path_segments()
+                .map(Cow::into_owned)
+                .collect()
+        };
+        assert!(segments("/").is_empty());
+        assert_eq!(segments("/users/42/posts"), ["users", "42", "posts"]);
+        assert_eq!(
+            segments("//files/my%20report%2Fdraft/caf%C3%A9/?x=1"),
+            ["files", "my report/draft", "café"]
+        );
+        // Malformed escapes and non-UTF-8 results are left alone.
+        assert_eq!(segments("/100%/a%zz/%ff"), ["100%", "a%zz", "%ff"]);
+        assert_eq!(segments("/%+1/%-1"), ["%+1", "%-1"]);
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        let config = ParseConfig {
+            max_body_size: Some(4),
+            ..ParseConfig::default()
+        };
+        let parse = |raw: &str, config: &ParseConfig| {
+            Request::parse_with(&mut Cursor::new(raw.as_bytes()), config)
+        };
+        let oversized = "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello";
+        assert!(matches!(
+            parse(oversized, &config),
+            Err(RequestError::BodyTooLarge(_))
+        ));
+
+        let expecting =
+            "POST / HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n";
+        assert!(matches!(
+            parse(expecting, &config),
+            Err(RequestError::ExpectationFailed(_))
+        ));
+        let config_413 = ParseConfig {
+            expect_rejection: ExpectRejection::ContentTooLarge,
+            ..config.clone()
+        };
+        assert!(matches!(
+            parse(expecting, &config_413),
+            Err(RequestError::BodyTooLarge(_))
+        ));
+
+        let fits = "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nhell";
+        assert_eq!(parse(fits, &config).unwrap().unwrap().body, b"hell");
+    }
+
+    #[test]
+    fn test_min_body_rate() {
+        /// Yields one byte per read, pausing before each.
+        struct Trickle(Cursor<Vec<u8>>, Duration);
+
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                std::thread::sleep(self.1);
+                let end = buf.len().min(1);
+                self.0.read(&mut buf[..end])
+            }
+        }
+
+        let raw = b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello";
+        let config = ParseConfig {
+            min_body_rate: Some(MinBodyRate::new(2, Duration::from_millis(20))),
+            ..ParseConfig::default()
+        };
+
+        let mut slow = BufReader::new(Trickle(
+            Cursor::new(raw.to_vec()),
+            Duration::from_millis(25),
+        ));
+        assert!(matches!(
+            Request::parse_with(&mut slow, &config),
+            Err(RequestError::Timeout(_))
+        ));
+
+        let mut fast = BufReader::new(Trickle(Cursor::new(raw.to_vec()), Duration::ZERO));
+        let request = Request::parse_with(&mut fast, &config).unwrap().unwrap();
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn test_min_body_rate_reads_the_clock() {
+        /// Yields one byte per read, moving the clock forward before each.
+        struct Ticking(Cursor<Vec<u8>>, Arc<MockClock>, Duration);
+
+        impl Read for Ticking {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1.advance(self.2);
+                let end = buf.len().min(1);
+                self.0.read(&mut buf[..end])
+            }
+        }
+
+        let body = b"hello".to_vec();
+        let clock = Arc::new(MockClock::new());
+        let config = ParseConfig {
+            min_body_rate: Some(MinBodyRate::new(2, Duration::from_secs(60))),
+            clock: Arc::clone(&clock) as _,
+            ..ParseConfig::default()
+        };
+
+        let mut slow = Ticking(
+            Cursor::new(body.clone()),
+            Arc::clone(&clock),
+            Duration::from_secs(61),
+        );
+        assert!(matches!(
+            read_to_end(&mut slow, &mut Vec::new(), &config),
+            Err(RequestError::Timeout(_))
+        ));
+
+        let mut fast = Ticking(
+            Cursor::new(body),
+            Arc::clone(&clock),
+            Duration::from_secs(20),
+        );
+        let mut read = Vec::new();
+        read_to_end(&mut fast, &mut read, &config).unwrap();
+        assert_eq!(read, b"hello");
+    }
+
+    #[test]
+    fn test_save_body_to_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("hello-upload-{}", std::process::id()));
+        let mut req = with_body("application/octet-stream", &[7; 100_000]);
+
+        assert_eq!(req.save_body_to(&path, 100_000).unwrap(), 100_000);
+        assert_eq!(fs::read(&path).unwrap(), [7; 100_000]);
+        assert!(req.body.is_empty());
+        fs::remove_file(&path).unwrap();
+
+        let mut req = with_body("application/octet-stream", &[7; 1001]);
+        assert!(matches!(
+            req.save_body_to(&path, 1000),
+            Err(RequestError::BodyTooLarge(_))
+        ));
+        assert!(!path.exists());
+        assert_eq!(req.body.len(), 1001);
+
+        let missing = dir.join("hello-no-such-dir").join("upload");
+        assert!(matches!(
+            req.save_body_to(&missing, 2000),
+            Err(RequestError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_save_streamed_body_to_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("hello-streamed-upload-{}", std::process::id()));
+        let streamed = |body: &'static [u8]| {
+            let mut req = Request::new(Method::Put, "/upload");
+            req.body_reader = Some(BodyReader::new(Box::new(body), None));
+            req
+        };
+
+        let mut req = streamed(&[7; 200_000]);
+        assert_eq!(req.save_body_to(&path, 200_000).unwrap(), 200_000);
+        assert_eq!(fs::read(&path).unwrap(), [7; 200_000]);
+
+        // An existing file is neither overwritten nor removed.
+        let mut req = streamed(b"new");
+        assert!(matches!(
+            req.save_body_to(&path, 100),
+            Err(RequestError::Io(ref e)) if e.kind() == io::ErrorKind::AlreadyExists
+        ));
+        assert_eq!(fs::read(&path).unwrap().len(), 200_000);
+        fs::remove_file(&path).unwrap();
+
+        // The cap is enforced as the body streams in.
+        let mut req = streamed(&[7; 200_000]);
+        assert!(matches!(
+            req.save_body_to(&path, 100_000),
+            Err(RequestError::BodyTooLarge(_))
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_content_negotiation() {
+        let accepting = |accept: Option<&str>| {
+            let mut req = Request::new(Method::Get, "/");
+            if let Some(accept) = accept {
+                req.headers.insert("Accept", accept);
+            }
+            req
+        };
+
+        let req = accepting(Some("application/json"));
+        assert!(req.accepts("application/json"));
+        assert!(!req.accepts("text/html"));
+        assert_eq!(
+            req.preferred(&["text/html", "application/json"]),
+            Some("application/json")
+        );
+
+        let req = accepting(Some("text/*, */*;q=0.1"));
+        assert!(req.accepts("text/plain"));
+        assert!(req.accepts("image/png"));
+        assert_eq!(
+            req.preferred(&["application/json", "text/html"]),
+            Some("text/html")
+        );
+
+        let req = accepting(Some("text/html;q=0.5, application/json;q=0.9, image/*;q=0"));
+        assert_eq!(
+            req.preferred(&["text/html", "application/json"]),
+            Some("application/json")
+        );
+        assert!(!req.accepts("image/png"));
+        assert_eq!(req.preferred(&["image/png"]), None);
+
+        // Without `Accept`, the server's first choice wins.
+        let req = accepting(None);
+        assert!(req.accepts("application/xml"));
+        assert_eq!(
+            req.preferred(&["text/html", "application/json"]),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn test_check_precondition() {
+        let current = ETag::strong("v2");
+        let with = |method: Method, name: &str, value: &str| {
+            let mut req = Request::new(method, "/doc");
+            req.headers.insert(name, value);
+            req
+        };
+
+        let req = with(Method::Put, "If-Match", "\"v1\", \"v2\"");
+        assert!(req.check_precondition(Some(&current)).is_none());
+
+        let req = with(Method::Put, "If-Match", "\"v1\"");
+        let response = req.check_precondition(Some(&current)).unwrap();
+        assert_eq!(response.status, 412);
+        // A weak tag never passes the strong comparison.
+        let req = with(Method::Put, "If-Match", "W/\"v2\"");
+        assert_eq!(req.check_precondition(Some(&current)).unwrap().status, 412);
+        let req = with(Method::Put, "If-Match", "*");
+        assert!(req.check_precondition(Some(&current)).is_none());
+        assert_eq!(req.check_precondition(None).unwrap().status, 412);
+
+        // `If-None-Match: *` makes a PUT create-only.
+        let req = with(Method::Put, "If-None-Match", "*");
+        assert!(req.check_precondition(None).is_none());
+        assert_eq!(req.check_precondition(Some(&current)).unwrap().status, 412);
+        let req = with(Method::Get, "If-None-Match", "W/\"v2\"");
+        let response = req.check_precondition(Some(&current)).unwrap();
+        assert_eq!(response.status, 304);
+        assert_eq!(response.headers.get("ETag"), Some("\"v2\""));
+
+        assert!(
+            Request::new(Method::Put, "/doc")
+                .check_precondition(None)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_version_tokens() {
+        assert_eq!("HTTP/1.2".parse::<Version>().unwrap(), Version::Http11);
+        assert!(matches!(
+            "HTTP/2.0".parse::<Version>(),
+            Err(RequestError::VersionNotSupported(_))
+        ));
+        assert!(matches!(
+            "HTTP/1.10".parse::<Version>(),
+            Err(RequestError::MalformedRequestLine(_))
+        ));
+        assert!(matches!(
+            parse("GET /\r\n\r\n"),
+            Err(RequestError::VersionNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_bad_request() {
+        let parse_bytes = |raw: &[u8]| Request::parse(&mut Cursor::new(raw));
+        assert!(matches!(
+            parse_bytes(b"GET /caf\xc3 HTTP/1.1\r\nHost: x\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse_bytes(b"GET /caf\xc3\xa9 HTTP/1.1\r\nHost: x\r\n\r\n"),
+            Err(RequestError::MalformedRequestLine(_))
+        ));
+        assert!(matches!(
+            parse_bytes(b"GET / HTTP/1.1\r\nHost: x\r\nX-Name: \xff\xfe\r\n\r\n"),
+            Err(RequestError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse_bytes("GET / HTTP/1.1\r\nHost: x\r\nX-Náme: a\r\n\r\n".as_bytes()),
+            Err(RequestError::InvalidHeader(_))
+        ));
+
+        let request = parse("GET / HTTP/1.1\r\nHost: x\r\nX-Name: café\r\n\r\n").unwrap();
+        assert_eq!(request.headers.get("X-Name"), Some("café"));
+    }
+
+    fn with_body(content_type: &str, body: &[u8]) -> Request {
+        let mut req = Request::new(Method::Post, "/");
+        req.headers.insert("Content-Type", content_type);
+        req.body = body.to_vec();
+        req
+    }
+
+    #[test]
+    fn test_text_body_charsets() {
+        let req = with_body("text/plain; charset=utf-8", "caf\u{e9} \u{2713}".as_bytes());
+        assert_eq!(req.text().unwrap(), "caf\u{e9} \u{2713}");
+
+        let req = with_body("text/plain; charset=\"ISO-8859-1\"", b"caf\xe9");
+        assert_eq!(req.text().unwrap(), "caf\u{e9}");
+
+        let req = with_body("text/plain", b"caf\xe9");
+        assert!(matches!(req.text(), Err(RequestError::InvalidEncoding(_))));
+
+        let req = with_body("text/plain; charset=koi8-r", b"x");
+        assert!(matches!(
+            req.text(),
+            Err(RequestError::UnsupportedCharset(charset)) if charset == "koi8-r"
+        ));
+    }
+
+    #[test]
+    fn test_host_is_parsed() {
+        let request = parse("GET /index.html?x=1 HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/index.html");
+        assert_eq!(request.query.as_deref(), Some("x=1"));
+        assert_eq!(request.host(), Some("example.com"));
+    }
+
+    #[test]
+    fn test_real_ip_from_trusted_proxy() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let trusted = ["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+
+        let ip = resolve_real_ip(proxy, Some("203.0.113.9, 10.0.0.2"), &trusted);
+        assert_eq!(ip, "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        // A client-supplied entry to the left of the real client is ignored.
+        let ip = resolve_real_ip(proxy, Some("1.2.3.4, 203.0.113.9"), &trusted);
+        assert_eq!(ip, "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        assert_eq!(resolve_real_ip(proxy, None, &trusted), proxy);
+    }
+
+    #[test]
+    fn test_real_ip_from_untrusted_peer() {
+        let peer: IpAddr = "198.51.100.7".parse().unwrap();
+        let trusted = ["10.0.0.1".parse().unwrap()];
+        assert_eq!(resolve_real_ip(peer, Some("1.2.3.4"), &trusted), peer);
+    }
+
+    #[test]
+    fn test_immediate_close_is_not_an_error() {
+        let result = Request::parse(&mut Cursor::new(Vec::new()));
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[test]
+    fn test_partial_request_line_is_rejected() {
+        let result = Request::parse(&mut Cursor::new(b"GET /ind".to_vec()));
+        assert!(matches!(result, Err(RequestError::BadRequest(_))));
+
+        let result = parse("GET / HTTP/1.1\r\nHost: exa");
+        assert!(matches!(result, Err(RequestError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_body_and_pipelining() {
+        let raw = "\r\nPOST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhelloGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut reader = Cursor::new(raw.as_bytes());
+
+        let first = Request::parse(&mut reader).unwrap().unwrap();
+        assert_eq!(first.body, b"hello");
+        let second = Request::parse(&mut reader).unwrap().unwrap();
+        assert_eq!(second.path, "/b");
+        assert!(Request::parse(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_keep_alive_defaults() {
+        let request = parse("GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        assert!(request.wants_keep_alive());
+        let request = parse("GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n").unwrap();
+        assert!(!request.wants_keep_alive());
+        let request = parse("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(!request.wants_keep_alive());
+        let request = parse("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(request.wants_keep_alive());
+    }
+
+    #[test]
+    fn test_http10_without_host_is_allowed() {
+        let request = parse("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(request.version, Version::Http10);
+        assert_eq!(request.host(), None);
+    }
+
+    /// A xorshift64 generator, so fuzz failures reproduce from the seed.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n.max(1) as u64) as usize
+        }
+    }
+
+    fn fuzz_seeds() -> Vec<Vec<u8>> {
+        let mut seeds: Vec<Vec<u8>> = [
+            "GET /a/b?c=d&e=%20f HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n",
+            "POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello",
+            "POST /up HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5;ext=1\r\nhello\r\n0\r\nTrailer: t\r\n\r\n",
+            "PUT /x HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\nabc",
+            "GET http://example.com:80/p HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            "OPTIONS * HTTP/1.1\r\nHost: x\r\n\r\n",
+        ]
+        .iter()
+        .map(|seed| seed.as_bytes().to_vec())
+        .collect();
+
+        let mut gzipped =
+            b"POST /z HTTP/1.1\r\nHost: x\r\nContent-Encoding: gzip\r\nContent-Length: ".to_vec();
+        let body = crate::compression::gzip(b"hello hello hello hello");
+        gzipped.extend_from_slice(format!("{}\r\n\r\n", body.len()).as_bytes());
+        gzipped.extend_from_slice(&body);
+        seeds.push(gzipped);
+        seeds
+    }
+
+    const FUZZ_TOKENS: &[&[u8]] = &[
+        b"\r\n",
+        b"\n",
+        b":",
+        b" ",
+        b"\t",
+        b"%",
+        b"%zz",
+        b"?",
+        b"-1",
+        b"0",
+        b"18446744073709551616",
+        b"ffffffffffffffff",
+        b"\xc3\xa9",
+        b"\xe2\x82",
+        b"\xff",
+        b"\0",
+        b"chunked",
+        b"gzip",
+        b"Content-Length: ",
+        b"Transfer-Encoding: ",
+        b"HTTP/1.1",
+    ];
+
+    fn mutate(rng: &mut XorShift, mut input: Vec<u8>) -> Vec<u8> {
+        for _ in 0..=rng.below(4) {
+            let at = rng.below(input.len() + 1);
+            match rng.below(5) {
+                0 if !input.is_empty() => {
+                    let at = at.min(input.len() - 1);
+                    input[at] = rng.next() as u8;
+                }
+                1 => input.truncate(at),
+                2 => {
+                    let token = FUZZ_TOKENS[rng.below(FUZZ_TOKENS.len())];
+                    input.splice(at..at, token.iter().copied());
+                }
+                3 => {
+                    let end = (at + rng.below(16)).min(input.len());
+                    input.drain(at..end);
+                }
+                _ => {
+                    let end = (at + rng.below(32)).min(input.len());
+                    let copy = input[at..end].to_vec();
+                    input.splice(at..at, copy);
+                }
+            }
+        }
+        input
+    }
+
+    #[test]
+    fn test_parser_never_panics_on_malformed_input() {
+        let limited = ParseConfig {
+            max_uri_length: Some(64),
+            max_single_header_bytes: Some(128),
+            max_body_size: Some(1024),
+            ..ParseConfig::default()
+        };
+        let configs = [ParseConfig::default(), limited];
+        let seeds = fuzz_seeds();
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+
+        for case in 0..20_000 {
+            let input = match case % 4 {
+                0 => (0..rng.below(96)).map(|_| rng.next() as u8).collect(),
+                _ => {
+                    let seed = seeds[rng.below(seeds.len())].clone();
+                    mutate(&mut rng, seed)
+                }
+            };
+            let config = &configs[rng.below(configs.len())];
+            let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let mut reader = Cursor::new(&input[..]);
+                // Parse pipelined requests until the input is used up or
+                // rejected; each round consumes input, so this ends.
+                loop {
+                    match Request::parse_from_reader(&mut reader, config) {
+                        Ok(_) => continue,
+                        Err(e) => break e.status_code(),
+                    }
+                }
+            }));
+            match outcome {
+                Ok(status) => assert!((400..600).contains(&status), "{status} for {input:?}"),
+                Err(_) => panic!("parser panicked on {:?}", String::from_utf8_lossy(&input)),
+            }
+        }
+    }
+}
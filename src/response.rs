@@ -0,0 +1,955 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    sync::{Arc, Mutex, mpsc},
+};
+
+use crate::{error_pages, headers::Headers};
+
+/// Produces a chunked body by writing to a `ChunkWriter`.
+type StreamFn = Arc<dyn Fn(&mut ChunkWriter<'_>) -> io::Result<()> + Send + Sync>;
+
+/// An HTTP response ready to be written to a client.
+#[derive(Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Set for error responses generated by the framework, so the server
+    /// can swap in a configured error page.
+    pub(crate) error: Option<String>,
+    /// Set by `Response::chunked` and `with_body_reader`; replaces `body`
+    /// when writing.
+    stream: Option<StreamFn>,
+    /// Known size of the `stream` output, sent as `Content-Length` instead
+    /// of chunking it.
+    stream_length: Option<u64>,
+    /// Set by `strip_body`: the framing headers describe a body that is not
+    /// sent.
+    head_only: bool,
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .field("error", &self.error)
+            .field("streamed", &self.stream.is_some())
+            .field("stream_length", &self.stream_length)
+            .field("head_only", &self.head_only)
+            .finish()
+    }
+}
+
+impl Response {
+    /// Create an empty response with the given status code.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            error: None,
+            stream: None,
+            stream_length: None,
+            head_only: false,
+        }
+    }
+
+    /// Create an empty `200 OK` response.
+    pub fn ok() -> Response {
+        Response::new(200)
+    }
+
+    /// Create a `201 Created` response pointing at the new resource.
+    pub fn created(location: impl Into<String>) -> Response {
+        Response::new(201).with_header("Location", location)
+    }
+
+    /// Create a `301 Moved Permanently` to the `https` URL for `host` and
+    /// `target`, the path plus any query string.
+    ///
+    /// A port on `host` is dropped, so the client uses the default HTTPS
+    /// port.
+    pub fn redirect_https(host: &str, target: &str) -> Response {
+        let host = match host.rsplit_once(':') {
+            Some((name, port))
+                if !port.is_empty()
+                    && port.bytes().all(|b| b.is_ascii_digit())
+                    && (!name.starts_with('[') || name.ends_with(']')) =>
+            {
+                name
+            }
+            _ => host,
+        };
+        Response::new(301).with_header("Location", format!("https://{host}{target}"))
+    }
+
+    /// Create a `204 No Content` response.
+    pub fn no_content() -> Response {
+        Response::new(204)
+    }
+
+    /// Create a `304 Not Modified` response.
+    ///
+    /// Validators such as `ETag` should be added by the caller, mirroring
+    /// what a `200` would have sent.
+    pub fn not_modified() -> Response {
+        Response::new(304)
+    }
+
+    /// Create a `412 Precondition Failed` error response, for a conditional
+    /// request whose validator no longer matches.
+    pub fn precondition_failed() -> Response {
+        Response::error(
+            412,
+            "The resource has changed since the request's validator was issued.",
+        )
+    }
+
+    /// Create a `404 Not Found` error response.
+    pub fn not_found() -> Response {
+        Response::error(404, "The requested resource was not found.")
+    }
+
+    /// Create an error response rendered with the built-in error page.
+    ///
+    /// The server replaces the body with a custom page when
+    /// `ServerConfig::error_pages` has one for `status`.
+    pub fn error(status: u16, message: impl Into<String>) -> Response {
+        let message = message.into();
+        let mut response = Response::html(status, error_pages::default_page(status, &message));
+        response.error = Some(message);
+        response
+    }
+
+    /// Create a response with a `text/plain` body.
+    pub fn text(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// Create a response with a `text/html` body.
+    pub fn html(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// Create a response whose body is generated while it is being sent.
+    ///
+    /// `write` runs once the headers are on the wire; every `write` call on
+    /// the `ChunkWriter` goes out as one `Transfer-Encoding: chunked` frame,
+    /// so clients see data as soon as it is produced.
+    pub fn chunked<F>(status: u16, write: F) -> Response
+    where
+        F: Fn(&mut ChunkWriter<'_>) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let mut response = Response::new(status);
+        response.stream = Some(Arc::new(write));
+        response
+    }
+
+    /// Create a `text/event-stream` (server-sent events) response.
+    ///
+    /// `events` runs while the connection stays open and pushes events with
+    /// `EventStream::send_event`; the stream ends when it returns.
+    pub fn sse<F>(events: F) -> Response
+    where
+        F: Fn(&mut EventStream<'_, '_>) -> io::Result<()> + Send + Sync + 'static,
+    {
+        Response::chunked(200, move |out| events(&mut EventStream { out }))
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+    }
+
+    /// Create a chunked `200 OK` response fed from another thread.
+    ///
+    /// Once the handler returns the response, the server writes each chunk
+    /// given to the `BodySender` as it arrives, ending the body when every
+    /// sender has been dropped. The handler's worker is busy writing until
+    /// then. Clones of the response share the one stream.
+    pub fn streaming() -> (Response, BodySender) {
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        let receiver = Mutex::new(Some(receiver));
+        let response = Response::chunked(200, move |out| {
+            let receiver = receiver.lock().expect("Mutex poisoned").take();
+            for chunk in receiver.iter().flatten() {
+                out.write_all(&chunk)?;
+            }
+            Ok(())
+        });
+        (response, BodySender { sender })
+    }
+
+    /// Sends the body by copying from `reader` while the response is written.
+    ///
+    /// With a known `length` the body goes out with that `Content-Length`;
+    /// the reader must produce exactly that many bytes, and writing fails
+    /// with `UnexpectedEof` if it ends early. Without one it is sent
+    /// chunked. The reader is consumed the first time the response is
+    /// written, so clones of the response share it.
+    pub fn with_body_reader(
+        mut self,
+        reader: impl Read + Send + 'static,
+        length: Option<u64>,
+    ) -> Response {
+        let reader: Mutex<Option<Box<dyn Read + Send>>> = Mutex::new(Some(Box::new(reader)));
+        self.body.clear();
+        self.stream = Some(Arc::new(move |out| {
+            let reader = reader.lock().expect("Mutex poisoned").take();
+            let Some(mut reader) = reader else {
+                return Ok(());
+            };
+            let copied = io::copy(&mut reader, out)?;
+            // A short body under a full `Content-Length` would leave the
+            // client reading the next response as the rest of this one.
+            match length {
+                Some(length) if copied != length => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("body ended after {copied} of {length} bytes"),
+                )),
+                _ => Ok(()),
+            }
+        }));
+        self.stream_length = length;
+        self
+    }
+
+    /// Returns `true` if the body will be sent with chunked encoding: built
+    /// with `Response::chunked`, or from a reader of unknown length.
+    pub fn is_chunked(&self) -> bool {
+        self.stream.is_some() && self.stream_length.is_none()
+    }
+
+    /// Returns `true` if the body is produced while writing rather than held
+    /// in `body`.
+    pub(crate) fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Runs a chunked response's generator to completion and stores the
+    /// output in `body`, for clients that cannot receive chunked bodies.
+    pub(crate) fn buffer_chunks(&mut self) -> io::Result<()> {
+        let Some(stream) = self.stream.take() else {
+            return Ok(());
+        };
+        self.stream_length = None;
+        let mut body = Vec::new();
+        let mut raw = ChunkWriter {
+            inner: &mut body,
+            framed: false,
+        };
+        stream(&mut raw)?;
+        self.body = body;
+        Ok(())
+    }
+
+    /// Drops the body for a `HEAD` reply, keeping the `Content-Length` a
+    /// `GET` would have sent when it is known.
+    ///
+    /// A chunked reply gets no framing headers at all, since a bodiless
+    /// response must not carry `Transfer-Encoding`.
+    pub(crate) fn strip_body(&mut self) {
+        if let Some(length) = self.stream_length.take() {
+            self.headers.insert("Content-Length", length.to_string());
+        } else if self.stream.is_none() && !self.headers.contains("Content-Length") {
+            self.headers
+                .insert("Content-Length", self.body.len().to_string());
+        }
+        self.stream = None;
+        self.body.clear();
+        self.head_only = true;
+    }
+
+    /// Sets a header, replacing any existing value.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Replaces the response body.
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Reads and parses one response, framed by `Content-Length`, chunked
+    /// encoding, or connection close. Replies to `HEAD` have no body to
+    /// read.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn read_from<R: BufRead>(reader: &mut R, has_body: bool) -> io::Result<Response> {
+        let mut response = Response::read_head(reader)?;
+        response.read_body_from(reader, has_body)?;
+        Ok(response)
+    }
+
+    /// Reads the body following a head from `read_head` into `body`.
+    pub(crate) fn read_body_from<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        has_body: bool,
+    ) -> io::Result<()> {
+        match self.framing(has_body)? {
+            Framing::Empty => {}
+            Framing::Length(length) => {
+                // Grow with what arrives rather than trusting the peer's
+                // length up front.
+                reader.take(length).read_to_end(&mut self.body)?;
+                if (self.body.len() as u64) < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            Framing::Chunked => {
+                ChunkedReader::new(reader).read_to_end(&mut self.body)?;
+            }
+            Framing::Close => {
+                reader.read_to_end(&mut self.body)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a response's status line and headers, leaving `reader` at the
+    /// start of the body.
+    pub(crate) fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Response> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| invalid_data("malformed status line"))?;
+
+        let mut response = Response::new(status);
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| invalid_data("malformed header"))?;
+            response.headers.append(name.trim(), value.trim());
+        }
+        Ok(response)
+    }
+
+    /// Works out how the body after a head from `read_head` is delimited.
+    pub(crate) fn framing(&self, has_body: bool) -> io::Result<Framing> {
+        if !has_body || forbids_body(self.status) {
+            return Ok(Framing::Empty);
+        }
+        let chunked = self
+            .headers
+            .get("Transfer-Encoding")
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+        if chunked {
+            return Ok(Framing::Chunked);
+        }
+        match self.headers.get("Content-Length") {
+            Some(length) => length
+                .parse()
+                .map(Framing::Length)
+                .map_err(|_| invalid_data("malformed Content-Length")),
+            None => Ok(Framing::Close),
+        }
+    }
+
+    /// Serializes the status line, headers, and body to `writer`.
+    ///
+    /// Responses that cannot have a body (1xx, `204`, and `304`) are sent
+    /// with neither a body nor framing headers, and a `HEAD` reply without
+    /// `Transfer-Encoding`; otherwise an empty body is framed as
+    /// `Content-Length: 0` so clients do not wait for more.
+    ///
+    /// A `Content-Length` header is added unless the response already carries
+    /// one or a `Transfer-Encoding`. Chunked responses get
+    /// `Transfer-Encoding: chunked` instead, and streamed bodies of known
+    /// length always use their own length.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // One buffer for the whole head, so it costs a single allocation
+        // and a single write.
+        let mut head = Vec::with_capacity(256);
+        self.encode_head(&mut head);
+        if forbids_body(self.status) || self.head_only {
+            writer.write_all(&head)?;
+            return writer.flush();
+        }
+        writer.write_all(&head)?;
+        match &self.stream {
+            Some(stream) if self.stream_length.is_some() => {
+                let mut raw = ChunkWriter {
+                    inner: writer,
+                    framed: false,
+                };
+                stream(&mut raw)?;
+                raw.flush()
+            }
+            Some(stream) => {
+                writer.flush()?;
+                let mut chunks = ChunkWriter::new(writer);
+                // On failure the terminator is withheld, so the caller must
+                // close the connection rather than reuse it.
+                stream(&mut chunks)?;
+                chunks.finish()
+            }
+            None => {
+                writer.write_all(&self.body)?;
+                writer.flush()
+            }
+        }
+    }
+
+    /// Appends the status line, headers, and blank line to `head`.
+    fn encode_head(&self, head: &mut Vec<u8>) {
+        // Writing to a `Vec` cannot fail.
+        let _ = write!(
+            head,
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+        let bodiless = forbids_body(self.status);
+        for (name, value) in self.headers.iter() {
+            let is_length = name.eq_ignore_ascii_case("Content-Length");
+            let is_encoding = name.eq_ignore_ascii_case("Transfer-Encoding");
+            if (bodiless && (is_length || is_encoding))
+                || (self.head_only && is_encoding)
+                || (self.stream.is_some() && is_length)
+            {
+                continue;
+            }
+            for part in [name.as_bytes(), b": ", value.as_bytes(), b"\r\n"] {
+                head.extend_from_slice(part);
+            }
+        }
+        if bodiless || self.head_only {
+            head.extend_from_slice(b"\r\n");
+            return;
+        }
+        match (&self.stream, self.stream_length) {
+            (Some(_), Some(length)) => {
+                let _ = write!(head, "Content-Length: {length}\r\n");
+            }
+            (Some(_), None) => head.extend_from_slice(b"Transfer-Encoding: chunked\r\n"),
+            (None, _)
+                if !self.headers.contains("Content-Length")
+                    && !self.headers.contains("Transfer-Encoding") =>
+            {
+                let _ = write!(head, "Content-Length: {}\r\n", self.body.len());
+            }
+            (None, _) => {}
+        }
+        head.extend_from_slice(b"\r\n");
+    }
+
+    /// Appends the whole response to `out`, byte for byte as `write_to`
+    /// would send it.
+    ///
+    /// For a buffered body the size is reserved up front, so the response
+    /// lands in one allocation, ready for a single write; the server uses
+    /// this for small responses. Streamed bodies go through `write_to`.
+    ///
+    /// # Errors
+    ///
+    /// Only a streamed body's generator can fail.
+    pub fn write_small(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.is_streamed() {
+            return self.write_to(out);
+        }
+        // The status line and framing headers are short; 64 bytes covers
+        // them.
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len() + 4)
+            .sum();
+        out.reserve(64 + headers + self.body.len());
+        self.encode_head(out);
+        if !forbids_body(self.status) && !self.head_only {
+            out.extend_from_slice(&self.body);
+        }
+        Ok(())
+    }
+}
+
+/// How a received response's body is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// No body follows.
+    Empty,
+    Length(u64),
+    Chunked,
+    /// The body runs until the connection closes.
+    Close,
+}
+
+/// Longest chunk-size or trailer line `ChunkedReader::new` accepts.
+const CHUNK_LINE_LIMIT: usize = 8 * 1024;
+
+/// Decodes a chunked body as it is read, ending after the final chunk and
+/// any trailers.
+pub(crate) struct ChunkedReader<R> {
+    inner: R,
+    /// Bytes left in the current chunk.
+    remaining: usize,
+    done: bool,
+    /// Longest chunk-size or trailer line, without its CRLF.
+    line_limit: Option<usize>,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub(crate) fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader::with_line_limit(inner, Some(CHUNK_LINE_LIMIT))
+    }
+
+    /// Like `new`, but rejects chunk-size and trailer lines longer than
+    /// `line_limit`; `None` allows any length.
+    pub(crate) fn with_line_limit(inner: R, line_limit: Option<usize>) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+            line_limit,
+        }
+    }
+
+    /// Reads one line without its line ending, reading no more than the
+    /// limit allows.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let read = match self.line_limit {
+            // Room for the limit plus CRLF; one byte more means it was exceeded.
+            Some(limit) => (&mut self.inner)
+                .take(limit as u64 + 3)
+                .read_until(b'\n', &mut line)?,
+            None => self.inner.read_until(b'\n', &mut line)?,
+        };
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(limit) = self.line_limit
+            && line.trim_ascii_end().len() > limit
+        {
+            return Err(invalid_data("chunk line too long"));
+        }
+        if line.pop() != Some(b'\n') {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(line)
+    }
+}
+
+/// Parses the size at the start of a chunk-size line: hex digits only, so
+/// nothing another parser might read differently, such as a sign.
+fn chunk_size(line: &[u8]) -> io::Result<usize> {
+    let size = line
+        .split(|&b| b == b';')
+        .next()
+        .unwrap_or_default()
+        .trim_ascii();
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid_data("malformed chunk size"));
+    }
+    // All ASCII, so this cannot fail.
+    let size = str::from_utf8(size).map_err(|_| invalid_data("malformed chunk size"))?;
+    usize::from_str_radix(size, 16).map_err(|_| invalid_data("chunk size too large"))
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let line = self.read_line()?;
+            self.remaining = chunk_size(&line)?;
+            if self.remaining == 0 {
+                while !self.read_line()?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let end = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..end])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n;
+        if self.remaining == 0 {
+            let mut crlf = [0; 2];
+            self.inner.read_exact(&mut crlf)?;
+            if crlf != *b"\r\n" {
+                return Err(invalid_data("chunk not followed by CRLF"));
+            }
+        }
+        Ok(n)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Returns `true` for statuses whose responses never have a body.
+pub(crate) fn forbids_body(status: u16) -> bool {
+    matches!(status, 100..=199 | 204 | 304)
+}
+
+/// Writes a chunked (`Transfer-Encoding: chunked`) body.
+///
+/// Each `write` is sent and flushed as its own chunk. The terminating
+/// zero-length chunk is written only by `finish`. A writer dropped without
+/// it leaves the body unterminated, so the client sees it as truncated
+/// rather than complete.
+pub struct ChunkWriter<'a> {
+    inner: &'a mut dyn Write,
+    /// `false` when buffering; writes then pass through unframed.
+    framed: bool,
+}
+
+impl<'a> ChunkWriter<'a> {
+    /// Wraps `inner`, which should already have the response headers.
+    pub fn new(inner: &'a mut dyn Write) -> ChunkWriter<'a> {
+        ChunkWriter {
+            inner,
+            framed: true,
+        }
+    }
+
+    /// Writes the final zero-length chunk.
+    pub fn finish(self) -> io::Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.framed {
+            return self.inner.write(buf);
+        }
+        // An empty chunk would end the body early.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        self.inner.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Feeds the body of a response made with `Response::streaming`.
+#[derive(Debug, Clone)]
+pub struct BodySender {
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl BodySender {
+    /// Queues `chunk` to be sent as the next piece of the body.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::BrokenPipe` once the response is gone: the
+    /// client disconnected or the response was dropped unsent.
+    pub fn send(&self, chunk: impl Into<Vec<u8>>) -> io::Result<()> {
+        self.sender
+            .send(chunk.into())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "response body closed"))
+    }
+}
+
+/// Returns the canonical reason phrase for a status code.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+/// Sends server-sent events on a response built with `Response::sse`.
+pub struct EventStream<'w, 'a> {
+    out: &'w mut ChunkWriter<'a>,
+}
+
+impl EventStream<'_, '_> {
+    /// Sends one event and flushes it to the client.
+    ///
+    /// `name` becomes the `event:` field; without it browsers dispatch a
+    /// `message` event. Each line of `data` is sent as its own `data:` field,
+    /// so multi-line payloads arrive intact.
+    pub fn send_event(&mut self, name: Option<&str>, data: &str) -> io::Result<()> {
+        let mut frame = String::new();
+        if let Some(name) = name {
+            // A newline would start a new field.
+            let name: String = name.chars().filter(|c| !matches!(c, '\r' | '\n')).collect();
+            frame.push_str(&format!("event: {name}\n"));
+        }
+        for line in data.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            frame.push_str(&format!("data: {line}\n"));
+        }
+        frame.push('\n');
+        self.out.write_all(frame.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_adds_content_length() {
+        let mut out = Vec::new();
+        Response::text(200, "hi").write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 2\r\n\r\nhi"
+        );
+    }
+
+    #[test]
+    fn test_body_reader_with_known_length() {
+        let response = Response::new(200)
+            .with_body_reader(io::Cursor::new(b"from a reader".to_vec()), Some(13))
+            .with_header("Content-Length", "99");
+        assert!(!response.is_chunked());
+
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\nfrom a reader"
+        );
+    }
+
+    #[test]
+    fn test_body_reader_of_unknown_length_is_chunked() {
+        // Hands out at most four bytes per read, like a slow upstream.
+        struct Trickle(io::Cursor<&'static [u8]>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(4);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        let response =
+            Response::new(200).with_body_reader(Trickle(io::Cursor::new(b"streamed")), None);
+        assert!(response.is_chunked());
+
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"));
+        let (_, body) = out.split_once("\r\n\r\n").unwrap();
+        let chunks: Vec<&str> = body.split("\r\n").collect();
+        assert_eq!(chunks.first(), Some(&"4"));
+        assert!(body.ends_with("0\r\n\r\n"));
+        assert_eq!(
+            chunks
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .copied()
+                .collect::<String>(),
+            "streamed"
+        );
+    }
+
+    fn status_line(response: &Response) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        out.lines().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_short_body_after_huge_content_length_is_an_error() {
+        let raw = "HTTP/1.1 200 OK\r\nContent-Length: 1000000000000000\r\n\r\nshort";
+        let mut reader = io::Cursor::new(raw);
+        let error = Response::read_from(&mut reader, true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let raw = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let response = Response::read_from(&mut io::Cursor::new(raw), true).unwrap();
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[test]
+    fn test_common_status_constructors() {
+        let response = Response::no_content();
+        assert_eq!(status_line(&response), "HTTP/1.1 204 No Content");
+        assert!(response.body.is_empty());
+
+        let response = Response::created("/users/7");
+        assert_eq!(status_line(&response), "HTTP/1.1 201 Created");
+        assert_eq!(response.headers.get("Location"), Some("/users/7"));
+
+        let response = Response::redirect_https("example.com:8080", "/a?b=1");
+        assert_eq!(response.status, 301);
+        assert_eq!(
+            response.headers.get("Location"),
+            Some("https://example.com/a?b=1")
+        );
+        let response = Response::redirect_https("[::1]", "/");
+        assert_eq!(response.headers.get("Location"), Some("https://[::1]/"));
+        assert!(response.body.is_empty());
+
+        let response = Response::not_modified();
+        assert_eq!(status_line(&response), "HTTP/1.1 304 Not Modified");
+        assert!(response.headers.get("Content-Type").is_none());
+        assert!(response.body.is_empty());
+    }
+
+    fn written(response: &Response) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_small_matches_write_to() {
+        let mut head = Response::text(200, "hello");
+        head.strip_body();
+        let responses = [
+            Response::text(200, "hello"),
+            Response::ok(),
+            Response::new(204).with_header("Content-Length", "0"),
+            Response::new(304).with_header("ETag", "\"v1\""),
+            Response::error(404, "gone"),
+            Response::ok()
+                .with_header("Content-Length", "3")
+                .with_body("abc"),
+            Response::ok()
+                .with_header("Transfer-Encoding", "identity")
+                .with_body("raw"),
+            Response::text(200, "é".repeat(500)).with_header("X-Long", "v".repeat(300)),
+            head,
+        ];
+        for response in responses {
+            let mut general = Vec::new();
+            response.write_to(&mut general).unwrap();
+            let mut small = Vec::new();
+            response.write_small(&mut small).unwrap();
+            assert_eq!(small, general, "{response:?}");
+        }
+    }
+
+    #[test]
+    fn test_bodiless_framing() {
+        let response = Response::no_content()
+            .with_body("ignored")
+            .with_header("Content-Length", "7");
+        assert_eq!(written(&response), "HTTP/1.1 204 No Content\r\n\r\n");
+
+        let response = Response::not_modified()
+            .with_header("ETag", "\"v1\"")
+            .with_header("Transfer-Encoding", "chunked");
+        assert_eq!(
+            written(&response),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n"
+        );
+
+        let response = Response::new(200);
+        assert_eq!(
+            written(&response),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_head_reply_framing() {
+        let mut response = Response::text(200, "twelve bytes");
+        response.strip_body();
+        assert_eq!(
+            written(&response),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 12\r\n\r\n"
+        );
+
+        let mut response = Response::chunked(200, |out| out.write_all(b"streamed"));
+        response.strip_body();
+        assert_eq!(written(&response), "HTTP/1.1 200 OK\r\n\r\n");
+
+        let mut response = Response::no_content();
+        response.strip_body();
+        assert_eq!(written(&response), "HTTP/1.1 204 No Content\r\n\r\n");
+    }
+
+    #[test]
+    fn test_chunked_body_framing() {
+        let response = Response::chunked(200, |out| {
+            out.write_all(b"hello, ")?;
+            out.write_all(b"")?;
+            out.write_all(b"world")
+        })
+        .with_header("Content-Length", "99");
+
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n7\r\nhello, \r\n5\r\nworld\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_generator_leaves_body_unterminated() {
+        let response = Response::chunked(200, |out| {
+            out.write_all(b"part")?;
+            Err(io::Error::other("upstream went away"))
+        });
+
+        let mut out = Vec::new();
+        assert!(response.write_to(&mut out).is_err());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("\r\n\r\n4\r\npart\r\n"), "{out:?}");
+    }
+}
@@ -0,0 +1,1108 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    etag::ETag,
+    logging::error,
+    middleware::Middleware,
+    request::{Method, Request},
+    response::Response,
+};
+
+/// A shareable request handler.
+pub type Handler = Arc<dyn Fn(&mut Request) -> Response + Send + Sync>;
+
+/// Wraps a closure as a `Handler`, for building route tables.
+pub fn handler<F>(handler: F) -> Handler
+where
+    F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+{
+    Arc::new(handler)
+}
+
+/// A handler that can report its resource's current entity tag without
+/// building the response, registered with `Router::add_tagged`.
+///
+/// Conditional requests are answered from the tag alone: a matching
+/// `If-None-Match` gets `304 Not Modified` (or `412` on unsafe methods), and
+/// a failed `If-Match` gets `412`, without calling `respond`.
+pub trait TaggedHandler: Send + Sync + 'static {
+    /// Returns the current tag, or `None` if the resource does not exist.
+    /// Should be much cheaper than `respond`.
+    fn etag(&self, req: &Request) -> Option<ETag>;
+
+    /// Builds the full response. It gets the tag from `etag` as an `ETag`
+    /// header unless it sets one itself.
+    fn respond(&self, req: &mut Request) -> Response;
+}
+
+/// One piece of a route pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    /// `:name` matches exactly one path segment.
+    Param(String),
+    /// `*name` matches the rest of the path, including slashes.
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    trailing_slash: bool,
+    handler: Handler,
+    timeout: Option<Duration>,
+    /// Whether the handler reads the body itself, from `Request::body_reader`.
+    streaming: bool,
+}
+
+impl Route {
+    /// Matches `path` against this route, returning the captured parameters.
+    ///
+    /// A trailing slash is significant: `/about/` does not match `/about`
+    /// unless the pattern ends in a wildcard.
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let is_wildcard = matches!(self.segments.last(), Some(Segment::Wildcard(_)));
+        if !is_wildcard && has_trailing_slash(path) != self.trailing_slash {
+            return None;
+        }
+        match_segments(&self.segments, path)
+    }
+
+    fn call(&self, req: &mut Request) -> Response {
+        match self.timeout {
+            Some(timeout) => run_with_timeout(&self.handler, req, timeout),
+            None => (self.handler)(req),
+        }
+    }
+}
+
+fn has_trailing_slash(path: &str) -> bool {
+    path.len() > 1 && path.ends_with('/')
+}
+
+/// Why routes could not be registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// A route for the same method and pattern shape already exists.
+    Duplicate { method: Method, pattern: String },
+    /// Another router is already mounted at the same prefix.
+    DuplicateMount { prefix: String },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::Duplicate { method, pattern } => {
+                write!(f, "Duplicate route: {method} {pattern}")
+            }
+            RouteError::DuplicateMount { prefix } => {
+                write!(f, "A router is already mounted at {prefix}")
+            }
+        }
+    }
+}
+
+/// Maps a method and path pattern to a handler.
+///
+/// Patterns are made of `/`-separated segments. A segment starting with `:`
+/// captures one path segment, and a final segment starting with `*` captures
+/// the remainder of the path. Captures are exposed through `Request::param`.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    /// Indexes `routes` by pattern, kept up to date whatever the strategy.
+    trie: TrieNode,
+    strategy: MatchStrategy,
+    fallback: Option<Handler>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    mounts: Vec<Mount>,
+}
+
+/// How a `Router` finds the routes matching a path.
+///
+/// Both strategies dispatch identically: the first route registered for a
+/// path and method wins either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchStrategy {
+    /// `Trie` once more than `TRIE_THRESHOLD` routes are registered,
+    /// `Linear` below that.
+    #[default]
+    Auto,
+    /// Tries every route in turn.
+    Linear,
+    /// Walks a tree of path segments, visiting only routes that share the
+    /// path's shape.
+    Trie,
+}
+
+/// Route count above which `MatchStrategy::Auto` switches to the trie.
+pub const TRIE_THRESHOLD: usize = 32;
+
+/// A node of the segment trie behind `MatchStrategy::Trie`, holding route
+/// indexes.
+#[derive(Default)]
+struct TrieNode {
+    statics: HashMap<String, TrieNode>,
+    param: Option<Box<TrieNode>>,
+    /// Routes whose pattern ends here with a wildcard.
+    wildcards: Vec<usize>,
+    /// Routes whose pattern ends exactly here.
+    routes: Vec<usize>,
+}
+
+impl TrieNode {
+    fn insert(&mut self, segments: &[Segment], index: usize) {
+        let mut node = self;
+        for segment in segments {
+            node = match segment {
+                Segment::Static(s) => node.statics.entry(s.clone()).or_default(),
+                Segment::Param(_) => node.param.get_or_insert_default(),
+                Segment::Wildcard(_) => {
+                    node.wildcards.push(index);
+                    return;
+                }
+            };
+        }
+        node.routes.push(index);
+    }
+
+    /// Collects the routes whose segments fit `parts`, in no set order.
+    /// Trailing slashes are left to `Route::matches`.
+    fn collect(&self, parts: &[&str], found: &mut Vec<usize>) {
+        found.extend(&self.wildcards);
+        let Some((first, rest)) = parts.split_first() else {
+            found.extend(&self.routes);
+            return;
+        };
+        if let Some(child) = self.statics.get(*first) {
+            child.collect(rest, found);
+        }
+        if let Some(child) = &self.param {
+            child.collect(rest, found);
+        }
+    }
+}
+
+/// A prefix claimed by `Router::mount`, with the mounted router's fallback.
+struct Mount {
+    prefix: Vec<Segment>,
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    /// Create a router with no routes.
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Chooses how routes are matched; see `MatchStrategy`.
+    pub fn match_strategy(&mut self, strategy: MatchStrategy) -> &mut Router {
+        self.strategy = strategy;
+        self
+    }
+
+    fn push_route(&mut self, route: Route) {
+        self.trie.insert(&route.segments, self.routes.len());
+        self.routes.push(route);
+    }
+
+    /// Returns the routes whose pattern may match `path`, in registration
+    /// order.
+    fn candidates<'a>(&'a self, path: &str) -> impl Iterator<Item = &'a Route> + 'a {
+        let use_trie = match self.strategy {
+            MatchStrategy::Auto => self.routes.len() > TRIE_THRESHOLD,
+            MatchStrategy::Linear => false,
+            MatchStrategy::Trie => true,
+        };
+        let indexed = use_trie.then(|| {
+            let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+            let mut found = Vec::new();
+            self.trie.collect(&parts, &mut found);
+            found.sort_unstable();
+            found.into_iter().map(|index| &self.routes[index])
+        });
+        let linear = (!use_trie).then(|| self.routes.iter());
+        linear
+            .into_iter()
+            .flatten()
+            .chain(indexed.into_iter().flatten())
+    }
+
+    /// Registers a handler for `method` requests matching `pattern`.
+    pub fn add<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.push_route(Route {
+            method,
+            segments: parse_pattern(pattern),
+            trailing_slash: has_trailing_slash(pattern),
+            handler: Arc::new(handler),
+            timeout: None,
+            streaming: false,
+        });
+        self
+    }
+
+    /// Registers a handler that must respond within `timeout`.
+    ///
+    /// The handler runs on its own thread with a copy of the request. If it
+    /// overruns, the client gets `503 Service Unavailable` right away, but
+    /// the handler thread cannot be cancelled and keeps running until it
+    /// returns; its response is then discarded.
+    pub fn add_with_timeout<F>(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        timeout: Duration,
+        handler: F,
+    ) -> &mut Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.add(method, pattern, handler);
+        if let Some(route) = self.routes.last_mut() {
+            route.timeout = Some(timeout);
+        }
+        self
+    }
+
+    /// Registers a handler that reads the request body as it arrives.
+    ///
+    /// The server leaves the body on the connection: `req.body` stays empty
+    /// and `req.body_reader` yields the decoded bytes, `Content-Length` or
+    /// chunked, until the end of the body or `ServerConfig::max_body_size`.
+    /// Past the limit reads fail and the client gets `413 Content Too
+    /// Large`, whatever the handler returns. A `Content-Encoding` is left
+    /// for the handler to decode. The connection closes after the response.
+    pub fn add_streaming<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.add(method, pattern, handler);
+        if let Some(route) = self.routes.last_mut() {
+            route.streaming = true;
+        }
+        self
+    }
+
+    /// Registers a handler whose conditional requests are answered from
+    /// its entity tag, before the response is built.
+    pub fn add_tagged<H: TaggedHandler>(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: H,
+    ) -> &mut Router {
+        self.add(method, pattern, move |req| {
+            let current = handler.etag(req);
+            if let Some(response) = req.check_precondition(current.as_ref()) {
+                return response;
+            }
+            let mut response = handler.respond(req);
+            if let Some(current) = current
+                && !response.headers.contains("ETag")
+            {
+                response.headers.insert("ETag", current.to_string());
+            }
+            response
+        })
+    }
+
+    /// Builds a router from a table of routes, such as one made with the
+    /// `routes!` macro.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RouteError::Duplicate` if two entries share a method and a
+    /// pattern (parameter names aside).
+    pub fn from_routes(routes: &[(Method, &str, Handler)]) -> Result<Router, RouteError> {
+        let mut router = Router::new();
+        for (method, pattern, handler) in routes {
+            router.try_add(method.clone(), pattern, Arc::clone(handler))?;
+        }
+        Ok(router)
+    }
+
+    /// Registers `handler` unless an equivalent route already exists.
+    ///
+    /// Unlike `add`, where the first of two equivalent routes silently wins,
+    /// this reports the conflict.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RouteError::Duplicate` if a route for `method` with the same
+    /// pattern shape is already registered.
+    pub fn try_add(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: Handler,
+    ) -> Result<&mut Router, RouteError> {
+        let segments = parse_pattern(pattern);
+        let trailing_slash = has_trailing_slash(pattern);
+        if self.has_equivalent(&method, &segments, trailing_slash) {
+            return Err(RouteError::Duplicate {
+                method,
+                pattern: pattern.to_string(),
+            });
+        }
+
+        self.push_route(Route {
+            method,
+            segments,
+            trailing_slash,
+            handler,
+            timeout: None,
+            streaming: false,
+        });
+        Ok(self)
+    }
+
+    fn has_equivalent(&self, method: &Method, segments: &[Segment], trailing_slash: bool) -> bool {
+        self.routes.iter().any(|route| {
+            route.method == *method
+                && route.trailing_slash == trailing_slash
+                && same_shape(&route.segments, segments)
+        })
+    }
+
+    /// Serves `sub`'s routes under `prefix`, so a `/users` route mounted at
+    /// `/api` answers `/api/users`.
+    ///
+    /// The prefix may contain `:name` captures. `sub`'s middleware still
+    /// wraps only its own routes, and its fallback answers unmatched paths
+    /// under the prefix.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RouteError::DuplicateMount` if a router is already mounted at
+    /// an equivalent prefix, or `RouteError::Duplicate` if one of `sub`'s
+    /// routes would shadow an existing one. Nothing is added on error.
+    pub fn mount(&mut self, prefix: &str, sub: Router) -> Result<&mut Router, RouteError> {
+        let prefix_segments = parse_pattern(prefix);
+        let prefix_str = format!("/{}", prefix.trim_matches('/'));
+        let join = |segments: &[Segment]| [prefix_segments.as_slice(), segments].concat();
+
+        if self
+            .mounts
+            .iter()
+            .any(|mount| same_shape(&mount.prefix, &prefix_segments))
+        {
+            return Err(RouteError::DuplicateMount { prefix: prefix_str });
+        }
+        for mount in &sub.mounts {
+            let nested = join(&mount.prefix);
+            if self.mounts.iter().any(|m| same_shape(&m.prefix, &nested)) {
+                return Err(RouteError::DuplicateMount {
+                    prefix: segments_to_pattern(&nested),
+                });
+            }
+        }
+        for route in &sub.routes {
+            let segments = join(&route.segments);
+            if self.has_equivalent(&route.method, &segments, route.trailing_slash) {
+                return Err(RouteError::Duplicate {
+                    method: route.method.clone(),
+                    pattern: segments_to_pattern(&segments),
+                });
+            }
+        }
+
+        let middleware = sub.middleware;
+        let wrap = |handler: Handler| -> Handler {
+            if middleware.is_empty() {
+                return handler;
+            }
+            let middleware = middleware.clone();
+            Arc::new(move |req: &mut Request| run_chain(&middleware, req, &*handler))
+        };
+
+        self.mounts.push(Mount {
+            prefix: prefix_segments.clone(),
+            fallback: sub.fallback.map(&wrap),
+        });
+        for mount in sub.mounts {
+            self.mounts.push(Mount {
+                prefix: join(&mount.prefix),
+                fallback: mount.fallback.map(&wrap),
+            });
+        }
+        for route in sub.routes {
+            self.push_route(Route {
+                segments: join(&route.segments),
+                handler: wrap(route.handler),
+                ..route
+            });
+        }
+        Ok(self)
+    }
+
+    /// Registers a handler for `GET` requests.
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.add(Method::Get, pattern, handler)
+    }
+
+    /// Registers a handler for `POST` requests.
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.add(Method::Post, pattern, handler)
+    }
+
+    /// Sets the handler used when no route matches the request path.
+    ///
+    /// Without a fallback, unmatched requests receive a plain `404 Not Found`.
+    pub fn fallback<F>(&mut self, handler: F) -> &mut Router
+    where
+        F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    /// Wraps every request handled by this router in `middleware`.
+    ///
+    /// Middleware runs in the order it is added, outermost first.
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Router {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Returns `true` if a route is registered for `method` and `path`.
+    ///
+    /// `GET` routes also count for `HEAD`.
+    pub fn has_route(&self, method: &Method, path: &str) -> bool {
+        let serves = |route: &Route| {
+            route.method == *method || (*method == Method::Head && route.method == Method::Get)
+        };
+        self.candidates(path)
+            .any(|route| serves(route) && route.matches(path).is_some())
+    }
+
+    /// Returns `true` if the route for `method` and `path` was added with
+    /// `add_streaming`.
+    pub fn streams_body(&self, method: &Method, path: &str) -> bool {
+        self.candidates(path)
+            .find(|route| route.method == *method && route.matches(path).is_some())
+            .is_some_and(|route| route.streaming)
+    }
+
+    /// Dispatches a request to the matching handler.
+    ///
+    /// `HEAD` requests without a `HEAD` route go to the `GET` handler. When
+    /// the path matches but the method does not, a `405 Method Not
+    /// Allowed` is returned with an `Allow` header listing the registered
+    /// methods.
+    pub fn handle(&self, req: &mut Request) -> Response {
+        run_chain(&self.middleware, req, &|req| self.dispatch(req))
+    }
+
+    fn dispatch(&self, req: &mut Request) -> Response {
+        let mut allowed = Vec::new();
+        // HEAD falls back to the GET handler; the server drops the body.
+        let mut get_for_head = None;
+
+        for route in self.candidates(&req.path) {
+            if let Some(params) = route.matches(&req.path) {
+                if route.method == req.method {
+                    req.params = params;
+                    return route.call(req);
+                }
+                if req.method == Method::Head && route.method == Method::Get {
+                    get_for_head.get_or_insert((route, params));
+                }
+                allowed.push(route.method.to_string());
+            }
+        }
+
+        if let Some((route, params)) = get_for_head {
+            req.params = params;
+            return route.call(req);
+        }
+
+        if !allowed.is_empty() {
+            if allowed.iter().any(|m| m == "GET") && !allowed.iter().any(|m| m == "HEAD") {
+                allowed.push(String::from("HEAD"));
+            }
+            return Response::error(405, "The method is not allowed for this resource.")
+                .with_header("Allow", allowed.join(", "));
+        }
+
+        // The fallback of the most specific mount covering the path.
+        let mounted = self
+            .mounts
+            .iter()
+            .filter_map(|mount| {
+                let params = match_prefix(&mount.prefix, &req.path)?;
+                Some((mount.prefix.len(), mount.fallback.as_ref()?, params))
+            })
+            .max_by_key(|(depth, ..)| *depth);
+        if let Some((_, fallback, params)) = mounted {
+            req.params = params;
+            return fallback(req);
+        }
+
+        match &self.fallback {
+            Some(fallback) => fallback(req),
+            None => Response::not_found(),
+        }
+    }
+}
+
+/// Runs `handler` on a separate thread, giving up after `timeout`.
+fn run_with_timeout(handler: &Handler, req: &Request, timeout: Duration) -> Response {
+    let (sender, receiver) = mpsc::channel();
+    let handler = Arc::clone(handler);
+    let mut copy = req.clone();
+    thread::spawn(move || {
+        let _ = sender.send(handler(&mut copy));
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(response) => response,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            error!(
+                "Handler for {} {} timed out after {:?}",
+                req.method, req.path, timeout
+            );
+            Response::error(503, "The server took too long to respond.")
+        }
+        // The handler panicked before producing a response.
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Response::error(500, "The handler failed to produce a response.")
+        }
+    }
+}
+
+fn run_chain(
+    middleware: &[Arc<dyn Middleware>],
+    req: &mut Request,
+    endpoint: &dyn Fn(&mut Request) -> Response,
+) -> Response {
+    match middleware.split_first() {
+        Some((first, rest)) => first.handle(req, &|req| run_chain(rest, req, endpoint)),
+        None => endpoint(req),
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if let Some(name) = s.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = s.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(s.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Returns `true` if two patterns match exactly the same paths.
+fn same_shape(a: &[Segment], b: &[Segment]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|pair| match pair {
+            (Segment::Static(x), Segment::Static(y)) => x == y,
+            (Segment::Param(_), Segment::Param(_)) => true,
+            (Segment::Wildcard(_), Segment::Wildcard(_)) => true,
+            _ => false,
+        })
+}
+
+/// Formats segments back into a pattern, for error messages.
+fn segments_to_pattern(segments: &[Segment]) -> String {
+    let parts: Vec<String> = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Static(s) => s.clone(),
+            Segment::Param(name) => format!(":{name}"),
+            Segment::Wildcard(name) => format!("*{name}"),
+        })
+        .collect();
+    format!("/{}", parts.join("/"))
+}
+
+/// Matches the leading segments of `path` against `prefix`.
+fn match_prefix(prefix: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let depth = prefix.len();
+    let head: Vec<&str> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .take(depth)
+        .collect();
+    match_segments(prefix, &head.join("/"))
+}
+
+fn match_segments(segments: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut parts = path.split('/').filter(|s| !s.is_empty());
+    let mut params = HashMap::new();
+
+    for segment in segments {
+        match segment {
+            Segment::Static(s) => {
+                if parts.next()? != s {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), parts.next()?.to_string());
+            }
+            Segment::Wildcard(name) => {
+                let rest: Vec<&str> = parts.by_ref().collect();
+                params.insert(name.clone(), rest.join("/"));
+                return Some(params);
+            }
+        }
+    }
+
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(params)
+}
+
+/// A host name pattern: either an exact name or a `*.suffix` wildcard.
+enum HostPattern {
+    Exact(String),
+    Suffix(String),
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> HostPattern {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => HostPattern::Suffix(format!(".{suffix}")),
+            None => HostPattern::Exact(pattern),
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => host == name,
+            HostPattern::Suffix(suffix) => host.len() > suffix.len() && host.ends_with(suffix),
+        }
+    }
+}
+
+/// Dispatches requests to a `Router` chosen by the request's `Host` header.
+///
+/// Exact host patterns take precedence over wildcard patterns such as
+/// `*.example.com`. Requests whose host matches nothing, or that carry no
+/// `Host` header, are handled by the default router.
+pub struct VHostRouter {
+    hosts: Vec<(HostPattern, Router)>,
+    default: Router,
+}
+
+impl VHostRouter {
+    /// Create a virtual-host router that falls back to `default`.
+    pub fn new(default: Router) -> VHostRouter {
+        VHostRouter {
+            hosts: Vec::new(),
+            default,
+        }
+    }
+
+    /// Registers a router for a host pattern (`example.com` or `*.example.com`).
+    pub fn add_host(&mut self, pattern: &str, router: Router) -> &mut VHostRouter {
+        self.hosts.push((HostPattern::parse(pattern), router));
+        self
+    }
+
+    /// Returns the router responsible for the given `Host` header value.
+    pub fn router_for(&self, host: Option<&str>) -> &Router {
+        let Some(host) = host else {
+            return &self.default;
+        };
+
+        let host = strip_port(host).to_ascii_lowercase();
+        let exact = self
+            .hosts
+            .iter()
+            .find(|(p, _)| matches!(p, HostPattern::Exact(_)) && p.matches(&host));
+        let wildcard = || {
+            self.hosts
+                .iter()
+                .find(|(p, _)| matches!(p, HostPattern::Suffix(_)) && p.matches(&host))
+        };
+
+        exact
+            .or_else(wildcard)
+            .map_or(&self.default, |(_, router)| router)
+    }
+
+    /// Dispatches a request to the router matching its host.
+    pub fn handle(&self, req: &mut Request) -> Response {
+        self.router_for(req.host()).handle(req)
+    }
+}
+
+impl From<Router> for VHostRouter {
+    fn from(router: Router) -> VHostRouter {
+        VHostRouter::new(router)
+    }
+}
+
+/// Removes a trailing `:port`, leaving bracketed IPv6 literals intact.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+/// Builds a route table for `Router::from_routes`.
+///
+/// ```ignore
+/// let router = Router::from_routes(&hello::routes![
+///     Get "/" => |_| Response::text(200, "home"),
+///     Post "/users" => create_user,
+/// ])?;
+/// ```
+#[macro_export]
+macro_rules! routes {
+    ($($method:ident $pattern:literal => $handler:expr),* $(,)?) => {
+        [$((
+            $crate::request::Method::$method,
+            $pattern,
+            $crate::router::handler($handler),
+        )),*]
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    fn request_for(host: &str, path: &str) -> Request {
+        let mut req = Request::new(Method::Get, path);
+        req.headers.insert("Host", host);
+        req
+    }
+
+    #[test]
+    fn test_route_params_and_wildcards() {
+        let mut router = Router::new();
+        router.get("/users/:id", |req| {
+            Response::text(200, req.param("id").unwrap_or_default().to_string())
+        });
+        router.get("/static/*rest", |req| {
+            Response::text(200, req.param("rest").unwrap_or_default().to_string())
+        });
+
+        let mut req = Request::new(Method::Get, "/users/42");
+        assert_eq!(body(&router.handle(&mut req)), "42");
+
+        let mut req = Request::new(Method::Get, "/static/css/site.css");
+        assert_eq!(body(&router.handle(&mut req)), "css/site.css");
+
+        let mut req = Request::new(Method::Get, "/users/42/extra");
+        assert_eq!(router.handle(&mut req).status, 404);
+
+        let mut req = Request::new(Method::Get, "/users/42/");
+        assert_eq!(router.handle(&mut req).status, 404);
+    }
+
+    #[test]
+    fn test_trie_matches_like_linear() {
+        let build = |strategy| {
+            let mut router = Router::new();
+            router.match_strategy(strategy);
+            let patterns = [
+                "/",
+                "/users",
+                "/users/",
+                "/users/me",
+                "/users/:id",
+                "/users/:id/posts",
+                "/users/:id/posts/:post",
+                "/files/*path",
+                "/files/readme",
+                "/:any/edit",
+                "/a/:b/c",
+                "/a/b/:c",
+                "/static/*rest",
+            ];
+            for (i, pattern) in patterns.into_iter().enumerate() {
+                let tag = format!("{i} {pattern}");
+                router.get(pattern, move |req| {
+                    let mut params: Vec<_> = req.params.iter().collect();
+                    params.sort();
+                    Response::text(200, format!("{tag} {params:?}"))
+                });
+            }
+            router.post("/users/:id", |_| Response::text(200, "post"));
+            router.add(Method::Delete, "/files/*path", |_| {
+                Response::text(200, "delete")
+            });
+            router
+        };
+        let linear = build(MatchStrategy::Linear);
+        let trie = build(MatchStrategy::Trie);
+
+        let paths = [
+            "/",
+            "/users",
+            "/users/",
+            "/users/me",
+            "/users/42",
+            "/users/42/",
+            "/users/42/posts",
+            "/users/42/posts/7",
+            "/files",
+            "/files/",
+            "/files/readme",
+            "/files/a/b/c",
+            "/users/edit",
+            "/x/edit",
+            "/a/b/c",
+            "/a/z/c",
+            "/a/b/z",
+            "/static",
+            "/nope",
+            "/users/42/posts/7/8",
+        ];
+        for method in [
+            Method::Get,
+            Method::Head,
+            Method::Post,
+            Method::Delete,
+            Method::Put,
+        ] {
+            for path in paths {
+                let mut a = Request::new(method.clone(), path);
+                let mut b = Request::new(method.clone(), path);
+                let (a, b) = (linear.handle(&mut a), trie.handle(&mut b));
+                assert_eq!(a.status, b.status, "{method} {path}");
+                assert_eq!(body(&a), body(&b), "{method} {path}");
+                assert_eq!(a.headers.get("Allow"), b.headers.get("Allow"));
+                assert_eq!(
+                    linear.has_route(&method, path),
+                    trie.has_route(&method, path)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tagged_handler_skips_body_when_not_modified() {
+        struct Report {
+            renders: Arc<std::sync::atomic::AtomicUsize>,
+        }
+
+        impl TaggedHandler for Report {
+            fn etag(&self, _req: &Request) -> Option<ETag> {
+                Some(ETag::strong("v7"))
+            }
+
+            fn respond(&self, _req: &mut Request) -> Response {
+                self.renders
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Response::text(200, "an expensive report")
+            }
+        }
+
+        let renders = Arc::default();
+        let mut router = Router::new();
+        router.add_tagged(
+            Method::Get,
+            "/report",
+            Report {
+                renders: Arc::clone(&renders),
+            },
+        );
+
+        let mut req = Request::new(Method::Get, "/report");
+        let response = router.handle(&mut req);
+        assert_eq!(body(&response), "an expensive report");
+        assert_eq!(response.headers.get("ETag"), Some("\"v7\""));
+
+        let mut req = Request::new(Method::Get, "/report");
+        req.headers.insert("If-None-Match", "\"v6\", \"v7\"");
+        let response = router.handle(&mut req);
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_empty());
+        assert_eq!(response.headers.get("ETag"), Some("\"v7\""));
+        assert_eq!(renders.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_route_timeout_returns_503() {
+        let mut router = Router::new();
+        router.add_with_timeout(Method::Get, "/slow", Duration::from_millis(50), |_| {
+            thread::sleep(Duration::from_secs(2));
+            Response::text(200, "too late")
+        });
+        router.add_with_timeout(Method::Get, "/fast/:id", Duration::from_secs(2), |req| {
+            Response::text(200, req.param("id").unwrap_or_default().to_string())
+        });
+
+        let started = std::time::Instant::now();
+        let response = router.handle(&mut Request::new(Method::Get, "/slow"));
+        assert_eq!(response.status, 503);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let response = router.handle(&mut Request::new(Method::Get, "/fast/7"));
+        assert_eq!(body(&response), "7");
+    }
+
+    #[test]
+    fn test_router_from_table() {
+        let router = Router::from_routes(&routes![
+            Get "/" => |_| Response::text(200, "home"),
+            Get "/users/:id" => |req: &mut Request| {
+                Response::text(200, req.param("id").unwrap_or_default().to_string())
+            },
+            Post "/users/:id" => |_| Response::new(201),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            body(&router.handle(&mut Request::new(Method::Get, "/"))),
+            "home"
+        );
+        assert_eq!(
+            body(&router.handle(&mut Request::new(Method::Get, "/users/9"))),
+            "9"
+        );
+        let mut req = Request::new(Method::Post, "/users/9");
+        assert_eq!(router.handle(&mut req).status, 201);
+    }
+
+    #[test]
+    fn test_duplicate_route_is_an_error() {
+        let result = Router::from_routes(&routes![
+            Get "/users/:id" => |_| Response::ok(),
+            Get "/users/:name" => |_| Response::ok(),
+        ]);
+        assert_eq!(
+            result.err(),
+            Some(RouteError::Duplicate {
+                method: Method::Get,
+                pattern: "/users/:name".into()
+            })
+        );
+
+        // A different method or trailing slash is a different route.
+        assert!(
+            Router::from_routes(&routes![
+                Get "/a" => |_| Response::ok(),
+                Post "/a" => |_| Response::ok(),
+                Get "/a/" => |_| Response::ok(),
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_method_mismatch_is_405() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::ok());
+
+        let mut req = Request::new(Method::Post, "/");
+        let response = router.handle(&mut req);
+        assert_eq!(response.status, 405);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD"));
+    }
+
+    #[test]
+    fn test_mount_sub_router() {
+        let mut api = Router::new();
+        api.get("/users", |_| Response::text(200, "users"));
+        api.fallback(|_| Response::text(404, "no such endpoint"));
+
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "home"));
+        router.mount("/api", api).unwrap();
+        let mut org = Router::new();
+        org.get("/users/:id", |req| {
+            let org = req.param("org").unwrap_or_default().to_string();
+            let id = req.param("id").unwrap_or_default();
+            Response::text(200, format!("{org}/{id}"))
+        });
+        router.mount("/orgs/:org", org).unwrap();
+
+        let mut req = Request::new(Method::Get, "/api/users");
+        assert_eq!(body(&router.handle(&mut req)), "users");
+        let mut req = Request::new(Method::Get, "/orgs/acme/users/7");
+        assert_eq!(body(&router.handle(&mut req)), "acme/7");
+        let mut req = Request::new(Method::Post, "/api/users");
+        assert_eq!(router.handle(&mut req).status, 405);
+
+        // The sub-router's fallback covers only its prefix.
+        let mut req = Request::new(Method::Get, "/api/missing");
+        assert_eq!(body(&router.handle(&mut req)), "no such endpoint");
+        let mut req = Request::new(Method::Get, "/missing");
+        assert_eq!(router.handle(&mut req).status, 404);
+        assert_ne!(body(&router.handle(&mut req)), "no such endpoint");
+    }
+
+    #[test]
+    fn test_mount_conflicts() {
+        let mut router = Router::new();
+        router.get("/api/users", |_| Response::ok());
+        let mut api = Router::new();
+        api.get("/users", |_| Response::ok());
+        let err = router.mount("/api", api).err().unwrap();
+        assert_eq!(
+            err,
+            RouteError::Duplicate {
+                method: Method::Get,
+                pattern: "/api/users".into()
+            }
+        );
+
+        router.mount("/v1/", Router::new()).unwrap();
+        let err = router.mount("v1", Router::new()).err().unwrap();
+        assert_eq!(
+            err,
+            RouteError::DuplicateMount {
+                prefix: "/v1".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_vhost_dispatch() {
+        let mut site_a = Router::new();
+        site_a.get("/", |_| Response::text(200, "site a"));
+        let mut site_b = Router::new();
+        site_b.get("/", |_| Response::text(200, "site b"));
+        let mut fallback = Router::new();
+        fallback.get("/", |_| Response::text(200, "default"));
+
+        let mut vhosts = VHostRouter::new(fallback);
+        vhosts.add_host("a.example.com", site_a);
+        vhosts.add_host("*.example.org", site_b);
+
+        let response = vhosts.handle(&mut request_for("a.example.com:7878", "/"));
+        assert_eq!(body(&response), "site a");
+
+        let response = vhosts.handle(&mut request_for("WWW.Example.org", "/"));
+        assert_eq!(body(&response), "site b");
+
+        let response = vhosts.handle(&mut request_for("example.org", "/"));
+        assert_eq!(body(&response), "default");
+
+        let response = vhosts.handle(&mut Request::new(Method::Get, "/"));
+        assert_eq!(body(&response), "default");
+    }
+}
@@ -0,0 +1,2708 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, io,
+    io::{BufRead, BufReader, Read, Write},
+    mem,
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    PoolError, ThreadPool,
+    clock::{Clock, SystemClock},
+    error_pages::{self, ErrorPages},
+    headers::Headers,
+    logging::{LogSink, error, info, warning},
+    request::{
+        self, BodyReader, ConnectionContext, ExpectRejection, Method, MinBodyRate, ParseConfig,
+        Request, RequestError, Version,
+    },
+    response::Response,
+    router::VHostRouter,
+    signals,
+};
+
+/// Largest response serialized through the worker's scratch buffer, and
+/// the most capacity the buffer keeps between responses.
+const SCRATCH_LIMIT: usize = 64 * 1024;
+
+thread_local! {
+    /// Reused by each worker to assemble responses, so small responses cost
+    /// no allocation for the write and go out in a single `write` call.
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+
+    /// The read buffer of the worker's last connection, handed to its next
+    /// one so connection churn does not allocate a buffer each time.
+    static READ_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A buffered reader over a connection whose buffer is borrowed from the
+/// worker's `READ_BUFFER` and returned to it on drop.
+///
+/// Only the allocation is reused: each connection starts with an empty
+/// buffer, so nothing one client sent can reach the next.
+struct ConnectionReader<'a> {
+    stream: &'a TcpStream,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<'a> ConnectionReader<'a> {
+    fn new(stream: &'a TcpStream, capacity: usize) -> ConnectionReader<'a> {
+        let mut buf = READ_BUFFER.take();
+        // An empty buffer would read as end of stream.
+        buf.resize(capacity.max(1), 0);
+        buf.shrink_to(capacity.max(1));
+        ConnectionReader {
+            stream,
+            buf,
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// Returns the bytes read from the connection but not yet consumed.
+    fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+}
+
+impl Read for ConnectionReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // Like `BufReader`, skip the copy for reads as large as the buffer.
+        if self.pos == self.filled && out.len() >= self.buf.len() {
+            return self.stream.read(out);
+        }
+        let n = self.fill_buf()?.read(out)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ConnectionReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.stream.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(self.buffer())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+impl Drop for ConnectionReader<'_> {
+    fn drop(&mut self) {
+        READ_BUFFER.set(mem::take(&mut self.buf));
+    }
+}
+
+/// How long the accept loop sleeps when no connection is pending.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often an idle connection checks whether the server started draining
+/// or its clock passed the keep-alive timeout.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Custom error type for starting a Server.
+#[derive(Debug)]
+pub enum ServerError {
+    BindError(io::Error),
+    PoolError(PoolError),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::BindError(e) => write!(f, "Failed to bind to address: {e}"),
+            ServerError::PoolError(e) => write!(f, "Failed to create ThreadPool: {e}"),
+        }
+    }
+}
+
+/// Settings that control how the server accepts and answers requests.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Number of worker threads handling connections.
+    pub pool_size: usize,
+    /// Stop after accepting this many connections; `None` runs forever.
+    pub max_connections: Option<usize>,
+    /// Redirect `/about/` to `/about` (or the reverse) with a `301` when only
+    /// the other form has a route. The root path is never redirected.
+    pub redirect_trailing_slash: bool,
+    /// Value of the `Server` header sent on every response; `None` omits it.
+    pub server_header: Option<String>,
+    /// Path answered with `200 OK` while the process is serving at all.
+    pub health_path: Option<String>,
+    /// Path answered with `200 OK` normally and `503` once draining, so load
+    /// balancers stop routing to this instance.
+    pub readiness_path: Option<String>,
+    /// Custom pages for error responses generated by the server.
+    pub error_pages: ErrorPages,
+    /// Send error responses as RFC 7807 `application/problem+json` to
+    /// requests whose `Accept` prefers JSON over HTML. This covers requests
+    /// that could not be parsed, as far as their `Accept` header could be
+    /// read.
+    pub problem_json: bool,
+    /// Proxies whose `X-Forwarded-For` header is believed when computing
+    /// `Request::real_ip`.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Serve further requests on a connection after the first (HTTP
+    /// persistent connections).
+    pub keep_alive: bool,
+    /// How long an idle connection waits for its next request before the
+    /// server closes it.
+    pub keep_alive_timeout: Duration,
+    /// Once the server is draining, how much longer an idle keep-alive
+    /// connection waits for another request. A request already arriving
+    /// is still served in full.
+    pub drain_timeout: Duration,
+    /// After the last response on a connection, how long to keep reading
+    /// and discarding input the client already sent, so closing does not
+    /// reset the connection before the response is read. Skipped when no
+    /// input is waiting.
+    pub linger_timeout: Duration,
+    /// Close a persistent connection after this many requests. `None` is
+    /// unlimited. Keep-alive responses advertise the timeout and the
+    /// requests left in a `Keep-Alive` header.
+    pub max_requests_per_connection: Option<usize>,
+    /// Capacity of each connection's read buffer, in bytes. Lines longer
+    /// than the buffer still parse; they just take more reads.
+    pub read_buffer_size: usize,
+    /// Length of the kernel's queue of connections not yet accepted; `None`
+    /// keeps the platform default. Only applied on Unix.
+    pub listen_backlog: Option<u32>,
+    /// Longest a connection may stay open across all its requests. Once it
+    /// is exceeded, the response in progress is sent with
+    /// `Connection: close` and the connection ends. `None` is unlimited.
+    pub max_connection_duration: Option<Duration>,
+    /// Most connections open at once from one client IP address. Further
+    /// connections from that address get `503 Service Unavailable` and are
+    /// closed. `None` is unlimited.
+    pub max_connections_per_ip: Option<usize>,
+    /// Stop accepting while this many connections are queued for a worker,
+    /// leaving new ones in the kernel's listen backlog. `None` always
+    /// accepts.
+    pub accept_high_watermark: Option<usize>,
+    /// Longest request target accepted; longer ones get `414 URI Too Long`
+    /// and the connection is closed. `None` is unlimited.
+    pub max_uri_length: Option<usize>,
+    /// Longest header line accepted, in bytes; a longer one gets `431
+    /// Request Header Fields Too Large` before the rest of it is read, and
+    /// the connection is closed. `None` is unlimited.
+    pub max_single_header_bytes: Option<usize>,
+    /// Slowest acceptable request body; a slower upload gets `408 Request
+    /// Timeout` and the connection is closed. `None` only applies the idle
+    /// read timeout.
+    pub min_body_rate: Option<MinBodyRate>,
+    /// Largest request body accepted, by its declared `Content-Length`.
+    /// Bigger requests are refused with `413 Content Too Large`, or as
+    /// `expect_rejection` says if they sent `Expect: 100-continue`, before
+    /// the body is read. `None` is unlimited.
+    pub max_body_size: Option<u64>,
+    /// Whether an oversized `Expect: 100-continue` request gets `417` or
+    /// `413`.
+    pub expect_rejection: ExpectRejection,
+    /// Start draining when the process receives `SIGINT` or `SIGTERM` (Ctrl-C
+    /// or Ctrl-Break on Windows), so `run` returns and queued work finishes.
+    /// Installs process-wide handlers via `signals::install` on bind, and
+    /// forgets any signal that arrived before then.
+    pub drain_on_signal: bool,
+    /// Where access log lines go. `None` logs them at `Info` through the
+    /// installed `Logger`.
+    pub access_log: Option<Arc<dyn LogSink>>,
+    /// Takes over connections that ask for `Upgrade: h2c`. Without one, the
+    /// upgrade offer is ignored and the request is answered over HTTP/1.1.
+    pub h2c_handler: Option<UpgradeHandler>,
+    /// Takes over connections whose first byte is a TLS record, as judged
+    /// by `detect_protocol`. Without one, every connection is read as
+    /// plaintext HTTP.
+    pub tls_handler: Option<TlsHandler>,
+    /// Measures keep-alive and drain waits, `max_connection_duration` and
+    /// `min_body_rate`, and is handed to the worker pool. Socket read
+    /// timeouts still run on real time, so a `MockClock` that never moves
+    /// only stops these limits from expiring.
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            pool_size: 4,
+            max_connections: None,
+            redirect_trailing_slash: false,
+            server_header: None,
+            health_path: None,
+            readiness_path: None,
+            error_pages: ErrorPages::new(),
+            problem_json: false,
+            trusted_proxies: Vec::new(),
+            keep_alive: true,
+            keep_alive_timeout: Duration::from_secs(5),
+            drain_timeout: Duration::from_secs(1),
+            linger_timeout: Duration::from_millis(250),
+            max_requests_per_connection: None,
+            read_buffer_size: 8 * 1024,
+            listen_backlog: None,
+            max_connection_duration: None,
+            max_connections_per_ip: None,
+            accept_high_watermark: None,
+            max_uri_length: Some(8 * 1024),
+            max_single_header_bytes: Some(8 * 1024),
+            min_body_rate: None,
+            max_body_size: None,
+            expect_rejection: ExpectRejection::default(),
+            drain_on_signal: false,
+            access_log: None,
+            h2c_handler: None,
+            tls_handler: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+/// A connection handed over after `101 Switching Protocols`.
+pub struct Upgraded {
+    pub stream: TcpStream,
+    /// Bytes the client already sent past the upgrade request, such as the
+    /// start of the HTTP/2 connection preface.
+    pub buffered: Vec<u8>,
+}
+
+/// Callback that speaks the new protocol on an upgraded connection.
+///
+/// It receives the request that asked for the upgrade, which HTTP/2 must
+/// answer as stream 1.
+#[derive(Clone)]
+pub struct UpgradeHandler(Arc<dyn Fn(Request, Upgraded) + Send + Sync>);
+
+impl UpgradeHandler {
+    pub fn new<F>(handler: F) -> UpgradeHandler
+    where
+        F: Fn(Request, Upgraded) + Send + Sync + 'static,
+    {
+        UpgradeHandler(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for UpgradeHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UpgradeHandler")
+    }
+}
+
+/// What a new connection speaks, judged from its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// A TLS record, as a `ClientHello` starts with the handshake content
+    /// type `0x16`.
+    Tls,
+    /// A plaintext HTTP/1 request line, or the blank lines that may precede
+    /// one.
+    Http,
+    /// Anything else.
+    Unknown,
+}
+
+impl Protocol {
+    /// Classifies the first bytes a client sent.
+    pub fn sniff(bytes: &[u8]) -> Protocol {
+        match bytes.first() {
+            Some(0x16) => Protocol::Tls,
+            Some(b) if b.is_ascii_alphabetic() || matches!(b, b'\r' | b'\n') => Protocol::Http,
+            _ => Protocol::Unknown,
+        }
+    }
+}
+
+/// Waits for the client's first byte and classifies it, without consuming
+/// anything, so the stream can be handed on intact.
+///
+/// Waits as long as the stream's read timeout allows.
+///
+/// # Errors
+///
+/// Returns the error from the peek, or `UnexpectedEof` if the client
+/// closed without sending anything.
+pub fn detect_protocol(stream: &TcpStream) -> io::Result<Protocol> {
+    let mut first = [0; 1];
+    match stream.peek(&mut first)? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Ok(Protocol::sniff(&first)),
+    }
+}
+
+/// Callback that takes over connections that open with TLS, for serving
+/// TLS and plaintext HTTP on one port.
+///
+/// It receives the stream untouched, `ClientHello` and all.
+#[derive(Clone)]
+pub struct TlsHandler(Arc<dyn Fn(TcpStream) + Send + Sync>);
+
+impl TlsHandler {
+    pub fn new<F>(handler: F) -> TlsHandler
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        TlsHandler(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for TlsHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TlsHandler")
+    }
+}
+
+/// State shared between the accept loop and connection handlers.
+struct Shared {
+    config: ServerConfig,
+    sites: VHostRouter,
+    draining: AtomicBool,
+    /// Open connections per client address, for `max_connections_per_ip`.
+    per_ip: Arc<IpLimiter>,
+}
+
+/// Caps how many connections each client address holds at once.
+struct IpLimiter {
+    max: Option<usize>,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl IpLimiter {
+    fn new(max: Option<usize>) -> IpLimiter {
+        IpLimiter {
+            max,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a new connection from `ip`, or returns `None` if `ip` is
+    /// already at the cap. The count drops when the permit is dropped.
+    fn try_acquire(self: &Arc<IpLimiter>, ip: IpAddr) -> Option<IpPermit> {
+        let Some(max) = self.max else {
+            return Some(IpPermit(None));
+        };
+        let mut open = self.open.lock().expect("Mutex poisoned");
+        let count = open.entry(ip).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(IpPermit(Some((Arc::clone(self), ip))))
+    }
+}
+
+/// One connection's share of its address's `IpLimiter` count.
+struct IpPermit(Option<(Arc<IpLimiter>, IpAddr)>);
+
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let Some((limiter, ip)) = &self.0 else {
+            return;
+        };
+        let mut open = limiter.open.lock().expect("Mutex poisoned");
+        if let Some(count) = open.get_mut(ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(ip);
+            }
+        }
+    }
+}
+
+/// One address for `Server::bind_all` to listen on.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    /// Address to bind, such as `0.0.0.0:80`.
+    pub addr: String,
+    /// Overrides `ServerConfig::listen_backlog` for this listener.
+    pub listen_backlog: Option<u32>,
+    /// Answer every plaintext request on this listener with a `301` to its
+    /// `https` URL, built from the `Host` header, path, and query, instead
+    /// of routing it. Connections taken by `tls_handler` and the health and
+    /// readiness paths are unaffected.
+    pub force_https: bool,
+}
+
+impl ListenerConfig {
+    /// Create a listener for `addr` with the server's default backlog.
+    pub fn new(addr: impl Into<String>) -> ListenerConfig {
+        ListenerConfig {
+            addr: addr.into(),
+            listen_backlog: None,
+            force_https: false,
+        }
+    }
+}
+
+/// A bound socket and the per-listener options that apply to it.
+struct BoundListener {
+    socket: TcpListener,
+    force_https: bool,
+}
+
+/// TCP listeners that dispatch each connection to a shared `ThreadPool`.
+pub struct Server {
+    listeners: Mutex<Vec<BoundListener>>,
+    local_addrs: Vec<SocketAddr>,
+    pool: ThreadPool,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    /// Binds a listener to `addr` and creates the worker pool.
+    ///
+    /// `sites` is either a single `Router` or a `VHostRouter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::BindError` if the address cannot be bound, or
+    /// `ServerError::PoolError` if the pool cannot be created.
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        config: ServerConfig,
+        sites: impl Into<VHostRouter>,
+    ) -> Result<Server, ServerError> {
+        let socket = bind_listener(addr, config.listen_backlog)?;
+        let listener = BoundListener {
+            socket,
+            force_https: false,
+        };
+        Server::with_listeners(vec![listener], config, sites)
+    }
+
+    /// Binds every listener in `listeners`, all served by one worker pool.
+    ///
+    /// `run` then accepts on all of them, and `max_connections` counts
+    /// connections across them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ServerError::BindError` if `listeners` is empty or any
+    /// address cannot be bound, or `ServerError::PoolError` if the pool
+    /// cannot be created.
+    pub fn bind_all(
+        listeners: Vec<ListenerConfig>,
+        config: ServerConfig,
+        sites: impl Into<VHostRouter>,
+    ) -> Result<Server, ServerError> {
+        if listeners.is_empty() {
+            return Err(ServerError::BindError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no listeners configured",
+            )));
+        }
+        let listeners = listeners
+            .iter()
+            .map(|listener| {
+                let backlog = listener.listen_backlog.or(config.listen_backlog);
+                Ok(BoundListener {
+                    socket: bind_listener(listener.addr.as_str(), backlog)?,
+                    force_https: listener.force_https,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Server::with_listeners(listeners, config, sites)
+    }
+
+    fn with_listeners(
+        listeners: Vec<BoundListener>,
+        config: ServerConfig,
+        sites: impl Into<VHostRouter>,
+    ) -> Result<Server, ServerError> {
+        let local_addrs = listeners
+            .iter()
+            .map(|listener| listener.socket.local_addr())
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(ServerError::BindError)?;
+        if config.drain_on_signal {
+            signals::reset();
+            if let Err(e) = signals::install() {
+                warning!("Failed to install signal handlers: {}", e);
+            }
+        }
+        let pool = ThreadPool::builder(config.pool_size)
+            .clock(Arc::clone(&config.clock))
+            .build()
+            .map_err(ServerError::PoolError)?;
+
+        Ok(Server {
+            listeners: Mutex::new(listeners),
+            local_addrs,
+            pool,
+            shared: Arc::new(Shared {
+                per_ip: Arc::new(IpLimiter::new(config.max_connections_per_ip)),
+                config,
+                sites: sites.into(),
+                draining: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Returns the address the server is listening on; the first one when
+    /// it has several.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// Returns the configuration the server runs with.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn config(&self) -> &ServerConfig {
+        &self.shared.config
+    }
+
+    /// Returns every address the server is listening on, in the order given
+    /// to `bind_all`.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Stops taking on new work while letting in-flight requests complete.
+    ///
+    /// The accept loop in `run` exits and closes the listener, so new
+    /// connections are refused, and the readiness endpoint starts failing for
+    /// requests still being served. Jobs already queued in the pool still run.
+    pub fn drain(&self) {
+        self.shared.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` once `drain` has been called.
+    pub fn is_draining(&self) -> bool {
+        self.shared.draining.load(Ordering::SeqCst)
+    }
+
+    /// Accepts connections until `max_connections` is reached or the server
+    /// starts draining, then closes the listeners.
+    ///
+    /// With several listeners, each gets its own accept thread; `run`
+    /// returns once all of them have stopped. While `accept_high_watermark`
+    /// connections are waiting for a worker, accepting pauses so the listen
+    /// backlog absorbs the excess.
+    ///
+    /// Dropping the server afterwards waits for in-flight connections.
+    pub fn run(&self) {
+        let listeners = std::mem::take(&mut *self.listeners.lock().expect("Mutex poisoned"));
+        let remaining = AtomicUsize::new(self.shared.config.max_connections.unwrap_or(usize::MAX));
+
+        thread::scope(|scope| {
+            for listener in &listeners {
+                scope.spawn(|| self.accept_loop(listener, &remaining));
+            }
+        });
+    }
+
+    fn accept_loop(&self, listener: &BoundListener, remaining: &AtomicUsize) {
+        if let Err(e) = listener.socket.set_nonblocking(true) {
+            error!("Failed to configure listener: {}", e);
+            return;
+        }
+
+        while remaining.load(Ordering::SeqCst) > 0 && !self.is_draining() {
+            if self.shared.config.drain_on_signal && signals::shutdown_requested() {
+                info!("Shutdown signal received; draining");
+                self.drain();
+                break;
+            }
+            if let Some(watermark) = self.shared.config.accept_high_watermark
+                && self.pool.queued_jobs() >= watermark
+            {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+
+            match listener.socket.accept() {
+                Ok((stream, peer)) => {
+                    // Another listener may have taken the last slot in the
+                    // meantime; this connection is served regardless.
+                    let _ = remaining
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        warning!("Connection failed: {}", e);
+                        continue;
+                    }
+                    let Some(permit) = self.shared.per_ip.try_acquire(peer.ip()) else {
+                        warning!("Refusing connection from {}: too many open", peer.ip());
+                        refuse_connection(stream, &self.shared.config);
+                        continue;
+                    };
+
+                    let shared = Arc::clone(&self.shared);
+                    let force_https = listener.force_https;
+                    let res = self.pool.execute(move || {
+                        handle_connection(stream, &shared, force_https);
+                        drop(permit);
+                    });
+
+                    if let Err(e) = res {
+                        error!("Failed to send job to pool: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                }
+                Err(e) => warning!("Connection failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Returns `true` if the client would rather have a JSON error document
+/// than an HTML page; without `Accept`, HTML wins the tie.
+fn prefers_json(headers: &Headers) -> bool {
+    request::preferred_media(
+        headers,
+        &["text/html", "application/problem+json", "application/json"],
+    )
+    .is_some_and(|mime| mime != "text/html")
+}
+
+/// Looks for `Accept` in the still-unread part of a request head that
+/// failed before its headers were parsed, such as one with a bad request
+/// line. Only what has already arrived is searched.
+fn buffered_accept(buffered: &[u8]) -> Headers {
+    let mut headers = Headers::new();
+    for line in buffered.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if let Ok(line) = str::from_utf8(line)
+            && let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("Accept")
+        {
+            headers.append("Accept", value.trim());
+            break;
+        }
+    }
+    headers
+}
+
+/// Answers `503 Service Unavailable` on a connection that will not be
+/// served, without parsing its request.
+///
+/// With `problem_json` on, the first `linger_timeout` of input is searched
+/// for an `Accept` header. The connection then closes through `linger`,
+/// like any other, so the client's unread request cannot reset it before
+/// the `503` is read.
+fn refuse_connection(stream: TcpStream, config: &ServerConfig) {
+    let message = "Too many connections from your address.";
+    let mut reader = ConnectionReader::new(&stream, config.read_buffer_size);
+    let wants_problem = config.problem_json
+        && stream.set_read_timeout(Some(config.linger_timeout)).is_ok()
+        && reader.fill_buf().is_ok()
+        && prefers_json(&buffered_accept(reader.buffer()));
+    let mut response = if wants_problem {
+        error_pages::problem_json(503, message)
+    } else {
+        config.error_pages.render(503, message)
+    }
+    .with_header("Connection", "close");
+    apply_server_headers(&mut response, config);
+    if write_response(&response, &mut &stream).is_ok() {
+        linger(&mut reader, &stream, config.linger_timeout);
+    }
+}
+
+/// Binds one listener, applying `backlog` if given.
+fn bind_listener(
+    addr: impl ToSocketAddrs,
+    backlog: Option<u32>,
+) -> Result<TcpListener, ServerError> {
+    let listener = TcpListener::bind(addr).map_err(ServerError::BindError)?;
+    if let Some(backlog) = backlog {
+        set_listen_backlog(&listener, backlog).map_err(ServerError::BindError)?;
+    }
+    Ok(listener)
+}
+
+/// Resizes the pending-connection queue of an already listening socket.
+///
+/// Calling `listen` again on a listening socket only updates its backlog,
+/// which std does not expose.
+#[cfg(unix)]
+fn set_listen_backlog(listener: &TcpListener, backlog: u32) -> io::Result<()> {
+    use std::os::{fd::AsRawFd, raw::c_int};
+
+    unsafe extern "C" {
+        fn listen(socket: c_int, backlog: c_int) -> c_int;
+    }
+
+    let backlog = c_int::try_from(backlog).unwrap_or(c_int::MAX);
+    // SAFETY: the descriptor is owned by `listener` and stays open for the
+    // duration of the call.
+    if unsafe { listen(listener.as_raw_fd(), backlog) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn set_listen_backlog(_listener: &TcpListener, _backlog: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Serves requests on one connection until either side closes it.
+///
+/// With `force_https`, requests are redirected rather than routed.
+fn handle_connection(stream: TcpStream, shared: &Shared, force_https: bool) {
+    let config = &shared.config;
+    if let Some(TlsHandler(handler)) = &config.tls_handler {
+        let sniffed = stream
+            .set_read_timeout(Some(config.keep_alive_timeout))
+            .and_then(|()| detect_protocol(&stream));
+        match sniffed {
+            Ok(Protocol::Tls) => return handler(stream),
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+    let clock = &*config.clock;
+    let deadline = config
+        .max_connection_duration
+        .map(|duration| clock.now() + duration);
+    let past_deadline = || deadline.is_some_and(|deadline| clock.now() >= deadline);
+    let peer_addr = stream.peer_addr().ok();
+    let parse_config = ParseConfig {
+        max_uri_length: config.max_uri_length,
+        max_single_header_bytes: config.max_single_header_bytes,
+        min_body_rate: config.min_body_rate,
+        max_body_size: config.max_body_size,
+        expect_rejection: config.expect_rejection,
+        clock: Arc::clone(&config.clock),
+    };
+    let mut reader = ConnectionReader::new(&stream, config.read_buffer_size);
+    let mut writer = &stream;
+    let mut served = 0;
+    let mut context = ConnectionContext::default();
+
+    loop {
+        // Waiting for the next request must not outlast the deadline either.
+        let idle_timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(clock.now()) {
+                Some(left) if !left.is_zero() => left.min(config.keep_alive_timeout),
+                _ => return,
+            },
+            None => config.keep_alive_timeout,
+        };
+        if !wait_for_request(&mut reader, &stream, idle_timeout, shared) {
+            return;
+        }
+        if let Err(e) = stream.set_read_timeout(Some(idle_timeout)) {
+            warning!("Failed to configure connection: {}", e);
+            return;
+        }
+
+        let mut is_head = false;
+        let mut wants_problem = false;
+        let mut head_headers = Headers::new();
+        let (mut response, keep_alive, version, request_line) =
+            match Request::parse_head(&mut reader, &parse_config, &mut head_headers).and_then(
+                |head| match head {
+                    Some(mut request) => {
+                        // Decided before the body is read, so a body error can be
+                        // negotiated too.
+                        wants_problem = config.problem_json && prefers_json(&request.headers);
+                        let reusable =
+                            receive_body(&mut request, &mut reader, shared, &parse_config)?;
+                        Ok(Some((request, reusable)))
+                    }
+                    None => Ok(None),
+                },
+            ) {
+                Ok(Some((mut request, reusable))) => {
+                    request.peer_addr = peer_addr;
+                    request.real_ip = peer_addr.map(|peer| {
+                        request::resolve_real_ip(
+                            peer.ip(),
+                            request.headers.get("X-Forwarded-For"),
+                            &config.trusted_proxies,
+                        )
+                    });
+
+                    if let Some(handler) = &config.h2c_handler
+                        && wants_h2c(&request)
+                    {
+                        let switch = "HTTP/1.1 101 Switching Protocols\r\n\
+                                  Connection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+                        if let Err(e) = writer.write_all(switch.as_bytes()) {
+                            warning!("Failed to write response to stream: {}", e);
+                            return;
+                        }
+                        let buffered = reader.buffer().to_vec();
+                        drop(reader);
+                        (handler.0)(request, Upgraded { stream, buffered });
+                        return;
+                    }
+
+                    let request_line =
+                        format!("{} {} {}", request.method, request.path, request.version);
+                    is_head = request.method == Method::Head;
+                    request.connection = mem::take(&mut context);
+                    let mut response = dispatch(&mut request, shared, force_https);
+                    context = mem::take(&mut request.connection);
+                    if request
+                        .body_reader
+                        .as_ref()
+                        .is_some_and(BodyReader::exceeded)
+                    {
+                        response = Response::error(413, "The request body exceeds the size limit.");
+                    }
+                    served += 1;
+                    let keep_alive = reusable
+                        && config.keep_alive
+                        && request.wants_keep_alive()
+                        && !shared.draining.load(Ordering::SeqCst)
+                        && !past_deadline()
+                        && config
+                            .max_requests_per_connection
+                            .is_none_or(|max| served < max);
+                    (response, keep_alive, request.version, request_line)
+                }
+                // The client closed (or went idle) between requests.
+                Ok(None) => return,
+                Err(RequestError::Io(_)) => return,
+                Err(e) => {
+                    if config.problem_json && !wants_problem {
+                        if !head_headers.contains("Accept") {
+                            head_headers = buffered_accept(reader.buffer());
+                        }
+                        wants_problem = prefers_json(&head_headers);
+                    }
+                    let mut response = Response::error(e.status_code(), e.detail());
+                    if matches!(e, RequestError::UnsupportedMediaType(_)) {
+                        // Name the codings that would have been accepted
+                        // (RFC 9110, section 15.5.16).
+                        response.headers.insert("Accept-Encoding", "gzip");
+                    }
+                    (response, false, Version::Http11, String::from("-"))
+                }
+            };
+
+        if let Some(message) = response.error.take() {
+            // Keep headers such as `Allow` or `WWW-Authenticate`; swap the page.
+            if wants_problem {
+                let problem = error_pages::problem_json(response.status, &message);
+                response
+                    .headers
+                    .insert("Content-Type", "application/problem+json");
+                response.body = problem.body;
+            } else {
+                response.body = config.error_pages.render(response.status, &message).body;
+            }
+        }
+        if is_head {
+            response.strip_body();
+        }
+        if version == Version::Http10
+            && response.is_chunked()
+            && let Err(e) = response.buffer_chunks()
+        {
+            warning!("Failed to generate response body: {}", e);
+            return;
+        }
+        apply_server_headers(&mut response, config);
+        if !keep_alive {
+            response.headers.insert("Connection", "close");
+        } else {
+            if version == Version::Http10 {
+                response.headers.insert("Connection", "keep-alive");
+            }
+            let mut params = format!("timeout={}", keep_alive_secs(config.keep_alive_timeout));
+            if let Some(max) = config.max_requests_per_connection {
+                params.push_str(&format!(", max={}", max - served));
+            }
+            response.headers.insert("Keep-Alive", params);
+        }
+
+        if let Err(e) = write_response(&response, &mut writer) {
+            warning!("Failed to write response to stream: {}", e);
+            return;
+        }
+        let peer = peer_addr.map_or_else(|| String::from("-"), |addr| addr.to_string());
+        let line = format_args!(
+            "{peer} \"{request_line}\" {} {}",
+            response.status,
+            response.body.len()
+        );
+        match &config.access_log {
+            Some(sink) => {
+                if let Err(e) = sink.write_line(&line.to_string()) {
+                    warning!("Failed to write access log: {}", e);
+                }
+            }
+            None => info!("{line}"),
+        }
+        if !keep_alive {
+            linger(&mut reader, &stream, config.linger_timeout);
+            return;
+        }
+    }
+}
+
+/// Waits up to `idle_timeout` for the first byte of the next request,
+/// returning `false` if the connection closed or stayed idle.
+///
+/// The wait is cut into `DRAIN_POLL_INTERVAL` slices so that a drain, or
+/// the server's clock passing the timeout, is noticed; once draining, the
+/// client gets only `drain_timeout` more.
+fn wait_for_request(
+    reader: &mut ConnectionReader,
+    stream: &TcpStream,
+    idle_timeout: Duration,
+    shared: &Shared,
+) -> bool {
+    let clock = &*shared.config.clock;
+    let mut deadline = clock.now() + idle_timeout;
+    let mut drain_noticed = false;
+    loop {
+        if !drain_noticed && shared.draining.load(Ordering::SeqCst) {
+            drain_noticed = true;
+            deadline = deadline.min(clock.now() + shared.config.drain_timeout);
+        }
+        let left = deadline.saturating_duration_since(clock.now());
+        if left.is_zero() {
+            return false;
+        }
+        if let Err(e) = stream.set_read_timeout(Some(left.min(DRAIN_POLL_INTERVAL))) {
+            warning!("Failed to configure connection: {}", e);
+            return false;
+        }
+        match reader.fill_buf() {
+            Ok(buf) => return !buf.is_empty(),
+            Err(e) if request::is_timeout(&e) || e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Gets `request`'s body ready for dispatch, returning whether another
+/// request can follow it on the connection.
+///
+/// A client waiting on `Expect: 100-continue` is told to go ahead only if
+/// a route takes the request; otherwise its body is never read and the
+/// handler, or the `404`, sees none. Streaming routes get the body unread,
+/// through a reader that owns what was buffered past the head, so the
+/// connection closes after them.
+fn receive_body(
+    request: &mut Request,
+    reader: &mut ConnectionReader,
+    shared: &Shared,
+    config: &ParseConfig,
+) -> Result<bool, RequestError> {
+    if !request.has_body() {
+        return Ok(true);
+    }
+    let router = shared.sites.router_for(request.host());
+    if request.expects_continue() {
+        if !router.has_route(&request.method, &request.path) {
+            return Ok(false);
+        }
+        let mut writer = reader.stream;
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+
+    if router.streams_body(&request.method, &request.path) {
+        let buffered = reader.buffer().to_vec();
+        reader.consume(buffered.len());
+        let stream = reader.stream.try_clone()?;
+        let source = BufReader::new(io::Cursor::new(buffered).chain(stream));
+        request.stream_body(source, config)?;
+        return Ok(false);
+    }
+    request.read_body(reader, config)?;
+    Ok(true)
+}
+
+/// Most bytes `linger` discards before giving up on the client.
+const LINGER_LIMIT: u64 = 64 * 1024;
+
+/// Closes a connection after its last response without losing it.
+///
+/// Closing a socket with unread input makes the kernel send a reset, which
+/// can destroy the response before the client reads it. Shutting down the
+/// write half first sends a clean EOF; the client's remaining bytes (an
+/// unread body, say) are then read and discarded until it closes too, for
+/// at most `timeout` or `LINGER_LIMIT` bytes.
+///
+/// With no input waiting there is nothing to cause a reset, so the
+/// connection is closed at once rather than holding a worker.
+fn linger(reader: &mut ConnectionReader, stream: &TcpStream, timeout: Duration) {
+    if stream.shutdown(Shutdown::Write).is_err() {
+        return;
+    }
+    if reader.buffer().is_empty() && !input_pending(stream) {
+        return;
+    }
+    let deadline = Instant::now() + timeout;
+    let mut discarded = 0;
+    let mut buf = [0; 4 * 1024];
+    while discarded < LINGER_LIMIT {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || stream.set_read_timeout(Some(left)).is_err() {
+            return;
+        }
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => discarded += n as u64,
+        }
+    }
+}
+
+/// Returns `true` if the client has sent bytes not yet read, without
+/// waiting for any.
+fn input_pending(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let pending = matches!(stream.peek(&mut [0]), Ok(n) if n > 0);
+    let _ = stream.set_nonblocking(false);
+    pending
+}
+
+/// Writes `response`, assembling small ones in the thread's scratch buffer
+/// first with `Response::write_small`.
+fn write_response<W: Write>(response: &Response, writer: &mut W) -> io::Result<()> {
+    if response.is_streamed() || response.body.len() > SCRATCH_LIMIT {
+        return response.write_to(writer);
+    }
+    SCRATCH.with_borrow_mut(|scratch| {
+        scratch.clear();
+        response.write_small(scratch)?;
+        let result = writer.write_all(scratch).and_then(|()| writer.flush());
+        if scratch.capacity() > SCRATCH_LIMIT {
+            *scratch = Vec::new();
+        }
+        result
+    })
+}
+
+/// Returns `true` if the request offers an upgrade to cleartext HTTP/2.
+fn wants_h2c(req: &Request) -> bool {
+    let has_token = |name: &str, token: &str| {
+        req.headers.get(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    req.version == Version::Http11
+        && has_token("Upgrade", "h2c")
+        && has_token("Connection", "upgrade")
+}
+
+/// The whole seconds to advertise in `Keep-Alive: timeout`, rounded up so
+/// a sub-second timeout is not announced as `timeout=0`.
+fn keep_alive_secs(timeout: Duration) -> u64 {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    secs.max(1)
+}
+
+/// Adds the headers the server manages itself.
+///
+/// Server-managed headers always come first, followed by the handler's
+/// headers in the order they were set, so output is deterministic.
+fn apply_server_headers(response: &mut Response, config: &ServerConfig) {
+    let mut headers = Headers::new();
+    if let Some(server) = &config.server_header {
+        headers.append("Server", server.as_str());
+    }
+
+    response.headers.remove("Server");
+    for (name, value) in response.headers.iter() {
+        headers.append(name, value);
+    }
+    response.headers = headers;
+}
+
+fn dispatch(req: &mut Request, shared: &Shared, force_https: bool) -> Response {
+    let config = &shared.config;
+
+    if config.health_path.as_deref() == Some(req.path.as_str()) {
+        return Response::text(200, "ok");
+    }
+    if config.readiness_path.as_deref() == Some(req.path.as_str()) {
+        return match shared.draining.load(Ordering::SeqCst) {
+            true => Response::text(503, "draining"),
+            false => Response::text(200, "ready"),
+        };
+    }
+
+    // After the health checks, which a load balancer probes over plain HTTP.
+    if force_https {
+        let Some(host) = req.host() else {
+            return Response::error(400, "A Host header is required.");
+        };
+        let target = match &req.query {
+            Some(query) => format!("{}?{query}", req.path),
+            None => req.path.clone(),
+        };
+        return Response::redirect_https(host, &target);
+    }
+
+    let router = shared.sites.router_for(req.host());
+
+    if config.redirect_trailing_slash
+        && req.path != "/"
+        && !router.has_route(&req.method, &req.path)
+    {
+        let canonical = match req.path.strip_suffix('/') {
+            Some(trimmed) => trimmed.to_string(),
+            None => format!("{}/", req.path),
+        };
+
+        if router.has_route(&req.method, &canonical) {
+            let location = match &req.query {
+                Some(query) => format!("{canonical}?{query}"),
+                None => canonical,
+            };
+            return Response::new(301).with_header("Location", location);
+        }
+    }
+
+    router.handle(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, router::Router};
+    use std::io::{BufRead, Read, Write};
+
+    /// Reads one response, using `Content-Length` to find the end of the body.
+    fn read_response<R: BufRead>(reader: &mut R) -> String {
+        let mut response = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            response.push_str(&line);
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+        }
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        response.push_str(&String::from_utf8(body).unwrap());
+        response
+    }
+
+    /// Serves exactly one connection on an ephemeral port and returns the
+    /// raw response to `raw_request`.
+    fn round_trip(config: ServerConfig, router: Router, raw_request: &str) -> String {
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..config
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw_request.as_bytes()).unwrap();
+        let response = read_response(&mut BufReader::new(&stream));
+        drop(stream);
+        handle.join().unwrap();
+        response
+    }
+
+    fn slash_router() -> Router {
+        let mut router = Router::new();
+        router.get("/about", |_| Response::text(200, "about"));
+        router.get("/docs/", |_| Response::text(200, "docs"));
+        router
+    }
+
+    fn redirect_config() -> ServerConfig {
+        ServerConfig {
+            redirect_trailing_slash: true,
+            ..ServerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_redirects_trailing_slash_to_canonical() {
+        let response = round_trip(
+            redirect_config(),
+            slash_router(),
+            "GET /about/?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(response.contains("Location: /about?x=1\r\n"));
+    }
+
+    #[test]
+    fn test_redirects_missing_slash_to_canonical() {
+        let response = round_trip(
+            redirect_config(),
+            slash_router(),
+            "GET /docs HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(response.contains("Location: /docs/\r\n"));
+    }
+
+    #[test]
+    fn test_force_https_redirects_every_request() {
+        let config = ServerConfig {
+            max_connections: Some(3),
+            health_path: Some("/health".into()),
+            ..ServerConfig::default()
+        };
+        let listeners = vec![
+            ListenerConfig {
+                force_https: true,
+                ..ListenerConfig::new("127.0.0.1:0")
+            },
+            ListenerConfig::new("127.0.0.1:0"),
+        ];
+        let server = Server::bind_all(listeners, config, slash_router()).unwrap();
+        let addrs = server.local_addrs().to_vec();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addrs[0]).unwrap();
+        stream
+            .write_all(b"GET /about?x=1 HTTP/1.1\r\nHost: example.com:7878\r\n\r\n")
+            .unwrap();
+        let redirected = read_response(&mut BufReader::new(&stream));
+        drop(stream);
+        let health = get(addrs[0], "/health");
+        let plain = get(addrs[1], "/about");
+        handle.join().unwrap();
+
+        assert!(redirected.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(redirected.contains("Location: https://example.com/about?x=1\r\n"));
+        assert!(health.starts_with("HTTP/1.1 200 OK\r\n"), "{health}");
+        assert!(plain.starts_with("HTTP/1.1 200 OK\r\n"), "{plain}");
+    }
+
+    #[test]
+    fn test_server_header_is_configurable() {
+        let router = || {
+            let mut router = Router::new();
+            router.get("/", |_| {
+                Response::text(200, "hi")
+                    .with_header("Server", "handler")
+                    .with_header("X-Custom", "1")
+            });
+            router
+        };
+        let raw = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let config = ServerConfig {
+            server_header: Some("hello/1.0".into()),
+            ..ServerConfig::default()
+        };
+        let response = round_trip(config, router(), raw);
+        assert!(response.starts_with(
+            "HTTP/1.1 200 OK\r\nServer: hello/1.0\r\nContent-Type: text/plain; charset=utf-8\r\nX-Custom: 1\r\nKeep-Alive: timeout=5\r\nContent-Length: 2\r\n"
+        ));
+
+        let response = round_trip(ServerConfig::default(), router(), raw);
+        assert!(!response.contains("Server:"));
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        read_response(&mut BufReader::new(&stream))
+    }
+
+    /// Sends `raw` on a fresh connection, half-closes it, and returns
+    /// everything the server writes back before closing.
+    fn send_and_close(config: ServerConfig, raw: &[u8]) -> Vec<u8> {
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..config
+        };
+        let server = Server::bind("127.0.0.1:0", config, Router::new()).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        handle.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn test_access_line_logged_at_info() {
+        crate::logging::tests::captured();
+        let mut router = Router::new();
+        router.get("/access-logged", |_| Response::text(200, "hi"));
+        round_trip(
+            ServerConfig::default(),
+            router,
+            "GET /access-logged HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        );
+
+        let logged = crate::logging::tests::captured();
+        assert!(logged.iter().any(|(level, line)| {
+            *level == crate::logging::Level::Info
+                && line.ends_with("\"GET /access-logged HTTP/1.1\" 200 2")
+        }));
+    }
+
+    /// Decodes a chunked body, returning each chunk's data separately.
+    fn read_chunks<R: BufRead>(reader: &mut R) -> Vec<String> {
+        let mut chunks = Vec::new();
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = usize::from_str_radix(size.trim(), 16).unwrap();
+            let mut data = vec![0; size + 2];
+            reader.read_exact(&mut data).unwrap();
+            if size == 0 {
+                return chunks;
+            }
+            data.truncate(size);
+            chunks.push(String::from_utf8(data).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_chunked_handler_streams_frames() {
+        let mut router = Router::new();
+        router.get("/stream", |_| {
+            Response::chunked(200, |out| {
+                for part in ["one ", "two ", "three"] {
+                    out.write_all(part.as_bytes())?;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Ok(())
+            })
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /stream HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        let chunks = read_chunks(&mut reader);
+        assert_eq!(chunks, ["one ", "two ", "three"]);
+        assert_eq!(chunks.concat(), "one two three");
+        drop(reader);
+        drop(stream);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_failed_chunked_body_closes_connection() {
+        let mut router = Router::new();
+        router.get("/stream", |_| {
+            Response::chunked(200, |out| {
+                out.write_all(b"part")?;
+                Err(io::Error::other("generator failed"))
+            })
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // A keep-alive request: only the server closing ends the read.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /stream HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).unwrap();
+        assert!(raw.ends_with("\r\n\r\n4\r\npart\r\n"), "{raw:?}");
+        drop(stream);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_streaming_body_from_another_thread() {
+        let mut router = Router::new();
+        router.get("/feed", |_| {
+            let (response, body) = Response::streaming();
+            thread::spawn(move || {
+                for chunk in ["alpha ", "beta ", "gamma"] {
+                    thread::sleep(Duration::from_millis(10));
+                    body.send(chunk).unwrap();
+                }
+            });
+            response.with_header("Content-Type", "text/plain")
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /feed HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+
+        let chunks = read_chunks(&mut reader);
+        assert_eq!(chunks, ["alpha ", "beta ", "gamma"]);
+        assert_eq!(chunks.concat(), "alpha beta gamma");
+        drop(reader);
+        drop(stream);
+        handle.join().unwrap();
+    }
+
+    const H2C_UPGRADE: &str = "GET / HTTP/1.1\r\nHost: x\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                               Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n";
+
+    #[test]
+    fn test_h2c_upgrade_ignored_without_handler() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "http/1.1"));
+        let response = round_trip(ServerConfig::default(), router, H2C_UPGRADE);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!response.contains("Upgrade"));
+        assert!(response.ends_with("http/1.1"));
+    }
+
+    #[test]
+    fn test_h2c_upgrade_hands_off_to_handler() {
+        let config = ServerConfig {
+            max_connections: Some(1),
+            h2c_handler: Some(UpgradeHandler::new(|req, mut upgraded| {
+                let reply = format!("{} {}", req.path, upgraded.buffered.len());
+                upgraded.stream.write_all(reply.as_bytes()).unwrap();
+            })),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, Router::new()).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(H2C_UPGRADE.as_bytes()).unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        handle.join().unwrap();
+
+        assert!(output.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(output.contains("Upgrade: h2c\r\n"));
+        assert!(output.ends_with("\r\n\r\n/ 0"));
+    }
+
+    #[test]
+    fn test_detect_protocol() {
+        let client_hello = [0x16, 0x03, 0x01, 0x02, 0x00, 0x01];
+        assert_eq!(Protocol::sniff(&client_hello), Protocol::Tls);
+        assert_eq!(Protocol::sniff(b"GET / HTTP/1.1\r\n"), Protocol::Http);
+        assert_eq!(Protocol::sniff(b"\r\nPOST / HTTP/1.1\r\n"), Protocol::Http);
+        assert_eq!(Protocol::sniff(b"\0\0"), Protocol::Unknown);
+        assert_eq!(Protocol::sniff(b""), Protocol::Unknown);
+
+        // Peeking leaves the byte for whoever reads the stream next.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+        client.write_all(&client_hello).unwrap();
+        assert_eq!(detect_protocol(&accepted).unwrap(), Protocol::Tls);
+        let mut received = [0; 6];
+        accepted.read_exact(&mut received).unwrap();
+        assert_eq!(received, client_hello);
+    }
+
+    #[test]
+    fn test_tls_connections_go_to_tls_handler() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "plain"));
+        let config = ServerConfig {
+            max_connections: Some(2),
+            tls_handler: Some(TlsHandler::new(|mut stream| {
+                // Reading everything sent keeps the close from resetting.
+                let mut record = [0; 3];
+                stream.read_exact(&mut record).unwrap();
+                stream.write_all(&[record[0], b'!']).unwrap();
+            })),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut tls = TcpStream::connect(addr).unwrap();
+        tls.write_all(&[0x16, 0x03, 0x01]).unwrap();
+        let mut output = Vec::new();
+        tls.read_to_end(&mut output).unwrap();
+        assert_eq!(output, [0x16, b'!']);
+
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut BufReader::new(&plain));
+        assert!(response.ends_with("plain"), "{response}");
+        drop((tls, plain));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_versions() {
+        let status = |raw: &[u8]| {
+            let response = send_and_close(ServerConfig::default(), raw);
+            let response = String::from_utf8(response).unwrap();
+            assert!(response.contains("Connection: close\r\n"));
+            response[9..12].to_string()
+        };
+
+        assert_eq!(status(b"GET /\r\n\r\n"), "505");
+        assert_eq!(status(b"GET / HTTP/2.0\r\nHost: x\r\n\r\n"), "505");
+        assert_eq!(status(b"GET / HTTX/one\r\nHost: x\r\n\r\n"), "400");
+    }
+
+    #[test]
+    fn test_close_half_closes_after_response() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "bye"));
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        // Our write side stays open, yet the server's EOF arrives at once.
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nbye"));
+
+        // Late bytes are drained rather than answered with a reset.
+        stream.write_all(b"trailing garbage").unwrap();
+        drop(stream);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_without_unread_input_frees_the_worker() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "ok"));
+        let config = ServerConfig {
+            pool_size: 1,
+            max_connections: Some(2),
+            linger_timeout: Duration::from_secs(30),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // This client keeps its side open after the response.
+        let mut first = TcpStream::connect(addr).unwrap();
+        first
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        first.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+        // The only worker is free for the next connection well before the
+        // linger timeout.
+        let start = Instant::now();
+        let second = get(addr, "/");
+        assert!(second.starts_with("HTTP/1.1 200 OK\r\n"), "{second}");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        drop(first);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_oversized_expect_is_refused_before_the_body() {
+        for (rejection, status) in [
+            (ExpectRejection::ExpectationFailed, "417 Expectation Failed"),
+            (ExpectRejection::ContentTooLarge, "413 Content Too Large"),
+        ] {
+            let mut router = Router::new();
+            router.post("/upload", |_| Response::text(200, "stored"));
+            let config = ServerConfig {
+                max_connections: Some(1),
+                max_body_size: Some(1024),
+                expect_rejection: rejection,
+                ..ServerConfig::default()
+            };
+            let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+            let addr = server.local_addr();
+            let handle = thread::spawn(move || server.run());
+
+            // Only the head goes out; the answer must come without the body.
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(
+                    b"POST /upload HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\n\
+                      Content-Length: 10485760\r\n\r\n",
+                )
+                .unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let response = read_response(&mut BufReader::new(&stream));
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+                "{response}"
+            );
+            assert!(response.contains("Connection: close\r\n"));
+            drop(stream);
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_streamed_chunked_upload_under_expect() {
+        let mut router = Router::new();
+        router.add_streaming(Method::Put, "/upload", |req| {
+            let Some(body) = &mut req.body_reader else {
+                return Response::text(500, "no stream");
+            };
+            match io::copy(body, &mut io::sink()) {
+                Ok(n) => Response::text(200, n.to_string()),
+                Err(_) => Response::text(200, "aborted"),
+            }
+        });
+        let config = ServerConfig {
+            max_connections: Some(3),
+            max_body_size: Some(1000),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let upload = |path: &str, chunks: usize| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "PUT {path} HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n"
+            )
+            .unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut interim = String::new();
+            reader.read_line(&mut interim).unwrap();
+            if interim == "HTTP/1.1 100 Continue\r\n" {
+                reader.read_line(&mut interim).unwrap();
+                for _ in 0..chunks {
+                    // A failed write means the server already gave up.
+                    if stream.write_all(b"64\r\n").is_err()
+                        || stream.write_all(&[b'x'; 100]).is_err()
+                        || stream.write_all(b"\r\n").is_err()
+                    {
+                        break;
+                    }
+                }
+                let _ = stream.write_all(b"0\r\n\r\n");
+                interim.clear();
+            }
+            let response = interim + &read_response(&mut reader);
+            drop((stream, reader));
+            response
+        };
+
+        let response = upload("/upload", 5);
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("\r\n\r\n500"));
+
+        let response = upload("/upload", 20);
+        assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+        assert!(response.contains("Connection: close\r\n"));
+
+        // No route, so no go-ahead: the answer comes before any body.
+        let response = upload("/elsewhere", 0);
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_trickled_body_gets_408() {
+        let mut router = Router::new();
+        router.post("/upload", |req| {
+            Response::text(200, req.body.len().to_string())
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            min_body_rate: Some(MinBodyRate::new(100, Duration::from_millis(200))),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: x\r\nContent-Length: 1000\r\n\r\n")
+            .unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let trickle = {
+            let mut stream = stream.try_clone().unwrap();
+            let done = Arc::clone(&done);
+            // One byte every 50ms would take 50 seconds to finish.
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) && stream.write_all(b"x").is_ok() {
+                    thread::sleep(Duration::from_millis(50));
+                }
+            })
+        };
+
+        let started = Instant::now();
+        let response = read_response(&mut BufReader::new(&stream));
+        done.store(true, Ordering::SeqCst);
+        assert!(
+            response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        trickle.join().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_long_uri_is_rejected() {
+        let config = ServerConfig {
+            max_uri_length: Some(100),
+            ..ServerConfig::default()
+        };
+        let mut router = Router::new();
+        router.get("/*path", |_| Response::text(200, "found"));
+
+        let long = format!("/{}", "a".repeat(10_000));
+        let response = round_trip(
+            config.clone(),
+            router,
+            &format!("GET {long} HTTP/1.1\r\nHost: x\r\n\r\n"),
+        );
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        let mut router = Router::new();
+        router.get("/*path", |_| Response::text(200, "found"));
+        let ok = format!("/{}", "a".repeat(99));
+        let response = round_trip(
+            config,
+            router,
+            &format!("GET {ok} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n"),
+        );
+        assert!(response.ends_with("found"));
+    }
+
+    #[test]
+    fn test_long_header_line_is_rejected() {
+        let config = ServerConfig {
+            max_single_header_bytes: Some(1024),
+            ..ServerConfig::default()
+        };
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "found"));
+
+        let cookie = "a".repeat(32 * 1024);
+        let response = round_trip(
+            config.clone(),
+            router,
+            &format!("GET / HTTP/1.1\r\nHost: x\r\nCookie: {cookie}\r\n\r\n"),
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("Connection: close\r\n"));
+
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "found"));
+        // "Cookie: " and the value make exactly 1024 bytes.
+        let cookie = "a".repeat(1024 - 8);
+        let response = round_trip(
+            config,
+            router,
+            &format!("GET / HTTP/1.1\r\nHost: x\r\nCookie: {cookie}\r\nConnection: close\r\n\r\n"),
+        );
+        assert!(response.ends_with("found"), "{response}");
+    }
+
+    #[test]
+    fn test_head_uses_get_handler_without_body() {
+        let mut router = Router::new();
+        router.get("/page", |_| Response::text(200, "twelve bytes"));
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // The body is absent, so read until the server closes.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"HEAD /page HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        drop(stream);
+        handle.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 12\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_sse_events_are_framed() {
+        let mut router = Router::new();
+        router.get("/events", |_| {
+            Response::sse(|events| {
+                events.send_event(Some("greeting"), "hello")?;
+                events.send_event(None, "line one\nline two")
+            })
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("Cache-Control: no-cache\r\n"));
+
+        assert_eq!(
+            read_chunks(&mut reader),
+            [
+                "event: greeting\ndata: hello\n\n",
+                "data: line one\ndata: line two\n\n"
+            ]
+        );
+        drop(reader);
+        drop(stream);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_accept_pauses_above_high_watermark() {
+        let mut router = Router::new();
+        router.get("/", |_| {
+            thread::sleep(Duration::from_millis(20));
+            Response::text(200, "done")
+        });
+        let config = ServerConfig {
+            pool_size: 1,
+            max_connections: Some(10),
+            accept_high_watermark: Some(2),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(Server::bind("127.0.0.1:0", config, router).unwrap());
+        let addr = server.local_addr();
+        let runner = Arc::clone(&server);
+        let handle = thread::spawn(move || runner.run());
+
+        let clients: Vec<_> = (0..10)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    stream
+                        .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+                        .unwrap();
+                    read_response(&mut BufReader::new(&stream))
+                })
+            })
+            .collect();
+
+        let mut max_queued = 0;
+        while !handle.is_finished() {
+            max_queued = max_queued.max(server.pool.queued_jobs());
+            thread::sleep(Duration::from_millis(1));
+        }
+        for client in clients {
+            assert!(client.join().unwrap().ends_with("done"));
+        }
+        handle.join().unwrap();
+        assert!(max_queued <= 2, "queued {max_queued}");
+    }
+
+    #[test]
+    fn test_headers_larger_than_read_buffer() {
+        let mut router = Router::new();
+        router.get("/", |req| {
+            Response::text(
+                200,
+                req.headers.get("X-Large").unwrap_or("").len().to_string(),
+            )
+        });
+        let config = ServerConfig {
+            read_buffer_size: 16,
+            ..ServerConfig::default()
+        };
+        let large = "a".repeat(500);
+        let request =
+            format!("GET / HTTP/1.1\r\nHost: x\r\nX-Large: {large}\r\nConnection: close\r\n\r\n");
+        let response = round_trip(config, router, &request);
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("\r\n\r\n500"), "{response}");
+    }
+
+    #[test]
+    fn test_custom_listen_backlog() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "hi"));
+        let config = ServerConfig {
+            listen_backlog: Some(1024),
+            ..ServerConfig::default()
+        };
+        let response = round_trip(config, router, "GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("hi"), "{response}");
+    }
+
+    #[test]
+    fn test_connection_closes_after_max_duration() {
+        let mut router = Router::new();
+        router.get("/", |_| {
+            thread::sleep(Duration::from_millis(60));
+            Response::text(200, "ok")
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            max_connection_duration: Some(Duration::from_millis(100)),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let request = "GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        stream.write_all(request.repeat(3).as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        drop(stream);
+        handle.join().unwrap();
+
+        // The second response finishes past the deadline and ends the
+        // connection; the third request is never answered.
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2, "{response}");
+        let (first, second) = response.split_at(response.rfind("HTTP/1.1").unwrap());
+        assert!(!first.contains("Connection: close"), "{response}");
+        assert!(second.contains("Connection: close"), "{response}");
+    }
+
+    #[test]
+    fn test_keep_alive_header_counts_down() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "ok"));
+        let config = ServerConfig {
+            max_connections: Some(1),
+            max_requests_per_connection: Some(3),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut exchange = || {
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+                .unwrap();
+            read_response(&mut reader)
+        };
+        assert!(exchange().contains("Keep-Alive: timeout=5, max=2\r\n"));
+        assert!(exchange().contains("Keep-Alive: timeout=5, max=1\r\n"));
+        let last = exchange();
+        assert!(last.contains("Connection: close\r\n"), "{last}");
+        assert!(!last.contains("Keep-Alive"), "{last}");
+        drop((stream, reader));
+        handle.join().unwrap();
+
+        assert_eq!(keep_alive_secs(Duration::from_millis(200)), 1);
+        assert_eq!(keep_alive_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(keep_alive_secs(Duration::from_secs(5)), 5);
+        assert_eq!(keep_alive_secs(Duration::ZERO), 1);
+    }
+
+    #[test]
+    fn test_connection_context_lasts_for_the_connection() {
+        struct Token(String);
+
+        let mut router = Router::new();
+        router.get("/login", |req| {
+            req.connection.insert(Token("secret".into()));
+            Response::text(200, "ok")
+        });
+        router.get("/whoami", |req| match req.connection.get::<Token>() {
+            Some(Token(token)) => Response::text(200, token.clone()),
+            None => Response::text(401, "anonymous"),
+        });
+        let config = ServerConfig {
+            max_connections: Some(2),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        for path in ["/login", "/whoami"] {
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            let response = read_response(&mut reader);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+            if path == "/whoami" {
+                assert!(response.ends_with("\r\n\r\nsecret"), "{response}");
+            }
+        }
+        drop((stream, reader));
+
+        // A new connection starts with an empty context.
+        let response = get(addr, "/whoami");
+        assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_read_buffer_reuse_starts_each_connection_empty() {
+        let mut router = Router::new();
+        router.get("/a", |_| Response::text(200, "first"));
+        router.get("/b", |_| Response::text(200, "second"));
+        let config = ServerConfig {
+            pool_size: 1,
+            max_connections: Some(2),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // Both connections run on the single worker. Leave half a request
+        // in the worker's buffer when the client leaves.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTT")
+            .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut reader = BufReader::new(&stream);
+        assert!(read_response(&mut reader).ends_with("first"));
+        assert!(read_response(&mut reader).starts_with("HTTP/1.1 400"));
+        drop(reader);
+        drop(stream);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /b HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut BufReader::new(&stream));
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("second"));
+        drop(stream);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_scratch_buffer_reuse_keeps_responses_intact() {
+        let mut router = Router::new();
+        router.get("/long", |_| Response::text(200, "x".repeat(1000)));
+        router.get("/short", |_| Response::text(200, "y"));
+        router.get("/huge", |_| {
+            Response::text(200, "z".repeat(SCRATCH_LIMIT + 1))
+        });
+        let config = ServerConfig {
+            pool_size: 1,
+            max_connections: Some(2),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // Both connections run on the single worker and share its buffer.
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            for (path, body) in [
+                ("/long", "x".repeat(1000)),
+                ("/short", "y".into()),
+                ("/huge", "z".repeat(SCRATCH_LIMIT + 1)),
+                ("/short", "y".into()),
+            ] {
+                write!(stream, "GET {path} HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+                let response = read_response(&mut reader);
+                let length = format!("Content-Length: {}\r\n", body.len());
+                assert!(response.contains(&length), "{path}");
+                assert!(response.ends_with(&format!("\r\n\r\n{body}")), "{path}");
+            }
+        }
+        handle.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sigterm_drains_server() {
+        unsafe extern "C" {
+            fn raise(signum: std::os::raw::c_int) -> std::os::raw::c_int;
+        }
+
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "up"));
+        let config = ServerConfig {
+            drain_on_signal: true,
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(Server::bind("127.0.0.1:0", config, router).unwrap());
+        let runner = Arc::clone(&server);
+        let handle = thread::spawn(move || runner.run());
+        assert!(get(server.local_addr(), "/").ends_with("up"));
+
+        // SAFETY: the handler installed by `bind` only sets a flag.
+        assert_eq!(unsafe { raise(15) }, 0);
+        handle.join().unwrap();
+        assert!(server.is_draining());
+
+        // The signal was for the first server; the next one starts afresh.
+        let config = ServerConfig {
+            drain_on_signal: true,
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "up again"));
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        assert!(!signals::shutdown_requested());
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+        assert!(get(addr, "/").ends_with("up again"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_two_listeners_share_one_pool() {
+        let mut router = Router::new();
+        router.get("/", |_| {
+            let worker = thread::current().id();
+            Response::text(200, format!("{worker:?}"))
+        });
+        let config = ServerConfig {
+            pool_size: 1,
+            max_connections: Some(2),
+            ..ServerConfig::default()
+        };
+        let listeners = vec![
+            ListenerConfig::new("127.0.0.1:0"),
+            ListenerConfig {
+                listen_backlog: Some(16),
+                ..ListenerConfig::new("127.0.0.1:0")
+            },
+        ];
+        let server = Server::bind_all(listeners, config, router).unwrap();
+        let addrs = server.local_addrs().to_vec();
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+        let handle = thread::spawn(move || server.run());
+
+        let first = get(addrs[0], "/");
+        let second = get(addrs[1], "/");
+        handle.join().unwrap();
+        assert!(first.starts_with("HTTP/1.1 200 OK"), "{first}");
+        // The single worker answered on both ports.
+        let worker = |response: &str| response.rsplit("\r\n").next().unwrap().to_string();
+        assert_eq!(worker(&first), worker(&second));
+
+        assert!(matches!(
+            Server::bind_all(Vec::new(), ServerConfig::default(), Router::new()),
+            Err(ServerError::BindError(_))
+        ));
+    }
+
+    #[test]
+    fn test_ip_limiter_counts_per_address() {
+        let limiter = Arc::new(IpLimiter::new(Some(2)));
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let first = limiter.try_acquire(a).unwrap();
+        let _second = limiter.try_acquire(a).unwrap();
+        assert!(limiter.try_acquire(a).is_none());
+        assert!(limiter.try_acquire(b).is_some());
+
+        drop(first);
+        assert!(limiter.try_acquire(a).is_some());
+    }
+
+    #[test]
+    fn test_excess_connections_from_one_ip_are_refused() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "ok"));
+        let config = ServerConfig {
+            max_connections: Some(3),
+            max_connections_per_ip: Some(2),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // Two idle keep-alive connections hold the address's allowance.
+        let held: Vec<_> = (0..2)
+            .map(|_| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream
+                    .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+                    .unwrap();
+                assert!(read_response(&mut BufReader::new(&stream)).ends_with("ok"));
+                stream
+            })
+            .collect();
+
+        let mut refused = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert!(response.contains("Connection: close\r\n"), "{response}");
+
+        drop(held);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_refused_connection_sends_problem_json_and_lingers() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "ok"));
+        let config = ServerConfig {
+            max_connections: Some(2),
+            max_connections_per_ip: Some(1),
+            problem_json: true,
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let held = TcpStream::connect(addr).unwrap();
+        (&held)
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut BufReader::new(&held)).ends_with("ok"));
+
+        // The refused request carries a body the server never reads; only
+        // lingering keeps it from resetting the connection.
+        let mut refused = TcpStream::connect(addr).unwrap();
+        let mut request = b"POST / HTTP/1.1\r\nHost: x\r\nAccept: application/json\r\n\
+            Content-Length: 16384\r\n\r\n"
+            .to_vec();
+        request.resize(request.len() + 16384, b'x');
+        refused.write_all(&request).unwrap();
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert!(
+            response.contains("Content-Type: application/problem+json\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("\"status\":503"), "{response}");
+
+        drop(held);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_immediate_close_is_silent() {
+        assert!(send_and_close(ServerConfig::default(), b"").is_empty());
+    }
+
+    #[test]
+    fn test_partial_request_then_eof_is_bad_request() {
+        let response = send_and_close(ServerConfig::default(), b"GET /ind");
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_keep_alive_serves_multiple_requests() {
+        let mut router = Router::new();
+        router.get("/a", |_| Response::text(200, "first"));
+        router.get("/b", |_| Response::text(200, "second"));
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut reader).ends_with("first"));
+        assert!(read_response(&mut reader).ends_with("second"));
+
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let last = read_response(&mut reader);
+        assert!(last.contains("Connection: close\r\n"));
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        drop((stream, reader));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_get_body_is_discarded() {
+        let mut router = Router::new();
+        router.get("/a", |req| {
+            Response::text(200, format!("a {}", req.body.len()))
+        });
+        router.get("/b", |req| {
+            Response::text(200, format!("b {}", req.body.len()))
+        });
+        let config = ServerConfig {
+            max_connections: Some(1),
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // Were the body left unread, it would be taken for the next request.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream
+            .write_all(
+                b"GET /a HTTP/1.1\r\nHost: x\r\nContent-Length: 9\r\n\r\nGET /evil\
+                  GET /b HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let first = read_response(&mut reader);
+        assert!(first.starts_with("HTTP/1.1 200"), "{first}");
+        assert!(first.ends_with("\r\n\r\na 0"));
+        let second = read_response(&mut reader);
+        assert!(second.starts_with("HTTP/1.1 200"), "{second}");
+        assert!(second.ends_with("\r\n\r\nb 0"));
+        drop((stream, reader));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_drain_refuses_new_connections() {
+        let mut router = Router::new();
+        router.get("/slow", |_| {
+            thread::sleep(Duration::from_millis(200));
+            Response::text(200, "finished")
+        });
+        let config = ServerConfig {
+            readiness_path: Some("/ready".into()),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(Server::bind("127.0.0.1:0", config, router).unwrap());
+        let addr = server.local_addr();
+        let runner = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run())
+        };
+
+        assert!(get(addr, "/ready").starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let in_flight = thread::spawn(move || get(addr, "/slow"));
+        thread::sleep(Duration::from_millis(50));
+        server.drain();
+        assert!(server.is_draining());
+
+        runner.join().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+
+        let completed = in_flight.join().unwrap();
+        assert!(completed.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(completed.ends_with("finished"));
+    }
+
+    #[test]
+    fn test_drain_closes_idle_keep_alive_connections() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "hi"));
+        let config = ServerConfig {
+            keep_alive_timeout: Duration::from_secs(30),
+            drain_timeout: Duration::from_millis(200),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(Server::bind("127.0.0.1:0", config, router).unwrap());
+        let addr = server.local_addr();
+        let runner = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.run())
+        };
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut reader).ends_with("hi"));
+
+        // The client holds the socket open; dropping the server waits for
+        // its connection, but only until the drain timeout.
+        let started = Instant::now();
+        server.drain();
+        runner.join().unwrap();
+        drop(Arc::into_inner(server).unwrap());
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed < Duration::from_secs(2),
+            "shutdown took {elapsed:?}"
+        );
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        drop((stream, reader));
+    }
+
+    #[test]
+    fn test_keep_alive_timeout_reads_the_clock() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "hi"));
+        let clock = Arc::new(MockClock::new());
+        let config = ServerConfig {
+            keep_alive_timeout: Duration::from_secs(60),
+            max_connections: Some(1),
+            clock: Arc::clone(&clock) as _,
+            ..ServerConfig::default()
+        };
+        let server = Server::bind("127.0.0.1:0", config, router).unwrap();
+        let addr = server.local_addr();
+        let runner = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut reader).ends_with("hi"));
+
+        // Only the clock moves; the idle connection must close within a
+        // poll interval rather than after a real minute.
+        clock.advance(Duration::from_secs(61));
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn test_readiness_reports_draining() {
+        let shared = Shared {
+            config: ServerConfig {
+                health_path: Some("/health".into()),
+                readiness_path: Some("/ready".into()),
+                ..ServerConfig::default()
+            },
+            sites: Router::new().into(),
+            draining: AtomicBool::new(true),
+            per_ip: Arc::new(IpLimiter::new(None)),
+        };
+
+        let mut req = Request::new(crate::Method::Get, "/ready");
+        assert_eq!(dispatch(&mut req, &shared, false).status, 503);
+        let mut req = Request::new(crate::Method::Get, "/health");
+        assert_eq!(dispatch(&mut req, &shared, false).status, 200);
+    }
+
+    #[test]
+    fn test_custom_error_page_file() {
+        let path = std::env::temp_dir().join(format!("hello-404-{}.html", std::process::id()));
+        std::fs::write(&path, "<h1>Custom missing page</h1>").unwrap();
+
+        let mut error_pages = ErrorPages::new();
+        error_pages.file(404, &path);
+        let config = ServerConfig {
+            error_pages,
+            ..ServerConfig::default()
+        };
+
+        let response = round_trip(
+            config,
+            Router::new(),
+            "GET /nowhere HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(response.ends_with("<h1>Custom missing page</h1>"));
+    }
+
+    #[test]
+    fn test_problem_json_follows_accept() {
+        let config = || ServerConfig {
+            problem_json: true,
+            ..ServerConfig::default()
+        };
+
+        let response = round_trip(
+            config(),
+            Router::new(),
+            "GET /nowhere HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Content-Type: application/problem+json\r\n"));
+        assert!(response.contains(r#""title":"Not Found","status":404,"detail":"#));
+
+        let response = round_trip(
+            config(),
+            Router::new(),
+            "GET /nowhere HTTP/1.1\r\nHost: localhost\r\nAccept: text/html\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(response.contains("<h1>404 Not Found</h1>"));
+    }
+
+    #[test]
+    fn test_problem_json_covers_parse_errors() {
+        let config = || ServerConfig {
+            problem_json: true,
+            max_body_size: Some(16),
+            ..ServerConfig::default()
+        };
+
+        let response = round_trip(
+            config(),
+            slash_router(),
+            "POST / HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\
+             Content-Length: 1000\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        assert!(response.contains("Content-Type: application/problem+json\r\n"));
+        assert!(response.contains(r#""status":413"#));
+
+        // The request line fails before any header is parsed.
+        let response = round_trip(
+            config(),
+            slash_router(),
+            "GET / HTTP/3.0\r\nHost: localhost\r\nAccept: application/json\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 505 "));
+        assert!(response.contains("Content-Type: application/problem+json\r\n"));
+
+        let response = round_trip(
+            config(),
+            slash_router(),
+            "GET / HTTP/3.0\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 505 "));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+    }
+
+    fn real_ip_router() -> Router {
+        let mut router = Router::new();
+        router.get("/ip", |req| {
+            Response::text(
+                200,
+                req.real_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            )
+        });
+        router
+    }
+
+    #[test]
+    fn test_real_ip_via_trusted_proxy() {
+        let config = ServerConfig {
+            trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+            ..ServerConfig::default()
+        };
+        let response = round_trip(
+            config,
+            real_ip_router(),
+            "GET /ip HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: 203.0.113.9\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\n203.0.113.9"));
+    }
+
+    #[test]
+    fn test_real_ip_ignores_header_from_untrusted_peer() {
+        let response = round_trip(
+            ServerConfig::default(),
+            real_ip_router(),
+            "GET /ip HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: 203.0.113.9\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\n127.0.0.1"));
+    }
+
+    #[test]
+    fn test_no_redirect_when_disabled() {
+        let response = round_trip(
+            ServerConfig::default(),
+            slash_router(),
+            "GET /about/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    io,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Set from the signal handler; only an atomic store is safe there.
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs handlers that record a shutdown request instead of killing the
+/// process: `SIGINT` and `SIGTERM` on Unix, Ctrl-C and Ctrl-Break on
+/// Windows. Containers stop with `SIGTERM`, terminals with `SIGINT`.
+///
+/// Installing more than once is a no-op. Servers with
+/// `ServerConfig::drain_on_signal` call this when they are bound.
+///
+/// # Errors
+///
+/// Returns the OS error if a handler could not be installed; later calls
+/// return the same failure.
+pub fn install() -> io::Result<()> {
+    static INSTALLED: OnceLock<Result<(), i32>> = OnceLock::new();
+    INSTALLED
+        .get_or_init(|| sys::install().map_err(|e| e.raw_os_error().unwrap_or(0)))
+        .map_err(io::Error::from_raw_os_error)
+}
+
+/// Returns `true` once a shutdown signal has arrived since `install`, or
+/// since the latest server with `ServerConfig::drain_on_signal` was bound.
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Forgets any shutdown request so far, so that a server bound after an
+/// earlier one drained on a signal does not drain at once.
+pub(crate) fn reset() {
+    SHUTDOWN_REQUESTED.store(false, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{io, os::raw::c_int, sync::atomic::Ordering};
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_ERR: usize = !0;
+
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_signal(_signum: c_int) {
+        super::SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        for signum in [SIGINT, SIGTERM] {
+            // SAFETY: `on_signal` only performs an atomic store, which is
+            // async-signal-safe.
+            let previous = unsafe { signal(signum, on_signal as extern "C" fn(c_int) as usize) };
+            if previous == SIG_ERR {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{io, sync::atomic::Ordering};
+
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    type HandlerRoutine = unsafe extern "system" fn(u32) -> i32;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<HandlerRoutine>, add: i32) -> i32;
+    }
+
+    unsafe extern "system" fn on_ctrl(event: u32) -> i32 {
+        match event {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+                super::SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        // SAFETY: `on_ctrl` has the signature Windows expects and only
+        // touches an atomic.
+        if unsafe { SetConsoleCtrlHandler(Some(on_ctrl), 1) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::io;
+
+    pub(super) fn install() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "signal handling is not supported on this platform",
+        ))
+    }
+}
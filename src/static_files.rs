@@ -0,0 +1,593 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    etag::{self, ETag},
+    logging::warning,
+    request::{Method, Request},
+    response::Response,
+};
+
+/// Most ranges one `Range` header may ask for before it is ignored and the
+/// whole body sent, so a request cannot multiply the response size.
+const MAX_RANGES: usize = 16;
+
+/// Returns the MIME type for a path based on its extension.
+pub fn mime_type(path: &str) -> &'static str {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reads a file from disk into a `200 OK` response with its MIME type.
+///
+/// The response advertises `Accept-Ranges: bytes` and carries a weak `ETag`
+/// built from the file's size and modification time, which cannot promise
+/// byte-for-byte equality.
+///
+/// Returns `None` if the file does not exist or cannot be read.
+pub fn serve_file(path: &Path) -> Option<Response> {
+    let contents = fs::read(path).ok()?;
+    let mime = mime_type(&path.to_string_lossy());
+    let modified = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs());
+    let etag = ETag::weak(format!("{:x}-{:x}", contents.len(), modified));
+
+    Some(
+        Response::ok()
+            .with_header("Content-Type", mime)
+            .with_header("Accept-Ranges", "bytes")
+            .with_header("ETag", etag.to_string())
+            .with_body(contents),
+    )
+}
+
+/// Answers conditional and range requests for a full `200 OK` response.
+///
+/// Uses the response's `ETag` header as the current validator:
+///
+/// - a matching `If-None-Match` (weak comparison) yields `304 Not Modified`;
+/// - a `Range: bytes=first-last` yields `206 Partial Content`, or `416` if
+///   it lies beyond the body; an open range such as `bytes=500-` runs to
+///   the end of the body, and a suffix range such as `bytes=-500` covers
+///   its last 500 bytes, or all of a shorter body;
+/// - several ranges, as in `bytes=0-99,200-299`, yield a `206` whose
+///   `multipart/byteranges` body holds one part per satisfiable range,
+///   with overlapping and adjacent ranges merged; more than `MAX_RANGES`
+///   ranges are ignored and the full body sent;
+/// - an `If-Range` tag must strongly match, so a weak tag never validates a
+///   range and the full body is sent instead.
+pub fn conditional(req: &Request, response: Response) -> Response {
+    if response.status != 200 || !matches!(req.method, Method::Get | Method::Head) {
+        return response;
+    }
+    let current = response.headers.get("ETag").and_then(ETag::parse);
+
+    if let (Some(header), Some(current)) = (req.headers.get("If-None-Match"), &current)
+        && etag::none_match(header, current)
+    {
+        return Response::not_modified().with_header("ETag", current.to_string());
+    }
+
+    let Some(range) = req.headers.get("Range") else {
+        return response;
+    };
+    if let Some(if_range) = req.headers.get("If-Range") {
+        let validated = match (ETag::parse(if_range), &current) {
+            (Some(tag), Some(current)) => tag.strong_eq(current),
+            // Dates are not supported as validators; send everything.
+            _ => false,
+        };
+        if !validated {
+            return response;
+        }
+    }
+
+    let len = response.body.len();
+    // Unsupported range syntax is ignored, as RFC 7233 allows.
+    let Some(ranges) = parse_ranges(range, len) else {
+        return response;
+    };
+    if ranges.len() > MAX_RANGES {
+        return response;
+    }
+    let mut ranges: Vec<(usize, usize)> = ranges
+        .into_iter()
+        .filter(|&(first, _)| first < len)
+        .map(|(first, last)| (first, last.min(len - 1)))
+        .collect();
+    // Overlapping or adjacent ranges are sent once (RFC 9110, section 14.2).
+    ranges.sort_unstable();
+    ranges.dedup_by(|next, merged| {
+        if next.0 > merged.1.saturating_add(1) {
+            return false;
+        }
+        merged.1 = merged.1.max(next.1);
+        true
+    });
+
+    let mut partial = response;
+    match ranges[..] {
+        [] => Response::error(416, "The requested range is not satisfiable.")
+            .with_header("Content-Range", format!("bytes */{len}")),
+        [(first, last)] => {
+            partial.status = 206;
+            partial.body = partial.body[first..=last].to_vec();
+            partial.with_header("Content-Range", format!("bytes {first}-{last}/{len}"))
+        }
+        _ => {
+            let boundary = byteranges_boundary();
+            let mime = partial.headers.get("Content-Type").map(str::to_string);
+            let mut body = Vec::new();
+            for &(first, last) in &ranges {
+                body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+                if let Some(mime) = &mime {
+                    body.extend_from_slice(format!("Content-Type: {mime}\r\n").as_bytes());
+                }
+                body.extend_from_slice(
+                    format!("Content-Range: bytes {first}-{last}/{len}\r\n\r\n").as_bytes(),
+                );
+                body.extend_from_slice(&partial.body[first..=last]);
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+            partial.status = 206;
+            partial.body = body;
+            partial.with_header(
+                "Content-Type",
+                format!("multipart/byteranges; boundary={boundary}"),
+            )
+        }
+    }
+}
+
+/// Parses a `bytes=first-last, ...` range set for a body of `len` bytes,
+/// one entry per range. An open range, `first-`, ends at `usize::MAX` for
+/// the caller to clamp. A suffix range, `-n`, starts `n` bytes before the
+/// end, so `-0` starts at `len` and is left for the caller to discard.
+fn parse_ranges(header: &str, len: usize) -> Option<Vec<(usize, usize)>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    specs
+        .split(',')
+        .map(|spec| {
+            let (first, last) = spec.split_once('-')?;
+            if first.trim().is_empty() {
+                let suffix: usize = last.trim().parse().ok()?;
+                return Some((len.saturating_sub(suffix), usize::MAX));
+            }
+            let first: usize = first.trim().parse().ok()?;
+            let last: usize = match last.trim() {
+                "" => usize::MAX,
+                last => last.parse().ok()?,
+            };
+            (first <= last).then_some((first, last))
+        })
+        .collect()
+}
+
+/// Returns a boundary for a `multipart/byteranges` body, unique to this
+/// process and moment so that it is vanishingly unlikely to occur inside
+/// the parts.
+fn byteranges_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("byteranges-{nanos:016x}-{count:x}")
+}
+
+/// Joins a request path onto `root`, refusing anything that would escape it.
+fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(request_path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+/// The built-in page served when `hello.html` is missing.
+pub const DEFAULT_HELLO_PAGE: &str = include_str!("../hello.html");
+
+/// The built-in page served when `404.html` is missing.
+pub const DEFAULT_NOT_FOUND_PAGE: &str = include_str!("../404.html");
+
+/// An HTML page read from disk on each request, with embedded content to
+/// serve instead when the file is missing or unreadable.
+#[derive(Debug, Clone)]
+pub struct FallbackPage {
+    path: PathBuf,
+    fallback: &'static str,
+}
+
+impl FallbackPage {
+    /// Create a page for `path`, logging a warning if the file does not
+    /// exist yet so a missing page is noticed at startup.
+    pub fn new(path: impl Into<PathBuf>, fallback: &'static str) -> FallbackPage {
+        let path = path.into();
+        if !path.is_file() {
+            warning!(
+                "{} not found; serving the built-in page instead",
+                path.display()
+            );
+        }
+        FallbackPage { path, fallback }
+    }
+
+    /// Builds an HTML response with the file's contents, or the fallback.
+    pub fn serve(&self, status: u16) -> Response {
+        let contents = fs::read_to_string(&self.path).unwrap_or_else(|_| self.fallback.to_string());
+        Response::html(status, contents)
+    }
+}
+
+/// A set of assets embedded in the binary, keyed by request path.
+///
+/// Assets not found in the map can optionally be looked up in a directory on
+/// disk. Use the `static_assets!` macro to build a set at compile time.
+#[derive(Debug, Clone, Default)]
+pub struct StaticAssets {
+    assets: HashMap<String, &'static [u8]>,
+    fallback_dir: Option<PathBuf>,
+}
+
+impl StaticAssets {
+    /// Create an empty asset set with no filesystem fallback.
+    pub fn new() -> StaticAssets {
+        StaticAssets::default()
+    }
+
+    /// Adds an asset served at `path` (e.g. `/css/site.css`).
+    pub fn insert(
+        &mut self,
+        path: impl Into<String>,
+        contents: &'static [u8],
+    ) -> &mut StaticAssets {
+        self.assets.insert(path.into(), contents);
+        self
+    }
+
+    /// Serves paths missing from the map from files under `dir`.
+    pub fn fallback_dir(&mut self, dir: impl Into<PathBuf>) -> &mut StaticAssets {
+        self.fallback_dir = Some(dir.into());
+        self
+    }
+
+    /// Returns the embedded contents for `path`, if any.
+    pub fn get(&self, path: &str) -> Option<&'static [u8]> {
+        self.assets.get(path).copied()
+    }
+
+    /// Serves the request path from the embedded map, then from the fallback
+    /// directory, and otherwise responds with `404 Not Found`.
+    ///
+    /// Embedded assets get a strong `ETag` hashed from their contents; see
+    /// `conditional` for how validators and ranges are handled.
+    pub fn serve(&self, req: &Request) -> Response {
+        if let Some(contents) = self.get(&req.path) {
+            let response = Response::ok()
+                .with_header("Content-Type", mime_type(&req.path))
+                .with_header("Accept-Ranges", "bytes")
+                .with_header("ETag", ETag::from_contents(contents).to_string())
+                .with_body(contents);
+            return conditional(req, response);
+        }
+
+        self.fallback_dir
+            .as_deref()
+            .and_then(|dir| resolve(dir, &req.path))
+            .and_then(|path| serve_file(&path))
+            .map(|response| conditional(req, response))
+            .unwrap_or_else(Response::not_found)
+    }
+}
+
+/// Builds a `StaticAssets` set from files embedded with `include_bytes!`.
+///
+/// ```ignore
+/// let assets = hello::static_assets! {
+///     "/" => "../hello.html",
+///     "/404.html" => "../404.html",
+/// };
+/// ```
+#[macro_export]
+macro_rules! static_assets {
+    ($($path:literal => $file:literal),* $(,)?) => {{
+        let mut assets = $crate::static_files::StaticAssets::new();
+        $(assets.insert($path, include_bytes!($file));)*
+        assets
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranged(path: &str, if_range: &str) -> Request {
+        let mut req = Request::new(Method::Get, path);
+        req.headers.insert("Range", "bytes=0-3");
+        req.headers.insert("If-Range", if_range);
+        req
+    }
+
+    #[test]
+    fn test_serves_embedded_asset() {
+        let mut assets = StaticAssets::new();
+        assets.insert("/app.js", b"console.log(1);");
+
+        let response = assets.serve(&Request::new(Method::Get, "/app.js"));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(response.body, b"console.log(1);");
+
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("Content-Length: 15\r\n")
+        );
+    }
+
+    #[test]
+    fn test_fallback_page_when_file_is_missing() {
+        let page = FallbackPage::new("no/such/404.html", DEFAULT_NOT_FOUND_PAGE);
+        let response = page.serve(404);
+        assert_eq!(response.status, 404);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.body, DEFAULT_NOT_FOUND_PAGE.as_bytes());
+        assert!(DEFAULT_NOT_FOUND_PAGE.starts_with("<!DOCTYPE html>"));
+
+        let page = FallbackPage::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("hello.html"), "");
+        assert_eq!(page.serve(200).body, include_bytes!("../hello.html"));
+    }
+
+    #[test]
+    fn test_macro_and_filesystem_fallback() {
+        let mut assets = static_assets! { "/" => "../hello.html" };
+        assets.fallback_dir(env!("CARGO_MANIFEST_DIR"));
+
+        let response = assets.serve(&Request::new(Method::Get, "/"));
+        assert_eq!(response.body, include_bytes!("../hello.html"));
+
+        let response = assets.serve(&Request::new(Method::Get, "/404.html"));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        let response = assets.serve(&Request::new(Method::Get, "/../Cargo.toml"));
+        assert_eq!(response.status, 404);
+    }
+
+    #[test]
+    fn test_strong_etag_if_range_is_honored() {
+        let mut assets = StaticAssets::new();
+        assets.insert("/data.txt", b"0123456789");
+        let etag = assets
+            .serve(&Request::new(Method::Get, "/data.txt"))
+            .headers
+            .get("ETag")
+            .unwrap()
+            .to_string();
+        assert!(!etag.starts_with("W/"));
+
+        let response = assets.serve(&ranged("/data.txt", &etag));
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"0123");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 0-3/10"));
+
+        // A stale validator means the range no longer applies.
+        let response = assets.serve(&ranged("/data.txt", "\"stale\""));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"0123456789");
+    }
+
+    #[test]
+    fn test_accept_ranges_and_open_ranges() {
+        let mut assets = StaticAssets::new();
+        assets.insert("/data.txt", b"0123456789");
+        assets.fallback_dir(env!("CARGO_MANIFEST_DIR"));
+        for path in ["/data.txt", "/hello.html"] {
+            let response = assets.serve(&Request::new(Method::Get, path));
+            assert_eq!(response.status, 200);
+            assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+        }
+
+        let open = |range: &str| {
+            let mut req = Request::new(Method::Get, "/data.txt");
+            req.headers.insert("Range", range);
+            assets.serve(&req)
+        };
+        let response = open("bytes=0-");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"0123456789");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 0-9/10"));
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+
+        let response = open("bytes=5-");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"56789");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 5-9/10"));
+
+        assert_eq!(open("bytes=10-").status, 416);
+    }
+
+    #[test]
+    fn test_suffix_ranges() {
+        let mut assets = StaticAssets::new();
+        assets.insert("/data.txt", b"0123456789");
+        let suffix = |range: &str| {
+            let mut req = Request::new(Method::Get, "/data.txt");
+            req.headers.insert("Range", range);
+            assets.serve(&req)
+        };
+
+        let response = suffix("bytes=-3");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"789");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 7-9/10"));
+
+        // A suffix longer than the body is clamped to all of it.
+        let response = suffix("bytes=-500");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"0123456789");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 0-9/10"));
+
+        let response = suffix("bytes=0-1,-2");
+        assert_eq!(response.status, 206);
+        assert!(String::from_utf8_lossy(&response.body).contains("bytes 8-9/10"));
+
+        assert_eq!(suffix("bytes=-0").status, 416);
+        assert_eq!(suffix("bytes=-").status, 200);
+    }
+
+    #[test]
+    fn test_multiple_ranges_are_multipart() {
+        let contents: Vec<u8> = (0..=255).cycle().take(400).collect();
+        let response = Response::ok()
+            .with_header("Content-Type", "application/pdf")
+            .with_body(contents.clone());
+        let mut req = Request::new(Method::Get, "/doc.pdf");
+        req.headers.insert("Range", "bytes=0-99, 200-299, 900-999");
+        let response = conditional(&req, response);
+        assert_eq!(response.status, 206);
+
+        let content_type = response.headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let delimiter = format!("--{boundary}\r\n");
+        let end = format!("--{boundary}--\r\n");
+        let body = response.body.as_slice();
+        assert!(body.ends_with(end.as_bytes()));
+
+        // Split the parts off one by one, head then exactly the stated bytes.
+        let mut rest = &body[..body.len() - end.len()];
+        let mut rebuilt = vec![None; contents.len()];
+        let mut parts = 0;
+        while !rest.is_empty() {
+            rest = rest.strip_prefix(delimiter.as_bytes()).unwrap();
+            let head_end = rest.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = std::str::from_utf8(&rest[..head_end]).unwrap();
+            assert!(head.contains("Content-Type: application/pdf"), "{head}");
+            let range = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Range: bytes "))
+                .unwrap();
+            let (span, total) = range.split_once('/').unwrap();
+            assert_eq!(total, "400");
+            let (first, last) = span.split_once('-').unwrap();
+            let (first, last): (usize, usize) = (first.parse().unwrap(), last.parse().unwrap());
+
+            rest = &rest[head_end + 4..];
+            for (offset, byte) in rest[..=last - first].iter().enumerate() {
+                rebuilt[first + offset] = Some(*byte);
+            }
+            rest = rest[last - first + 1..].strip_prefix(b"\r\n").unwrap();
+            parts += 1;
+        }
+
+        // The unsatisfiable third range is dropped.
+        assert_eq!(parts, 2);
+        for (i, byte) in rebuilt.iter().enumerate() {
+            let requested = i < 100 || (200..300).contains(&i);
+            assert_eq!(byte.is_some(), requested, "byte {i}");
+            if let Some(byte) = byte {
+                assert_eq!(*byte, contents[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_overlapping_and_excess_ranges() {
+        let body = || Response::ok().with_body("0123456789");
+        let ranged = |range: &str| {
+            let mut req = Request::new(Method::Get, "/data.txt");
+            req.headers.insert("Range", range);
+            conditional(&req, body())
+        };
+
+        let response = ranged("bytes=0-,0-,0-");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"0123456789");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 0-9/10"));
+
+        // Adjacent and overlapping ranges merge into one part.
+        let response = ranged("bytes=6-9, 0-2, 3-4, 2-3");
+        assert_eq!(response.status, 206);
+        assert!(
+            response
+                .headers
+                .get("Content-Type")
+                .unwrap()
+                .starts_with("multipart/")
+        );
+        let body = String::from_utf8(response.body).unwrap();
+        assert_eq!(body.matches("Content-Range:").count(), 2, "{body}");
+        assert!(body.contains("Content-Range: bytes 0-4/10\r\n\r\n01234\r\n"));
+        assert!(body.contains("Content-Range: bytes 6-9/10\r\n\r\n6789\r\n"));
+
+        let many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        let response = ranged(&format!("bytes={many}"));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"0123456789");
+    }
+
+    #[test]
+    fn test_weak_etag_if_range_sends_full_body() {
+        let mut assets = StaticAssets::new();
+        assets.fallback_dir(env!("CARGO_MANIFEST_DIR"));
+        let full = assets.serve(&Request::new(Method::Get, "/hello.html"));
+        let etag = full.headers.get("ETag").unwrap().to_string();
+        assert!(etag.starts_with("W/"));
+
+        let response = assets.serve(&ranged("/hello.html", &etag));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, full.body);
+
+        // Weak comparison still validates a cache.
+        let mut req = Request::new(Method::Get, "/hello.html");
+        req.headers
+            .insert("If-None-Match", etag.trim_start_matches("W/"));
+        assert_eq!(assets.serve(&req).status, 304);
+    }
+}
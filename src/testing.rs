@@ -0,0 +1,349 @@
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, TcpStream},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    request::Method,
+    response::Response,
+    router::VHostRouter,
+    server::{Server, ServerConfig},
+};
+
+/// Runs a server on an ephemeral port for the lifetime of the client.
+///
+/// Each request goes out on a fresh connection with `Connection: close`, and
+/// the parsed response is returned. I/O failures panic, since this is meant
+/// for tests. Dropping the client drains the server and waits for it.
+///
+/// Available with the `test-util` feature.
+pub struct TestClient {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestClient {
+    /// Starts serving `sites` with `config`.
+    ///
+    /// `max_connections` is ignored; the server runs until the client is
+    /// dropped.
+    pub fn new(config: ServerConfig, sites: impl Into<VHostRouter>) -> TestClient {
+        let config = ServerConfig {
+            max_connections: None,
+            ..config
+        };
+        let server =
+            Arc::new(Server::bind("127.0.0.1:0", config, sites).expect("failed to start server"));
+        let runner = Arc::clone(&server);
+        let thread = thread::spawn(move || runner.run());
+
+        TestClient {
+            server,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the server the client talks to.
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Sends a `GET` request for `path`.
+    pub fn get(&self, path: &str) -> Response {
+        self.request(Method::Get, path, &[], b"")
+    }
+
+    /// Sends a `POST` request for `path` with `body`.
+    pub fn post(&self, path: &str, body: impl AsRef<[u8]>) -> Response {
+        self.request(Method::Post, path, &[], body.as_ref())
+    }
+
+    /// Sends a request with extra headers and a body.
+    ///
+    /// `Host`, `Connection`, and (for non-empty bodies) `Content-Length` are
+    /// added automatically.
+    pub fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Response {
+        let mut head =
+            format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n");
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut stream =
+            TcpStream::connect(self.server.local_addr()).expect("failed to connect to server");
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(body))
+            .expect("failed to send request");
+        let has_body = method != Method::Head;
+        Response::read_from(&mut BufReader::new(stream), has_body).expect("failed to read response")
+    }
+
+    /// Sends raw bytes on a fresh connection and returns the status of
+    /// every response read back before the server closes it, interim
+    /// `1xx` responses included.
+    ///
+    /// The connection's write half is shut after the bytes are sent, so a
+    /// request cut short reads as the client having gone away.
+    pub fn send_raw(&self, request: &[u8]) -> Vec<u16> {
+        let mut stream =
+            TcpStream::connect(self.server.local_addr()).expect("failed to connect to server");
+        stream
+            .set_read_timeout(Some(RAW_READ_TIMEOUT))
+            .and_then(|()| stream.write_all(request))
+            .and_then(|()| stream.shutdown(Shutdown::Write))
+            .expect("failed to send request");
+        let mut reader = BufReader::new(stream);
+        let mut statuses = Vec::new();
+        while !reader
+            .fill_buf()
+            .expect("failed to read response")
+            .is_empty()
+        {
+            let response = Response::read_from(&mut reader, true).expect("failed to read response");
+            statuses.push(response.status);
+        }
+        statuses
+    }
+
+    /// Runs a battery of HTTP/1.1 edge cases against the server and
+    /// returns the ones answered wrongly.
+    ///
+    /// The cases cover malformed framing, `Host` rules, size limits,
+    /// pipelining, and `Expect: 100-continue`. Requests that should reach
+    /// the router go to `path`, which must accept both `GET` and `POST`;
+    /// what a plain `GET` and `POST` there return is learned first, so any
+    /// handler will do.
+    pub fn conformance(&self, path: &str) -> Result<(), Vec<ConformanceFailure>> {
+        let get = self.get(path).status;
+        let post = self.post(path, "ping").status;
+
+        let mut cases = vec![
+            (
+                "missing Host",
+                format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n"),
+                vec![400],
+            ),
+            (
+                "duplicate Host",
+                format!("GET {path} HTTP/1.1\r\nHost: a\r\nHost: b\r\nConnection: close\r\n\r\n"),
+                vec![400],
+            ),
+            (
+                "HTTP/1.0 without Host",
+                format!("GET {path} HTTP/1.0\r\n\r\n"),
+                vec![get],
+            ),
+            (
+                "unsupported version",
+                format!("GET {path} HTTP/2.0\r\nHost: localhost\r\n\r\n"),
+                vec![505],
+            ),
+            (
+                "malformed request line",
+                format!("GET  {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+                vec![400],
+            ),
+            (
+                "chunked body",
+                post_with(
+                    path,
+                    "Transfer-Encoding: chunked",
+                    "2\r\npi\r\n2;ext=1\r\nng\r\n0\r\nTrailer: x\r\n\r\n",
+                ),
+                vec![post],
+            ),
+            (
+                "bad chunk size",
+                post_with(
+                    path,
+                    "Transfer-Encoding: chunked",
+                    "zz\r\nping\r\n0\r\n\r\n",
+                ),
+                vec![400],
+            ),
+            (
+                "truncated chunked body",
+                post_with(path, "Transfer-Encoding: chunked", "8\r\nping"),
+                vec![400],
+            ),
+            (
+                "Transfer-Encoding with Content-Length",
+                post_with(
+                    path,
+                    "Transfer-Encoding: chunked\r\nContent-Length: 4",
+                    "4\r\nping\r\n0\r\n\r\n",
+                ),
+                vec![400],
+            ),
+            (
+                "unsupported Transfer-Encoding",
+                post_with(path, "Transfer-Encoding: gzip", "ping"),
+                vec![501],
+            ),
+            (
+                "unsupported Content-Encoding",
+                post_with(path, "Content-Encoding: br\r\nContent-Length: 4", "ping"),
+                vec![415],
+            ),
+            (
+                "invalid Content-Length",
+                post_with(path, "Content-Length: four", "ping"),
+                vec![400],
+            ),
+            (
+                "pipelined requests",
+                format!(
+                    "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                     GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+                ),
+                vec![get, get],
+            ),
+            (
+                "100-continue",
+                post_with(path, "Expect: 100-continue\r\nContent-Length: 4", "ping"),
+                vec![100, post],
+            ),
+        ];
+        if let Some(max) = self.server.config().max_uri_length {
+            cases.push((
+                "oversized request target",
+                format!(
+                    "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    "a".repeat(max)
+                ),
+                vec![414],
+            ));
+        }
+        if let Some(max) = self.server.config().max_single_header_bytes {
+            cases.push((
+                "oversized header line",
+                format!(
+                    "GET {path} HTTP/1.1\r\nHost: localhost\r\nX-Padding: {}\r\n\r\n",
+                    "a".repeat(max)
+                ),
+                vec![431],
+            ));
+        }
+
+        let failures: Vec<_> = cases
+            .into_iter()
+            .filter_map(|(case, request, expected)| {
+                let received = self.send_raw(request.as_bytes());
+                (received != expected).then_some(ConformanceFailure {
+                    case,
+                    expected,
+                    received,
+                })
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+}
+
+/// How long `TestClient::send_raw` waits on a silent server.
+const RAW_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Builds a closing `POST` to `path` with extra header lines and a raw
+/// body.
+fn post_with(path: &str, headers: &str, body: &str) -> String {
+    format!(
+        "POST {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n\r\n{body}"
+    )
+}
+
+/// A `TestClient::conformance` case the server answered wrongly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceFailure {
+    /// What the case checks.
+    pub case: &'static str,
+    /// The statuses that should have come back, in order.
+    pub expected: Vec<u16>,
+    /// The statuses that did, interim responses included.
+    pub received: Vec<u16>,
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {:?}, received {:?}",
+            self.case, self.expected, self.received
+        )
+    }
+}
+
+impl Drop for TestClient {
+    fn drop(&mut self) {
+        self.server.drain();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    fn client() -> TestClient {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "home"));
+        router.post("/echo", |req| {
+            Response::new(200).with_body(req.body.clone())
+        });
+        TestClient::new(ServerConfig::default(), router)
+    }
+
+    #[test]
+    fn test_get_and_post() {
+        let client = client();
+        let response = client.get("/");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"home");
+
+        assert_eq!(client.post("/echo", "ping").body, b"ping");
+    }
+
+    #[test]
+    fn test_not_found() {
+        let response = client().get("/missing");
+        assert_eq!(response.status, 404);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn test_conformance_suite_passes() {
+        let mut router = Router::new();
+        router.get("/", |_| Response::text(200, "home"));
+        router.post("/", |req| Response::new(201).with_body(req.body.clone()));
+        let client = TestClient::new(ServerConfig::default(), router);
+
+        if let Err(failures) = client.conformance("/") {
+            let report: Vec<_> = failures.iter().map(ToString::to_string).collect();
+            panic!("conformance failures:\n{}", report.join("\n"));
+        }
+    }
+}